@@ -0,0 +1,12 @@
+// Tests SubBus.hdl, which reads an internal bus through sub-buses
+
+load SubBus.hdl,
+output-file SubBus.out,
+compare-to SubBus.cmp,
+output-list a%B1.4.1 out%B1.2.1 mid%B1.2.1;
+
+set a %B0000, eval, output;
+set a %B0011, eval, output;
+set a %B0101, eval, output;
+set a %B1100, eval, output;
+set a %B1111, eval, output;
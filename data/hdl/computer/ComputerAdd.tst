@@ -0,0 +1,24 @@
+// Runs Add.hack, which computes RAM[0] = 2 + 3, on the Computer with the
+// built-in ARegister, DRegister and PC
+
+load Computer.hdl,
+output-file ComputerAdd.out,
+compare-to ComputerAdd.cmp,
+output-list time%S1.4.1 reset%B2.1.2 ARegister[]%D1.7.1 DRegister[]%D1.7.1 PC[]%D0.5.0 RAM16K[0]%D1.7.1;
+
+ROM32K load Add.hack,
+output;
+
+repeat 6 {
+    tick, tock, output;
+}
+
+// restarts the program with RAM[0] cleared
+set reset 1,
+set RAM16K[0] 0,
+tick, tock, output;
+
+set reset 0,
+repeat 6 {
+    tick, tock, output;
+}
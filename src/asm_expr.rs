@@ -0,0 +1,157 @@
+// Constant expressions of A commands, like `@SCREEN+32*row` or `@LOOP+2`
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(char),
+}
+
+/// Evaluate an expression made of decimal, `0x` hexadecimal, `0b` binary and
+/// `'c'` character literals and symbols, combined with `+ - * / %`, unary `-`
+/// and parentheses. `resolve` gives the value of a symbol, or an error for an
+/// unknown one. Errors describe what is wrong with the expression.
+pub fn evaluate(
+    expression: &str,
+    resolve: &mut dyn FnMut(&str) -> Result<i64, String>,
+) -> Result<i64, String> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        resolve,
+    };
+
+    let value = parser.sum()?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(format!("unexpected {:?}", token)),
+        None => Ok(value),
+    }
+}
+
+/// Whether an A command is a plain symbol rather than an expression
+pub fn is_symbol(expression: &str) -> bool {
+    matches!(&tokenize(expression).as_deref(), Ok([Token::Symbol(_)]))
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if "+-*/%()".contains(c) {
+            tokens.push(Token::Op(c));
+            i += 1;
+        } else if c == '\'' {
+            match chars[i + 1..] {
+                [c, '\'', ..] => tokens.push(Token::Number(c as i64)),
+                _ => return Err("invalid character literal".to_owned()),
+            }
+            i += 3;
+        } else if is_symbol_char(c) {
+            let start = i;
+            while i < chars.len() && is_symbol_char(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                tokens.push(Token::Number(number(&word)?));
+            } else {
+                tokens.push(Token::Symbol(word));
+            }
+        } else {
+            return Err(format!("unexpected character {:?}", c));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn number(word: &str) -> Result<i64, String> {
+    let parsed = if let Some(digits) = word.strip_prefix("0x") {
+        i64::from_str_radix(digits, 16)
+    } else if let Some(digits) = word.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2)
+    } else {
+        word.parse::<i64>()
+    };
+    parsed.map_err(|_| format!("invalid number {}", word))
+}
+
+struct Parser<'a> {
+    tokens:   Vec<Token>,
+    position: usize,
+    resolve:  &'a mut dyn FnMut(&str) -> Result<i64, String>,
+}
+
+impl Parser<'_> {
+    fn next_op(&mut self, ops: &str) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Op(op)) if ops.contains(*op) => {
+                self.position += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.product()?;
+        while let Some(op) = self.next_op("+-") {
+            let rhs = self.product()?;
+            value = match op {
+                '+' => value.checked_add(rhs),
+                _ => value.checked_sub(rhs),
+            }
+            .ok_or("overflow")?;
+        }
+        Ok(value)
+    }
+
+    // product := unary (('*' | '/' | '%') unary)*
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.next_op("*/%") {
+            let rhs = self.unary()?;
+            value = match op {
+                '*' => value.checked_mul(rhs).ok_or("overflow")?,
+                _ if rhs == 0 => return Err("division by zero".to_owned()),
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    // unary := '-' unary | '(' sum ')' | number | symbol
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.next_op("-").is_some() {
+            return Ok(-self.unary()?);
+        }
+        if self.next_op("(").is_some() {
+            let value = self.sum()?;
+            return match self.next_op(")") {
+                Some(_) => Ok(value),
+                None => Err("missing )".to_owned()),
+            };
+        }
+
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Symbol(symbol)) => (self.resolve)(&symbol),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_owned()),
+        }
+    }
+}
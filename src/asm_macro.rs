@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{pseudo_instruction, Pseudo};
+
+// Deepest nesting of macro expansions and includes, to stop recursive ones
+const MAX_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    body:   Vec<String>,
+}
+
+/// Expands the `.define`, `.macro`/`.endm` and `.include` directives of Hack
+/// assembly into plain instructions and labels.
+///
+/// - `.define NAME value` replaces `NAME` in the A commands that follow.
+/// - `.macro NAME a, b` up to `.endm` defines a macro, expanded by a `NAME x,
+///   y` line with `a` and `b` replaced by `x` and `y`. Labels like `%%loop` in
+///   its body are renamed to `NAME$n$loop`, unique to the n-th expansion.
+/// - `.include "file.asm"` expands a file searched in the directory of the
+///   including file, then in the include directories.
+/// - Pseudo instructions like `goto LABEL` are replaced by the instructions
+///   they stand for, whose A commands take the `.define` values too.
+///
+/// Lines are (code without comment, source line number) pairs, and the lines
+/// an included file or a macro expands into take the number of the line that
+/// includes or invokes them.
+pub struct MacroExpander<'a> {
+    include_dirs: &'a [PathBuf],
+    defines:      HashMap<String, String>,
+    macros:       HashMap<String, Macro>,
+    // number of macro expansions so far, to name their local labels
+    expansions:   usize,
+    // number of `halt` pseudo instructions so far, to name their labels
+    halts:        usize,
+    // files being included, innermost last
+    including:    Vec<PathBuf>,
+}
+
+impl<'a> MacroExpander<'a> {
+    pub fn new(include_dirs: &'a [PathBuf]) -> Self {
+        Self {
+            include_dirs,
+            defines: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            halts: 0,
+            including: vec![],
+        }
+    }
+
+    /// Expand the directives of `lines`. Errors read `invalid: ..., line: N`.
+    pub fn expand(&mut self, lines: Vec<(String, usize)>) -> Result<Vec<(String, usize)>, String> {
+        let mut output = vec![];
+        self.process(lines, None, 0, &mut output)?;
+        Ok(output)
+    }
+
+    // `dir` is the directory of the file being included, None for the source
+    fn process(
+        &mut self,
+        lines: Vec<(String, usize)>,
+        dir: Option<&Path>,
+        depth: usize,
+        output: &mut Vec<(String, usize)>,
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "invalid: macros or includes nested deeper than {}, line: {}",
+                MAX_DEPTH,
+                lines.first().map_or(0, |v| v.1)
+            ));
+        }
+
+        let mut lines = lines.into_iter();
+        while let Some((line, line_no)) = lines.next() {
+            let (word, rest) = match line.split_once(char::is_whitespace) {
+                Some((word, rest)) => (word, rest.trim()),
+                None => (line.as_str(), ""),
+            };
+
+            match word {
+                ".define" => {
+                    let (name, value) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| format!("invalid: {}, line: {}", line, line_no))?;
+                    let value = substitute(value.trim(), &self.defines);
+                    self.defines.insert(name.to_owned(), value);
+                }
+                ".macro" => {
+                    let mut params = split_args(rest);
+                    if params.is_empty() {
+                        return Err(format!("invalid: {}, line: {}", line, line_no));
+                    }
+                    let name = params.remove(0);
+
+                    let mut body = vec![];
+                    loop {
+                        match lines.next() {
+                            Some((line, _)) if line == ".endm" => break,
+                            Some((line, line_no)) if line.starts_with(".macro") => {
+                                return Err(format!("invalid: nested .macro, line: {}", line_no))
+                            }
+                            Some((line, _)) => body.push(line),
+                            None => {
+                                return Err(format!(
+                                    "invalid: .macro {} without .endm, line: {}",
+                                    name, line_no
+                                ))
+                            }
+                        }
+                    }
+                    self.macros.insert(name, Macro { params, body });
+                }
+                ".endm" => return Err(format!("invalid: .endm without .macro, line: {}", line_no)),
+                ".include" => {
+                    let path = self.find_include(rest, dir, line_no)?;
+                    if self.including.contains(&path) {
+                        return Err(format!(
+                            "invalid: recursive .include {}, line: {}",
+                            rest, line_no
+                        ));
+                    }
+
+                    let source = read_to_string(&path).unwrap();
+                    let included = code_lines(&source)
+                        .into_iter()
+                        .map(|(code, _)| (code, line_no))
+                        .collect();
+                    let dir = path.parent().unwrap().to_path_buf();
+                    self.including.push(path);
+                    self.process(included, Some(&dir), depth + 1, output)?;
+                    self.including.pop();
+                }
+                _ if self.macros.contains_key(word) => {
+                    let expanded = self.expand_macro(word, rest, line_no)?;
+                    self.process(expanded, dir, depth + 1, output)?;
+                }
+                _ if line.starts_with('@') => output.push((
+                    format!("@{}", substitute(&line[1..], &self.defines)),
+                    line_no,
+                )),
+                _ if line.starts_with('.') => {
+                    return Err(format!(
+                        "invalid: unknown directive: {}, line: {}",
+                        word, line_no
+                    ))
+                }
+                _ => match pseudo_instruction(&line, line_no)? {
+                    Some(Pseudo::Halt) => {
+                        self.halts += 1;
+                        let label = format!("halt${}", self.halts);
+                        output.extend(
+                            [
+                                format!("({})", label),
+                                format!("@{}", label),
+                                "0;JMP".to_owned(),
+                            ]
+                            .map(|code| (code, line_no)),
+                        );
+                    }
+                    Some(Pseudo::Codes(codes)) => {
+                        let codes = codes.into_iter().map(|code| (code, line_no)).collect();
+                        self.process(codes, dir, depth, output)?;
+                    }
+                    None => output.push((line, line_no)),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_macro(
+        &mut self,
+        name: &str,
+        args: &str,
+        line_no: usize,
+    ) -> Result<Vec<(String, usize)>, String> {
+        let r#macro = &self.macros[name];
+        let args: Vec<String> = split_args(args)
+            .iter()
+            .map(|arg| substitute(arg, &self.defines))
+            .collect();
+        if args.len() != r#macro.params.len() {
+            return Err(format!(
+                "invalid: macro {} takes {} arguments, got {}, line: {}",
+                name,
+                r#macro.params.len(),
+                args.len(),
+                line_no
+            ));
+        }
+
+        self.expansions += 1;
+        let mut values: HashMap<String, String> =
+            r#macro.params.iter().cloned().zip(args).collect();
+        for line in r#macro.body.iter() {
+            for token in tokens(line) {
+                if let Some(label) = token.strip_prefix("%%") {
+                    values.insert(
+                        token.to_owned(),
+                        format!("{}${}${}", name, self.expansions, label),
+                    );
+                }
+            }
+        }
+
+        Ok(r#macro
+            .body
+            .iter()
+            .map(|line| (substitute(line, &values), line_no))
+            .collect())
+    }
+
+    fn find_include(
+        &self,
+        name: &str,
+        dir: Option<&Path>,
+        line_no: usize,
+    ) -> Result<PathBuf, String> {
+        let name = name
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| format!("invalid: .include {}, line: {}", name, line_no))?;
+
+        dir.into_iter()
+            .chain(self.include_dirs.iter().map(|v| v.as_path()))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .or_else(|| Some(PathBuf::from(name)).filter(|path| path.is_file()))
+            .map(|path| path.canonicalize().unwrap())
+            .ok_or_else(|| format!("invalid: {}, no such file, line: {}", name, line_no))
+    }
+}
+
+/// The codes of an assembly source without comments and blank lines, with
+/// their line numbers
+pub fn code_lines(source: &str) -> Vec<(String, usize)> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let code = line.split("//").next().unwrap().trim();
+            if code.is_empty() {
+                None
+            } else {
+                Some((code.to_owned(), i + 1))
+            }
+        })
+        .collect()
+}
+
+fn split_args(args: &str) -> Vec<String> {
+    args.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_owned())
+        .collect()
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:%".contains(c)
+}
+
+// The symbols and numbers of a line
+fn tokens(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !is_symbol_char(c))
+        .filter(|v| !v.is_empty())
+}
+
+// Replace the whole symbols of a line found in `values`
+fn substitute(line: &str, values: &HashMap<String, String>) -> String {
+    let mut output = String::new();
+    let mut token = String::new();
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if is_symbol_char(c) {
+            token.push(c);
+            continue;
+        }
+        output.push_str(values.get(&token).unwrap_or(&token));
+        token.clear();
+        if c != '\n' {
+            output.push(c);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(source: &str, include_dirs: &[PathBuf]) -> Result<Vec<String>, String> {
+        let lines = MacroExpander::new(include_dirs).expand(code_lines(source))?;
+        Ok(lines.into_iter().map(|(code, _)| code).collect())
+    }
+
+    const PUSH: &str = "\
+.macro PUSH value
+@value
+D=A
+@SP
+AM=M+1
+A=A-1
+M=D
+.endm
+";
+
+    #[test]
+    fn expand_macros_with_arguments() {
+        let source = format!("{}.define TEN 10\nPUSH TEN\nPUSH 7 // seven", PUSH);
+        assert_eq!(expand(&source, &[]).unwrap(), [
+            "@10", "D=A", "@SP", "AM=M+1", "A=A-1", "M=D", "@7", "D=A", "@SP", "AM=M+1", "A=A-1",
+            "M=D",
+        ]);
+
+        let lines = MacroExpander::new(&[])
+            .expand(code_lines(&format!("{}\n@0\nPUSH 1", PUSH)))
+            .unwrap();
+        assert!(lines[1..].iter().all(|(_, line_no)| *line_no == 11));
+    }
+
+    #[test]
+    fn rename_local_labels_per_expansion() {
+        let source = "\
+.macro WAIT n
+@n
+D=A
+(%%loop)
+D=D-1
+@%%loop
+D;JGT
+.endm
+WAIT 3
+WAIT 5";
+        let lines = expand(source, &[]).unwrap();
+        assert_eq!(lines[2], "(WAIT$1$loop)");
+        assert_eq!(lines[4], "@WAIT$1$loop");
+        assert_eq!(lines[8], "(WAIT$2$loop)");
+        assert_eq!(lines[10], "@WAIT$2$loop");
+    }
+
+    #[test]
+    fn substitute_whole_symbols() {
+        let source = ".define N 5\n.define M N+1\n@N\n@M\n@NN\n@N.x\n@LOOP+N";
+        assert_eq!(expand(source, &[]).unwrap(), [
+            "@5", "@5+1", "@NN", "@N.x", "@LOOP+5"
+        ]);
+    }
+
+    #[test]
+    fn include_files_from_the_include_dirs() {
+        let dir = std::env::temp_dir().join(format!("nand2tetris-rs-asm-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/push.asm"), PUSH).unwrap();
+        std::fs::write(
+            dir.join("lib/all.asm"),
+            ".include \"push.asm\"\n// nothing else",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/loop.asm"), ".include \"loop.asm\"").unwrap();
+
+        let include_dirs = [dir.join("lib")];
+        let lines = expand(".include \"all.asm\"\nPUSH 2", &include_dirs).unwrap();
+        assert_eq!(lines[0], "@2");
+        assert_eq!(
+            expand("\n.include \"loop.asm\"", &include_dirs).unwrap_err(),
+            "invalid: recursive .include \"loop.asm\", line: 2"
+        );
+        assert_eq!(
+            expand(".include \"none.asm\"", &include_dirs).unwrap_err(),
+            "invalid: none.asm, no such file, line: 1"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn report_invalid_directives() {
+        let error = |source: &str| expand(source, &[]).unwrap_err();
+
+        assert_eq!(
+            error(&format!("{}PUSH 1, 2", PUSH)),
+            "invalid: macro PUSH takes 1 arguments, got 2, line: 9"
+        );
+        assert_eq!(
+            error(".macro LOOP\nLOOP\n.endm\nLOOP"),
+            "invalid: macros or includes nested deeper than 64, line: 4"
+        );
+        assert_eq!(
+            error(".macro A\n.macro B\n"),
+            "invalid: nested .macro, line: 2"
+        );
+        assert_eq!(error(".endm"), "invalid: .endm without .macro, line: 1");
+        assert_eq!(
+            error(".org 100"),
+            "invalid: unknown directive: .org, line: 1"
+        );
+    }
+}
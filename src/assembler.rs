@@ -5,109 +5,59 @@ use std::path::PathBuf;
 
 use once_cell::sync::OnceCell;
 
+use crate::asm_expr;
+use crate::asm_macro::{code_lines, MacroExpander};
 use crate::utils::{save_file, substr};
 
 pub static COMP_TABLE: OnceCell<HashMap<&str, &str>> = OnceCell::new();
 pub static DEST_TABLE: OnceCell<HashMap<&str, &str>> = OnceCell::new();
 pub static JMP_TABLE: OnceCell<HashMap<&str, &str>> = OnceCell::new();
 pub static PREDEFINED_SYMBOL_TABLE: OnceCell<HashMap<&str, u32>> = OnceCell::new();
+// comp fields of the shift instructions `101a cccc ccdd djjj` of the extended
+// instruction set: c1 shifts left rather than right, c2 shifts D rather than A
+// or M, and `>>` is an arithmetic shift
+pub static SHIFT_TABLE: OnceCell<HashMap<&str, &str>> = OnceCell::new();
+
+pub const ROM_SIZE: usize = 32768;
+/// The largest constant of an A command, whose 16th bit is 0
+pub const MAX_CONSTANT: u32 = 32767;
+/// Variables are allocated from 16 up to the stack base
+pub const DEFAULT_RAM_BUDGET: u32 = 256;
 
 pub struct Assembler {
     symbol_table:  HashMap<String, u32>,
+    // label symbols like (Xxx) and their ROM addresses
+    labels:        HashMap<String, u32>,
+    // variable symbols and their RAM addresses
+    variables:     HashMap<String, u32>,
     codes:         Vec<String>,
+    // the source line number of each code
+    line_numbers:  Vec<usize>,
+    source:        Vec<String>,
     output:        Vec<u8>,
     alloc_address: u32,
+    // variables must be allocated below this RAM address
+    ram_budget:    u32,
+    warnings:      Vec<String>,
+    include_dirs:  Vec<PathBuf>,
+    // only accept the spelling of the C commands in the tables
+    strict:        bool,
+    // accept the shift instructions
+    extended:      bool,
 }
 
 impl Assembler {
     /// Read in the file and ignore the blank lines and comment lines
     pub fn new(path: PathBuf) -> Self {
-        COMP_TABLE
-            .set(HashMap::from([
-                ("0", "0101010"),
-                ("1", "0111111"),
-                ("-1", "0111010"),
-                ("D", "0001100"),
-                ("A", "0110000"),
-                ("!D", "0001101"),
-                ("!A", "0110001"),
-                ("-D", "0001111"),
-                ("-A", "0110011"),
-                ("D+1", "0011111"),
-                ("A+1", "0110111"),
-                ("D-1", "0001110"),
-                ("A-1", "0110010"),
-                ("D+A", "0000010"),
-                ("D-A", "0010011"),
-                ("A-D", "0000111"),
-                ("D&A", "0000000"),
-                ("D|A", "0010101"),
-                ("M", "1110000"),
-                ("!M", "1110001"),
-                ("-M", "1110011"),
-                ("M+1", "1110111"),
-                ("M-1", "1110010"),
-                ("D+M", "1000010"),
-                ("D-M", "1010011"),
-                ("M-D", "1000111"),
-                ("D&M", "1000000"),
-                ("D|M", "1010101"),
-            ]))
-            .unwrap();
-
-        DEST_TABLE
-            .set(HashMap::from([
-                ("null", "000"),
-                ("M", "001"),
-                ("D", "010"),
-                ("MD", "011"),
-                ("A", "100"),
-                ("AM", "101"),
-                ("AD", "110"),
-                ("AMD", "111"),
-            ]))
-            .unwrap();
-
-        JMP_TABLE
-            .set(HashMap::from([
-                ("null", "000"),
-                ("JGT", "001"),
-                ("JEQ", "010"),
-                ("JGE", "011"),
-                ("JLT", "100"),
-                ("JNE", "101"),
-                ("JLE", "110"),
-                ("JMP", "111"),
-            ]))
-            .unwrap();
-
-        PREDEFINED_SYMBOL_TABLE
-            .set(HashMap::from([
-                ("SP", 0),
-                ("LCL", 1),
-                ("ARG", 2),
-                ("THIS", 3),
-                ("THAT", 4),
-                ("R0", 0),
-                ("R1", 1),
-                ("R2", 2),
-                ("R3", 3),
-                ("R4", 4),
-                ("R5", 5),
-                ("R6", 6),
-                ("R7", 7),
-                ("R8", 8),
-                ("R9", 9),
-                ("R10", 10),
-                ("R11", 11),
-                ("R12", 12),
-                ("R13", 13),
-                ("R14", 14),
-                ("R15", 15),
-                ("SCREEN", 16384),
-                ("KBD", 24576),
-            ]))
-            .unwrap();
+        assert_eq!(path.extension().unwrap(), "asm");
+        let mut assembler = Self::from_source(&read_to_string(&path).unwrap());
+        assembler.set_include_dirs(vec![path.parent().unwrap().to_path_buf()]);
+        assembler
+    }
+
+    /// Ignore the blank lines and comment lines of the assembly source
+    pub fn from_source(source: &str) -> Self {
+        init_tables();
 
         let mut symbol_table = HashMap::new();
 
@@ -115,119 +65,698 @@ impl Assembler {
             symbol_table.insert(symbol.0.to_owned().to_owned(), symbol.1.to_owned());
         }
 
-        assert_eq!(path.extension().unwrap(), "asm");
-
-        let mut codes = vec![];
-
-        for line in read_to_string(path).unwrap().lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('/') {
-                continue;
-            }
-            if line.find('/').is_some() {
-                codes.push(substr(line, 0, line.find('/').unwrap()).trim().to_string());
-                continue;
-            }
-            codes.push(line.to_string());
-        }
+        let (codes, line_numbers) = code_lines(source).into_iter().unzip();
 
         Self {
             symbol_table,
+            labels: HashMap::new(),
+            variables: HashMap::new(),
             codes,
+            line_numbers,
+            source: source.lines().map(|line| line.to_owned()).collect(),
             output: vec![],
             // the next address to be allocated to the variable symbol
             alloc_address: 16,
+            ram_budget: DEFAULT_RAM_BUDGET,
+            warnings: vec![],
+            include_dirs: vec![],
+            strict: false,
+            extended: false,
         }
     }
 
-    pub fn run(&mut self) {
-        self.process_lable();
-        self.parse();
+    /// Accept the shift instructions like `D=D<<` or `M=M>>` of the extended
+    /// instruction set
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    /// Only accept C commands spelled as in the book, without spaces,
+    /// lowercase, reordered destinations or commuted operands
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Directories searched for the files of `.include` directives, after the
+    /// directory of the including file
+    pub fn set_include_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.include_dirs = dirs;
+    }
+
+    /// Warn about the variables allocated at or above `budget` instead of the
+    /// stack base, 256
+    pub fn set_ram_budget(&mut self, budget: u32) {
+        self.ram_budget = budget;
+    }
+
+    /// Assemble the codes. Errors read `invalid: ..., line: N`.
+    pub fn run(&mut self) -> Result<(), String> {
+        self.expand_macros()?;
+        self.process_lable()?;
+        self.parse()
+    }
+
+    // Expand the macro directives and the pseudo instructions before the two
+    // passes, so that the first pass counts the instructions of the latter in
+    // the label addresses
+    fn expand_macros(&mut self) -> Result<(), String> {
+        let lines = self
+            .codes
+            .drain(..)
+            .zip(self.line_numbers.drain(..))
+            .collect();
+        let lines = MacroExpander::new(&self.include_dirs).expand(lines)?;
+        (self.codes, self.line_numbers) = lines.into_iter().unzip();
+        Ok(())
     }
 
     // First pass through the code to find label symbol like (Xxx)
-    fn process_lable(&mut self) {
+    fn process_lable(&mut self) -> Result<(), String> {
         let mut no_label_codes = vec![];
+        let mut no_label_line_numbers = vec![];
         let mut current_line = 0u32;
 
-        for line in self.codes.iter() {
+        for (line, &line_no) in self.codes.iter().zip(self.line_numbers.iter()) {
             if line.starts_with('(') {
                 let symbol = substr(line, 1, line.len() - 2);
-                self.symbol_table.insert(symbol, current_line);
+                if PREDEFINED_SYMBOL_TABLE
+                    .get()
+                    .unwrap()
+                    .contains_key(symbol.as_str())
+                {
+                    return Err(format!(
+                        "invalid: label shadows a predefined symbol: {}, line: {}",
+                        symbol, line_no
+                    ));
+                }
+                if self.labels.contains_key(&symbol) {
+                    return Err(format!(
+                        "invalid: duplicate label: {}, line: {}",
+                        symbol, line_no
+                    ));
+                }
+                self.symbol_table.insert(symbol.clone(), current_line);
+                self.labels.insert(symbol, current_line);
             } else {
                 no_label_codes.push(line.to_owned());
+                no_label_line_numbers.push(line_no);
                 current_line += 1;
             }
         }
 
+        if no_label_codes.len() > ROM_SIZE {
+            return Err(format!(
+                "invalid: {} instructions do not fit in the ROM of {} words",
+                no_label_codes.len(),
+                ROM_SIZE
+            ));
+        }
+
         self.codes = no_label_codes;
+        self.line_numbers = no_label_line_numbers;
+        Ok(())
     }
 
     // Second pass through the codes to generate binary codes
-    fn parse(&mut self) {
+    fn parse(&mut self) -> Result<(), String> {
         let codes = self.codes.clone();
+        let line_numbers = self.line_numbers.clone();
 
-        for line in codes.iter() {
+        for (line, line_no) in codes.iter().zip(line_numbers) {
             if line.starts_with('@') {
-                self.parse_a_command(&substr(line, 1, line.len()));
+                self.parse_a_command(&substr(line, 1, line.len()), line_no)?;
             } else {
-                self.parse_c_command(line);
+                self.parse_c_command(line, line_no)?;
             }
         }
+        Ok(())
     }
 
     // Generate binary codes for A command which like @Xxx
-    // Note that Xxx can be a symbol or a decimal
-    fn parse_a_command(&mut self, command: &str) {
-        if command.parse::<u32>().is_ok() {
-            writeln!(&mut self.output, "{:016b}", command.parse::<u32>().unwrap()).unwrap();
-        } else if self.symbol_table.contains_key(command) {
-            let address = self.symbol_table.get(command).unwrap();
-            writeln!(&mut self.output, "{:016b}", address).unwrap();
-        } else {
-            // new variable
-            let binary = format!("{:016b}", self.alloc_address);
-            self.symbol_table
-                .insert(command.to_owned(), self.alloc_address);
-            self.alloc_address += 1;
-            writeln!(&mut self.output, "{}", binary).unwrap();
+    // Note that Xxx can be a symbol, a literal or a constant expression of them,
+    // whose symbols must be labels or predefined symbols rather than variables
+    fn parse_a_command(&mut self, command: &str, line_no: usize) -> Result<(), String> {
+        let is_symbol = asm_expr::is_symbol(command);
+        let value = asm_expr::evaluate(command, &mut |symbol| {
+            if is_symbol {
+                return Ok(self.resolve_symbol(symbol, line_no) as i64);
+            }
+            self.labels
+                .get(symbol)
+                .or_else(|| PREDEFINED_SYMBOL_TABLE.get().unwrap().get(symbol))
+                .map(|address| *address as i64)
+                .ok_or_else(|| format!("undefined label or constant {}", symbol))
+        })
+        .map_err(|error| format!("invalid: {}, {}, line: {}", command, error, line_no))?;
+
+        // the 16th bit would turn the constant into a C command
+        if !(0..=MAX_CONSTANT as i64).contains(&value) {
+            return Err(format!(
+                "invalid: constant out of range 0..{}: {}, line: {}",
+                MAX_CONSTANT,
+                if is_symbol || command == value.to_string() {
+                    value.to_string()
+                } else {
+                    format!("{} = {}", command, value)
+                },
+                line_no
+            ));
         }
+        writeln!(&mut self.output, "{:016b}", value).unwrap();
+        Ok(())
     }
 
-    // Generate binary codes for C command which like dest=comp;jmp
-    fn parse_c_command(&mut self, command: &str) {
-        let mut equal_loc = 0;
-        let mut semicolon_loc = command.len();
-        let mut dest = "null".to_owned();
-        let mut jmp = "null".to_owned();
-
-        if command.find('=').is_some() {
-            equal_loc = command.find('=').unwrap();
-            dest = substr(command, 0, equal_loc);
+    // The address of a label or a variable, allocating a new variable for an
+    // unknown symbol
+    fn resolve_symbol(&mut self, symbol: &str, line_no: usize) -> u32 {
+        if let Some(address) = self.symbol_table.get(symbol) {
+            return *address;
         }
 
-        if command.find(';').is_some() {
-            semicolon_loc = command.find(';').unwrap();
-            jmp = substr(command, semicolon_loc + 1, command.len() - semicolon_loc);
+        if self.alloc_address >= self.ram_budget {
+            self.warnings.push(format!(
+                "warning: variable {} at RAM address {} exceeds the budget of {}, line: {}",
+                symbol, self.alloc_address, self.ram_budget, line_no
+            ));
         }
+        let address = self.alloc_address;
+        self.symbol_table.insert(symbol.to_owned(), address);
+        self.variables.insert(symbol.to_owned(), address);
+        self.alloc_address += 1;
+        address
+    }
 
-        let comp = if dest == "null" {
-            substr(command, 0, semicolon_loc)
+    // Generate binary codes for C command which like dest=comp;jmp
+    fn parse_c_command(&mut self, command: &str, line_no: usize) -> Result<(), String> {
+        let command = if self.strict {
+            command.to_owned()
         } else {
-            substr(command, equal_loc + 1, semicolon_loc - equal_loc + 1)
+            normalize_c_command(command)
+        };
+
+        let (dest, rest) = command.split_once('=').unwrap_or(("null", &command));
+        let (comp, jmp) = rest.split_once(';').unwrap_or((rest, "null"));
+        let lookup = |table: &OnceCell<HashMap<&str, &'static str>>, field: &str| {
+            table
+                .get()
+                .unwrap()
+                .get(field)
+                .copied()
+                .ok_or_else(|| format!("invalid: {}, line: {}", command, line_no))
         };
 
+        let shift = self.extended && SHIFT_TABLE.get().unwrap().contains_key(comp);
         writeln!(
             &mut self.output,
-            "111{}{}{}",
-            COMP_TABLE.get().unwrap().get(comp.as_str()).unwrap(),
-            DEST_TABLE.get().unwrap().get(dest.as_str()).unwrap(),
-            JMP_TABLE.get().unwrap().get(jmp.as_str()).unwrap(),
+            "{}{}{}{}",
+            if shift { "101" } else { "111" },
+            lookup(if shift { &SHIFT_TABLE } else { &COMP_TABLE }, comp)?,
+            lookup(&DEST_TABLE, dest)?,
+            lookup(&JMP_TABLE, jmp)?,
         )
         .unwrap();
+        Ok(())
+    }
+
+    /// Problems that do not stop the assembly, one message per line
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn labels(&self) -> &HashMap<String, u32> {
+        &self.labels
+    }
+
+    /// The generated instructions as lines of `0` and `1`
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// The generated instructions as 16-bit words
+    pub fn binary(&self) -> Vec<u16> {
+        String::from_utf8_lossy(&self.output)
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap())
+            .collect()
     }
 
     pub fn save_binary(&self, dst_path: &PathBuf) {
         save_file(&self.output, dst_path).unwrap();
     }
+
+    /// Every line of the source preceded by the ROM address and binary word
+    /// of its instruction, or by blanks for labels, comments and blank lines
+    pub fn listing(&self) -> Vec<u8> {
+        // a line expanding a macro, an include or a pseudo instruction has
+        // several instructions
+        let mut instructions: HashMap<usize, Vec<(usize, String)>> = HashMap::new();
+        for (address, (line_no, word)) in self
+            .line_numbers
+            .iter()
+            .zip(String::from_utf8_lossy(&self.output).lines())
+            .enumerate()
+        {
+            instructions
+                .entry(*line_no)
+                .or_default()
+                .push((address, word.to_owned()));
+        }
+
+        let mut listing = vec![];
+        for (i, line) in self.source.iter().enumerate() {
+            let code = line.split("//").next().unwrap().trim();
+            let lines = match instructions.get(&(i + 1)) {
+                Some(words) if words.len() == 1 && self.codes[words[0].0] == code => {
+                    vec![format!("{:5}  {}  {}", words[0].0, words[0].1, line)]
+                }
+                // the source line, then the instructions it expands into
+                Some(words) => std::iter::once(format!("{:5}  {:16}  {}", "", "", line))
+                    .chain(words.iter().map(|(address, word)| {
+                        format!("{:5}  {}      {}", address, word, self.codes[*address])
+                    }))
+                    .collect(),
+                None => vec![format!("{:5}  {:16}  {}", "", "", line)],
+            };
+            for line in lines {
+                writeln!(&mut listing, "{}", line.trim_end()).unwrap();
+            }
+        }
+        listing
+    }
+
+    /// The labels with their ROM addresses, then the variables with their RAM
+    /// addresses, one `label|variable name address` per line in the order of
+    /// the addresses
+    pub fn symbols(&self) -> Vec<u8> {
+        let mut symbols = vec![];
+        for (kind, table) in [("label", &self.labels), ("variable", &self.variables)] {
+            let mut entries: Vec<(&String, &u32)> = table.iter().collect();
+            entries.sort_by_key(|(name, address)| (**address, *name));
+            for (name, address) in entries {
+                writeln!(&mut symbols, "{} {} {}", kind, name, address).unwrap();
+            }
+        }
+        symbols
+    }
+}
+
+// Spell a C command as in the tables: without whitespace, in uppercase, with
+// the destinations in the order of `AMD` and `D`, `A` or `M` on the left of a
+// commutative operator, so that `md = 1 + m` reads `MD=M+1`
+fn normalize_c_command(command: &str) -> String {
+    let command: String = command
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+
+    let (dest, rest) = match command.split_once('=') {
+        Some((dest, rest)) => (Some(dest), rest),
+        None => (None, command.as_str()),
+    };
+    let (comp, jmp) = match rest.split_once(';') {
+        Some((comp, jmp)) => (comp, Some(jmp)),
+        None => (rest, None),
+    };
+
+    let comp = match comp
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| "+&|".contains(*c))
+    {
+        Some((i, op)) if !COMP_TABLE.get().unwrap().contains_key(comp) => {
+            format!("{}{}{}", &comp[i + 1..], op, &comp[..i])
+        }
+        _ => comp.to_owned(),
+    };
+
+    let mut normalized = String::new();
+    if let Some(dest) = dest {
+        // a repeated destination is left as is to be reported
+        let is_set = dest.len() == "AMD".chars().filter(|c| dest.contains(*c)).count();
+        if is_set {
+            normalized.extend("AMD".chars().filter(|c| dest.contains(*c)));
+        } else {
+            normalized.push_str(dest);
+        }
+        normalized.push('=');
+    }
+    normalized.push_str(&comp);
+    if let Some(jmp) = jmp {
+        normalized.push(';');
+        normalized.push_str(jmp);
+    }
+    normalized
+}
+
+/// Fill the instruction and predefined symbol tables
+pub fn init_tables() {
+    COMP_TABLE.get_or_init(|| {
+        HashMap::from([
+            ("0", "0101010"),
+            ("1", "0111111"),
+            ("-1", "0111010"),
+            ("D", "0001100"),
+            ("A", "0110000"),
+            ("!D", "0001101"),
+            ("!A", "0110001"),
+            ("-D", "0001111"),
+            ("-A", "0110011"),
+            ("D+1", "0011111"),
+            ("A+1", "0110111"),
+            ("D-1", "0001110"),
+            ("A-1", "0110010"),
+            ("D+A", "0000010"),
+            ("D-A", "0010011"),
+            ("A-D", "0000111"),
+            ("D&A", "0000000"),
+            ("D|A", "0010101"),
+            ("M", "1110000"),
+            ("!M", "1110001"),
+            ("-M", "1110011"),
+            ("M+1", "1110111"),
+            ("M-1", "1110010"),
+            ("D+M", "1000010"),
+            ("D-M", "1010011"),
+            ("M-D", "1000111"),
+            ("D&M", "1000000"),
+            ("D|M", "1010101"),
+        ])
+    });
+
+    DEST_TABLE.get_or_init(|| {
+        HashMap::from([
+            ("null", "000"),
+            ("M", "001"),
+            ("D", "010"),
+            ("MD", "011"),
+            ("A", "100"),
+            ("AM", "101"),
+            ("AD", "110"),
+            ("AMD", "111"),
+        ])
+    });
+
+    JMP_TABLE.get_or_init(|| {
+        HashMap::from([
+            ("null", "000"),
+            ("JGT", "001"),
+            ("JEQ", "010"),
+            ("JGE", "011"),
+            ("JLT", "100"),
+            ("JNE", "101"),
+            ("JLE", "110"),
+            ("JMP", "111"),
+        ])
+    });
+
+    PREDEFINED_SYMBOL_TABLE.get_or_init(|| {
+        HashMap::from([
+            ("SP", 0),
+            ("LCL", 1),
+            ("ARG", 2),
+            ("THIS", 3),
+            ("THAT", 4),
+            ("R0", 0),
+            ("R1", 1),
+            ("R2", 2),
+            ("R3", 3),
+            ("R4", 4),
+            ("R5", 5),
+            ("R6", 6),
+            ("R7", 7),
+            ("R8", 8),
+            ("R9", 9),
+            ("R10", 10),
+            ("R11", 11),
+            ("R12", 12),
+            ("R13", 13),
+            ("R14", 14),
+            ("R15", 15),
+            ("SCREEN", 16384),
+            ("KBD", 24576),
+        ])
+    });
+
+    SHIFT_TABLE.get_or_init(|| {
+        HashMap::from([
+            ("A>>", "0000000"),
+            ("D>>", "0010000"),
+            ("A<<", "0100000"),
+            ("D<<", "0110000"),
+            ("M>>", "1000000"),
+            ("M<<", "1100000"),
+        ])
+    });
+}
+
+pub enum Pseudo {
+    // needs a label of its own
+    Halt,
+    Codes(Vec<String>),
+}
+
+// The instructions of a pseudo instruction, None for other codes
+//
+//   goto LABEL      @LABEL, 0;JMP
+//   D=@CONST        @CONST, D=A
+//   JZ D, LABEL     @LABEL, D;JEQ  (also JNZ and the jumps of C commands)
+//   halt            (halt$n), @halt$n, 0;JMP
+pub fn pseudo_instruction(code: &str, line_no: usize) -> Result<Option<Pseudo>, String> {
+    let (word, rest) = match code.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (code, ""),
+    };
+
+    let jump = match word {
+        "JZ" => "JEQ",
+        "JNZ" => "JNE",
+        _ => word,
+    };
+
+    let pseudo = match (word, rest) {
+        ("halt", "") => Some(Pseudo::Halt),
+        ("goto", label) if !label.is_empty() => Some(Pseudo::Codes(vec![
+            format!("@{}", label),
+            "0;JMP".to_owned(),
+        ])),
+        _ if code.split_once('=').is_some_and(|(dest, value)| {
+            dest.trim() == "D" && value.trim_start().starts_with('@')
+        }) =>
+        {
+            let value = code.split_once('=').unwrap().1.trim();
+            Some(Pseudo::Codes(vec![value.to_owned(), "D=A".to_owned()]))
+        }
+        _ if !rest.is_empty() && jump != "null" && JMP_TABLE.get().unwrap().contains_key(jump) => {
+            match rest
+                .split_once(',')
+                .map(|(v, label)| (v.trim(), label.trim()))
+            {
+                Some(("D", label)) if !label.is_empty() => Some(Pseudo::Codes(vec![
+                    format!("@{}", label),
+                    format!("D;{}", jump),
+                ])),
+                _ => {
+                    return Err(format!(
+                        "invalid: {}, expected {} D, LABEL, line: {}",
+                        code, word, line_no
+                    ))
+                }
+            }
+        }
+        _ => None,
+    };
+    Ok(pseudo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(source: &str) -> Assembler {
+        let mut assembler = Assembler::from_source(source);
+        assembler.run().unwrap();
+        assembler
+    }
+
+    const SOURCE: &str = "\
+// count down
+@10
+D=A  // start
+@count
+M=D
+(LOOP)
+@count
+MD=M-1
+JNZ D, LOOP
+@SCREEN
+halt";
+
+    #[test]
+    fn list_addresses_and_words() {
+        let listing = String::from_utf8(assemble(SOURCE).listing()).unwrap();
+        assert_eq!(listing.lines().collect::<Vec<_>>(), [
+            "                         // count down",
+            "    0  0000000000001010  @10",
+            "    1  1110110000010000  D=A  // start",
+            "    2  0000000000010000  @count",
+            "    3  1110001100001000  M=D",
+            "                         (LOOP)",
+            "    4  0000000000010000  @count",
+            "    5  1111110010011000  MD=M-1",
+            "                         JNZ D, LOOP",
+            "    6  0000000000000100      @LOOP",
+            "    7  1110001100000101      D;JNE",
+            "    8  0100000000000000  @SCREEN",
+            "                         halt",
+            "    9  0000000000001001      @halt$1",
+            "   10  1110101010000111      0;JMP",
+        ]);
+    }
+
+    #[test]
+    fn list_labels_then_variables() {
+        let symbols = String::from_utf8(assemble(SOURCE).symbols()).unwrap();
+        assert_eq!(symbols, "label LOOP 4\nlabel halt$1 9\nvariable count 16\n");
+    }
+
+    fn error(source: &str) -> String {
+        Assembler::from_source(source).run().unwrap_err()
+    }
+
+    #[test]
+    fn check_the_range_of_constants() {
+        assert_eq!(assemble("@32767\n@SCREEN+8191").binary(), [32767, 24575]);
+        assert_eq!(
+            error("@0\n@32768"),
+            "invalid: constant out of range 0..32767: 32768, line: 2"
+        );
+        assert_eq!(
+            error("@KBD+KBD"),
+            "invalid: constant out of range 0..32767: KBD+KBD = 49152, line: 1"
+        );
+        assert_eq!(
+            error("@0-1"),
+            "invalid: constant out of range 0..32767: 0-1 = -1, line: 1"
+        );
+    }
+
+    #[test]
+    fn check_labels() {
+        assert_eq!(
+            error("(LOOP)\n@LOOP\n(LOOP)"),
+            "invalid: duplicate label: LOOP, line: 3"
+        );
+        assert_eq!(
+            error("@0\n(R1)"),
+            "invalid: label shadows a predefined symbol: R1, line: 2"
+        );
+        assert_eq!(
+            error("(SCREEN)"),
+            "invalid: label shadows a predefined symbol: SCREEN, line: 1"
+        );
+    }
+
+    #[test]
+    fn check_the_rom_size() {
+        assert!(Assembler::from_source(&"D=0\n".repeat(ROM_SIZE))
+            .run()
+            .is_ok());
+        assert_eq!(
+            error(&"D=0\n".repeat(ROM_SIZE + 1)),
+            "invalid: 32769 instructions do not fit in the ROM of 32768 words"
+        );
+    }
+
+    #[test]
+    fn warn_about_variables_past_the_budget() {
+        let mut assembler = Assembler::from_source("@a\n@b\n@c\n@a");
+        assembler.set_ram_budget(18);
+        assembler.run().unwrap();
+
+        assert_eq!(assembler.binary(), [16, 17, 18, 16]);
+        assert_eq!(assembler.warnings(), [
+            "warning: variable c at RAM address 18 exceeds the budget of 18, line: 3"
+        ]);
+    }
+
+    #[test]
+    fn evaluate_expressions_of_labels_and_constants() {
+        let source = "@SCREEN+32*2\n@END-1\n@'A'+0x10\n(END)\n@x\n@R5+0b11";
+        assert_eq!(assemble(source).binary(), [16448, 2, 81, 16, 8]);
+
+        assert_eq!(
+            error("@FOO+1"),
+            "invalid: FOO+1, undefined label or constant FOO, line: 1"
+        );
+        // not even a variable allocated before
+        assert_eq!(
+            error("@x\n@x+1"),
+            "invalid: x+1, undefined label or constant x, line: 2"
+        );
+    }
+
+    #[test]
+    fn substitute_defines_in_pseudo_instructions() {
+        let source = "\
+.define N 5
+.define L END
+.define Z SCREEN+1
+D=@N
+JZ D, L
+goto L
+D = @Z
+(END)
+halt";
+        let assembler = assemble(source);
+        assert_eq!(&assembler.codes[..8], [
+            "@5",
+            "D=A",
+            "@END",
+            "D;JEQ",
+            "@END",
+            "0;JMP",
+            "@SCREEN+1",
+            "D=A"
+        ]);
+        assert_eq!(assembler.binary()[..8], [
+            5, 0xec10, 8, 0xe302, 8, 0xea87, 16385, 0xec10
+        ]);
+        assert!(assembler.variables.is_empty());
+    }
+
+    #[test]
+    fn normalize_c_commands() {
+        init_tables();
+        for (command, normalized) in [
+            ("D=M+1", "D=M+1"),
+            ("md = 1 + m", "MD=M+1"),
+            ("A+D", "D+A"),
+            ("M=M&D", "M=D&M"),
+            ("d=a|d;jgt", "D=D|A;JGT"),
+            ("DMA=0", "AMD=0"),
+            ("MA=-1", "AM=-1"),
+            ("0 ; jmp", "0;JMP"),
+            // not commutative, left to be reported
+            ("D=1-M", "D=1-M"),
+            ("MM=D", "MM=D"),
+        ] {
+            assert_eq!(normalize_c_command(command), normalized);
+        }
+    }
+
+    #[test]
+    fn assemble_commuted_commands_unless_strict() {
+        let source = "d = m + d\nMD=1+M\nam=D|A;jne";
+        assert_eq!(
+            assemble(source).binary(),
+            assemble("D=D+M\nMD=M+1\nAM=D|A;JNE").binary()
+        );
+
+        let mut assembler = Assembler::from_source(source);
+        assembler.set_strict(true);
+        assert_eq!(assembler.run().unwrap_err(), "invalid: d = m + d, line: 1");
+        assert_eq!(error("D=1-M"), "invalid: D=1-M, line: 1");
+        assert_eq!(error("MM=D"), "invalid: MM=D, line: 1");
+    }
 }
@@ -5,10 +5,18 @@ use crate::symbol_table::VarKind;
 #[derive(Default)]
 pub struct Class {
     pub name:        String,
+    pub consts:      Vec<ConstDec>,
     pub vars:        Vec<ClassVarDec>,
     pub subroutines: Vec<SubroutineDec>,
 }
 
+/// `const int NAME = expr;`, members of an `enum` are lowered to these too
+pub struct ConstDec {
+    pub name:  String,
+    pub value: Expression,
+    pub line:  usize,
+}
+
 pub struct ClassVarDec {
     pub kind:  ClassScope,
     pub type_: VarType,
@@ -44,6 +52,7 @@ pub struct SubroutineDec {
     pub name:   String,
     pub params: Vec<Param>,
     pub body:   SubroutineBody,
+    pub line:   usize,
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -53,6 +62,8 @@ pub enum SubroutineKind {
     Method,
 }
 
+/// The return type of a subroutine
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum SubroutineType {
     Void,
     Type(VarType),
@@ -81,29 +92,47 @@ pub enum Statement {
     Return(ReturnStatement),
 }
 
+impl Statement {
+    /// Line of the statement's keyword in the source file
+    pub fn line(&self) -> usize {
+        match self {
+            Statement::Let(v) => v.line,
+            Statement::If(v) => v.line,
+            Statement::While(v) => v.line,
+            Statement::Do(v) => v.line,
+            Statement::Return(v) => v.line,
+        }
+    }
+}
+
 pub struct LetStatement {
     pub var_name:    String,
     pub array_index: Option<Expression>,
     pub right_expr:  Expression,
+    pub line:        usize,
 }
 
 pub struct IfStatement {
     pub cond:      Expression,
     pub if_body:   Vec<Statement>,
     pub else_body: Option<Vec<Statement>>,
+    pub line:      usize,
 }
 
 pub struct WhileStatement {
     pub cond: Expression,
     pub body: Vec<Statement>,
+    pub line: usize,
 }
 
 pub struct DoStatement {
     pub subroutine_call: SubroutineCall,
+    pub line:            usize,
 }
 
 pub struct ReturnStatement {
     pub expr: Option<Expression>,
+    pub line: usize,
 }
 
 pub struct Expression {
@@ -121,6 +150,7 @@ pub enum Term {
     StringConst(String),
     KeywordConst(KeywordConstant),
     VarName(String),
+    ConstRef(ConstRef),
     Array(Array),
     SubRoutineCall(SubroutineCall),
     Expression(Expression),
@@ -132,6 +162,12 @@ pub struct Array {
     pub index: Box<Expression>,
 }
 
+/// `Class.NAME` referring to a constant of another class
+pub struct ConstRef {
+    pub class_name: String,
+    pub name:       String,
+}
+
 pub enum SubroutineCall {
     Internal(InternalCall),
     External(ExternalCall),
@@ -167,6 +203,7 @@ pub enum Op {
     Euqal,
 }
 
+#[derive(PartialEq, Eq)]
 pub enum UnaryOp {
     Neg,
     Not,
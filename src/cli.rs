@@ -0,0 +1,996 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::Assembler;
+use crate::disassembler::disassemble_rom;
+use crate::emulator::Emulator;
+use crate::hack_format::{self, HackFormat};
+use crate::hdl::simulator::{ChipLibrary, Simulator};
+use crate::hdl::stats;
+use crate::hdl::test_script::TestScript;
+use crate::hdl::verilog;
+use crate::jack_compiler::compile_to_vm;
+use crate::jack_parser::JackParser;
+use crate::jack_tokenizer::JackTokenizer;
+use crate::native_os;
+use crate::profiler::Profiler;
+use crate::utils::{expand_path, is_stdio, save_file, Input, OutputOptions};
+use crate::vm_interpreter::VmInterpreter;
+use crate::vm_linker::{check, Diagnostic};
+use crate::vm_translator::{link_os, read_vm_sources, Bootstrap, OsOptions, VmTranslator};
+use crate::vm_writer::DebugInfo;
+
+/// Run the command line tool on the arguments of the process
+pub fn run() {
+    let cmd = clap::Command::new("compiler")
+        .version(clap::crate_version!())
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .subcommand_required(true)
+        .subcommand(
+            clap::Command::new("asm")
+                .about("Compile *.asm files into *.hack files")
+                .arg(
+                    clap::Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("paths to *.asm files, patterns like *.asm, or - for stdin"),
+                )
+                .arg(
+                    clap::Arg::new("format")
+                        .long("format")
+                        .num_args(1)
+                        .default_value("text")
+                        .value_parser(["text", "raw", "hex", "logisim"])
+                        .help("write binary text, raw big-endian words, Intel HEX or a Logisim image"),
+                )
+                .arg(
+                    clap::Arg::new("listing")
+                        .long("listing")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("write a *.lst file with the ROM address and binary word of each source line"),
+                )
+                .arg(
+                    clap::Arg::new("symbols")
+                        .long("symbols")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("write a *.sym file with the addresses of the labels and variables"),
+                )
+                .arg(
+                    clap::Arg::new("ram-budget")
+                        .long("ram-budget")
+                        .num_args(1)
+                        .default_value("256")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("warn about variables allocated at or above this RAM address"),
+                )
+                .arg(
+                    clap::Arg::new("include-dir")
+                        .long("include-dir")
+                        .short('I')
+                        .num_args(1)
+                        .action(clap::ArgAction::Append)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("search this directory for .include files"),
+                )
+                .arg(
+                    clap::Arg::new("strict")
+                        .long("strict")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("only accept C-instructions spelled as in the book"),
+                )
+                .arg(extended_arg())
+                .args(output_args()),
+        )
+        .subcommand(
+            clap::Command::new("vm")
+                .about("Compile *.vm files into an *.asm file")
+                .arg(
+                    clap::Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("paths to *.vm files or directories, patterns like *.vm, or - for stdin"),
+                )
+                .args(output_args())
+                .arg(
+                    clap::Arg::new("annotate")
+                        .long("annotate")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("precede the assembly of each VM command with its file, line and text"),
+                )
+                .arg(
+                    clap::Arg::new("map")
+                        .long("map")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("write a *.asm.map file mapping ROM addresses to VM commands"),
+                )
+                .arg(
+                    clap::Arg::new("no-os")
+                        .long("no-os")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("os-dir")
+                        .help("do not link the bundled Jack OS classes"),
+                )
+                .arg(
+                    clap::Arg::new("os-dir")
+                        .long("os-dir")
+                        .num_args(1)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("directory of *.vm or *.jack files overriding the bundled Jack OS classes"),
+                )
+                .arg(
+                    clap::Arg::new("keep-unused")
+                        .long("keep-unused")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("keep the functions that are never called"),
+                )
+                .arg(extended_arg())
+                .arg(
+                    clap::Arg::new("bootstrap")
+                        .long("bootstrap")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("no-bootstrap")
+                        .help("emit the bootstrap code, the default for a directory"),
+                )
+                .arg(
+                    clap::Arg::new("no-bootstrap")
+                        .long("no-bootstrap")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("do not emit the bootstrap code, the default for a single file"),
+                )
+                .arg(
+                    clap::Arg::new("sp")
+                        .long("sp")
+                        .num_args(1)
+                        .default_value("256")
+                        .value_parser(clap::value_parser!(u16))
+                        .help("initial value of SP set by the bootstrap"),
+                )
+                .arg(
+                    clap::Arg::new("entry")
+                        .long("entry")
+                        .num_args(1)
+                        .default_value("Sys.init")
+                        .help("function called by the bootstrap"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("token")
+                .about("Compile *.jack files into *.token.xml files")
+                .arg(
+                    clap::Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("paths to *.jack files or directories, patterns like *.jack, or - for stdin"),
+                )
+                .args(output_args()),
+        )
+        .subcommand(
+            clap::Command::new("parse")
+                .about("Compile *.jack files into *.tree.xml files")
+                .arg(
+                    clap::Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("paths to *.jack files or directories, patterns like *.jack, or - for stdin"),
+                )
+                .args(output_args()),
+        )
+        .subcommand(
+            clap::Command::new("compile")
+                .about("Compile *.jack files into *.vm files")
+                .arg(
+                    clap::Arg::new("path")
+                        .long("path")
+                        .short('p')
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::builder::ValueParser::os_string())
+                        .help("paths to *.jack files or directories, patterns like *.jack, or - for stdin"),
+                )
+                .args(output_args())
+                .arg(
+                    clap::Arg::new("optimize")
+                        .short('O')
+                        .action(clap::ArgAction::SetTrue)
+                        .help("fold constants and simplify expressions"),
+                )
+                .arg(
+                    clap::Arg::new("debug")
+                        .long("debug")
+                        .short('g')
+                        .num_args(1)
+                        .value_parser(["comments", "map"])
+                        .help("record the Jack source line of each VM command as comments or in a *.vm.map file"),
+                ),
+        );
+
+    let cmd = cmd.subcommand(
+        clap::Command::new("run")
+            .about("Run *.asm or machine code file in the Hack CPU emulator")
+            .arg(
+                clap::Arg::new("path")
+                    .long("path")
+                    .short('p')
+                    .required(true)
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("path to *.asm file or machine code file"),
+            )
+            .arg(
+                clap::Arg::new("format")
+                    .long("format")
+                    .num_args(1)
+                    .value_parser(["text", "raw", "hex", "logisim"])
+                    .help("format of the machine code, guessed from the file by default"),
+            )
+            .arg(
+                clap::Arg::new("cycles")
+                    .long("cycles")
+                    .num_args(1)
+                    .default_value("10000000")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("stop after this many cycles if the program has not halted"),
+            )
+            .arg(
+                clap::Arg::new("profile")
+                    .long("profile")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("print the cycles spent in each VM function, needs a *.asm file"),
+            )
+            .arg(
+                clap::Arg::new("collapsed")
+                    .long("collapsed")
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("write the profiled call stacks in collapsed format for flame graphs"),
+            )
+            .arg(extended_arg()),
+    );
+
+    let cmd = cmd.subcommand(
+        clap::Command::new("disasm")
+            .about("Disassemble machine code files into *.asm files")
+            .arg(
+                clap::Arg::new("path")
+                    .long("path")
+                    .short('p')
+                    .required(true)
+                    .num_args(1..)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("paths to *.hack files, patterns like *.hack, or - for stdin"),
+            )
+            .arg(
+                clap::Arg::new("format")
+                    .long("format")
+                    .num_args(1)
+                    .value_parser(["text", "raw", "hex", "logisim"])
+                    .help("format of the machine code, guessed from the file by default"),
+            )
+            .arg(extended_arg())
+            .args(output_args()),
+    );
+
+    let cmd = cmd.subcommand(
+        clap::Command::new("vm-run")
+            .about("Run *.vm file or directory in the VM interpreter with a native Jack OS")
+            .arg(
+                clap::Arg::new("path")
+                    .long("path")
+                    .short('p')
+                    .required(true)
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("path to *.vm file or directory"),
+            )
+            .arg(
+                clap::Arg::new("cycles")
+                    .long("cycles")
+                    .num_args(1)
+                    .default_value("10000000")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("stop after this many VM commands if the program has not halted"),
+            )
+            .arg(
+                clap::Arg::new("jack")
+                    .long("jack")
+                    .num_args(1)
+                    .value_delimiter(',')
+                    .help("OS functions or classes to run in Jack instead of natively, e.g. Math.multiply,Output"),
+            )
+            .arg(
+                clap::Arg::new("os-dir")
+                    .long("os-dir")
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("directory of *.vm or *.jack files overriding the bundled Jack OS classes"),
+            ),
+    );
+
+    let cmd = cmd.subcommand(
+        clap::Command::new("hdl")
+            .about("Evaluate a chip of a *.hdl file on given inputs, or run a *.tst test script")
+            .arg(
+                clap::Arg::new("path")
+                    .long("path")
+                    .short('p')
+                    .required(true)
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("path to *.hdl or *.tst file"),
+            )
+            .arg(
+                clap::Arg::new("set")
+                    .long("set")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .help("set an input pin, e.g. a=1 or x=0x7fff, unset inputs are 0"),
+            )
+            .arg(
+                clap::Arg::new("search-dir")
+                    .long("search-dir")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("directory to search for the *.hdl files of parts, after the chip's directory"),
+            )
+            .arg(
+                clap::Arg::new("rom")
+                    .long("rom")
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("machine code file to load into the ROM32K parts"),
+            )
+            .arg(
+                clap::Arg::new("format")
+                    .long("format")
+                    .num_args(1)
+                    .value_parser(["text", "raw", "hex", "logisim"])
+                    .help("format of the machine code, guessed from the file by default"),
+            )
+            .arg(
+                clap::Arg::new("cycles")
+                    .long("cycles")
+                    .num_args(1)
+                    .default_value("0")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("clock cycles (tick, tock) to run after evaluating the inputs"),
+            )
+            .arg(
+                clap::Arg::new("peek")
+                    .long("peek")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .help("print a built-in part at the end, e.g. RAM16K[0] or ARegister"),
+            )
+            .arg(
+                clap::Arg::new("stats")
+                    .long("stats")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("print the Nand gates of the chip and its parts and the critical path instead"),
+            ),
+    );
+
+    let cmd = cmd.subcommand(
+        clap::Command::new("verilog")
+            .about("Export a chip of a *.hdl file and its parts to Verilog")
+            .arg(
+                clap::Arg::new("path")
+                    .long("path")
+                    .short('p')
+                    .required(true)
+                    .num_args(1)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("path to *.hdl file"),
+            )
+            .arg(
+                clap::Arg::new("search-dir")
+                    .long("search-dir")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .value_parser(clap::builder::ValueParser::os_string())
+                    .help("directory to search for the *.hdl files of parts, after the chip's directory"),
+            )
+            .arg(
+                clap::Arg::new("no-primitives")
+                    .long("no-primitives")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("leave out the modules of the built-in chips, provided by the toolchain"),
+            )
+            .arg(
+                clap::Arg::new("testbench")
+                    .long("testbench")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(usize))
+                    .help("also write a *_tb.v testbench checking this many input vectors run through the simulator"),
+            )
+            .args(output_args()),
+    );
+
+    let matches = cmd.get_matches();
+
+    match matches.subcommand() {
+        Some(("asm", matches)) => assembly(matches),
+        Some(("vm", matches)) => vm_translate(matches),
+        Some(("token", matches)) => tokenize(matches),
+        Some(("parse", matches)) => parse(matches),
+        Some(("compile", matches)) => compile(matches),
+        Some(("run", matches)) => emulate(matches),
+        Some(("vm-run", matches)) => vm_run(matches),
+        Some(("disasm", matches)) => disassembly(matches),
+        Some(("hdl", matches)) => hdl(matches),
+        Some(("verilog", matches)) => export_verilog(matches),
+        _ => unreachable!(),
+    }
+}
+
+// The --out and --out-dir options of the commands writing files
+fn output_args() -> [clap::Arg; 2] {
+    [
+        clap::Arg::new("out")
+            .long("out")
+            .short('o')
+            .num_args(1)
+            .conflicts_with("out-dir")
+            .value_parser(clap::builder::ValueParser::os_string())
+            .help("output file, or - for stdout, instead of output/<name> next to the input"),
+        clap::Arg::new("out-dir")
+            .long("out-dir")
+            .num_args(1)
+            .value_parser(clap::builder::ValueParser::os_string())
+            .help("directory of the output files instead of output/ next to each input"),
+    ]
+}
+
+// The files given by --path, directories and patterns being expanded to
+// their files with the extension
+fn inputs(matches: &clap::ArgMatches, extension: &str) -> Vec<Input> {
+    matches
+        .get_many::<OsString>("path")
+        .unwrap()
+        .flat_map(|path| expand_path(path, extension))
+        .collect()
+}
+
+// The --extended option of the commands dealing with machine code
+fn extended_arg() -> clap::Arg {
+    clap::Arg::new("extended")
+        .long("extended")
+        .action(clap::ArgAction::SetTrue)
+        .help("use the shift instructions like D=D<< of the extended instruction set")
+}
+
+fn output_options(matches: &clap::ArgMatches, outputs: usize) -> OutputOptions {
+    let out = matches.get_one::<OsString>("out").map(PathBuf::from);
+    if out.is_some() && outputs > 1 {
+        panic!("invalid: --out with {} outputs, use --out-dir", outputs);
+    }
+
+    OutputOptions {
+        out,
+        out_dir: matches.get_one::<OsString>("out-dir").map(PathBuf::from),
+    }
+}
+
+// The --format option of the commands reading or writing machine code
+fn hack_format(matches: &clap::ArgMatches) -> Option<HackFormat> {
+    matches
+        .get_one::<String>("format")
+        .map(|v| HackFormat::from_name(v).unwrap())
+}
+
+// Report a file being written, keeping the standard output for the output
+// itself
+fn print_output(dst_path: &Path) {
+    if !is_stdio(dst_path) {
+        eprintln!("\noutput: {}", dst_path.to_str().unwrap());
+    }
+}
+
+fn assembly(matches: &clap::ArgMatches) {
+    let inputs = inputs(matches, "asm");
+    let output = output_options(matches, inputs.len());
+
+    let format = hack_format(matches).unwrap();
+
+    for input in inputs.iter() {
+        let mut assembler = Assembler::from_source(&input.read());
+        assembler.set_ram_budget(*matches.get_one::<u32>("ram-budget").unwrap());
+        // the directory of the input, or the working directory for stdin
+        let include_dirs = input
+            .dir()
+            .into_iter()
+            .chain(
+                matches
+                    .get_many::<OsString>("include-dir")
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::from),
+            )
+            .collect();
+        assembler.set_include_dirs(include_dirs);
+        assembler.set_strict(matches.get_flag("strict"));
+        assembler.set_extended(matches.get_flag("extended"));
+        or_exit(assembler.run());
+        for warning in assembler.warnings() {
+            eprintln!("{}", warning);
+        }
+
+        // the text format keeps the name of the files under data/asm/output
+        let extension = match format {
+            HackFormat::Text => "vm",
+            _ => format.extension(),
+        };
+        let dst_path = output.path(
+            &format!("{}.{}", input.name(), extension),
+            input.dir().as_deref(),
+        );
+        print_output(&dst_path);
+
+        if format == HackFormat::Text {
+            assembler.save_binary(&dst_path);
+        } else {
+            save_file(&hack_format::encode(&assembler.binary(), format), &dst_path).unwrap();
+        }
+
+        for (flag, extension, content) in [
+            (
+                "listing",
+                "lst",
+                Assembler::listing as fn(&Assembler) -> Vec<u8>,
+            ),
+            ("symbols", "sym", Assembler::symbols),
+        ] {
+            if !matches.get_flag(flag) {
+                continue;
+            }
+            if is_stdio(&dst_path) {
+                panic!("invalid: --{} needs an output file", flag);
+            }
+            save_file(&content(&assembler), &dst_path.with_extension(extension)).unwrap();
+        }
+    }
+}
+
+fn disassembly(matches: &clap::ArgMatches) {
+    let inputs = inputs(matches, "hack");
+    let output = output_options(matches, inputs.len());
+    let format = hack_format(matches);
+
+    for input in inputs.iter() {
+        let content = input.read_bytes();
+        let format = format.unwrap_or_else(|| match &input {
+            Input::File(path) => {
+                let extension = path.extension().and_then(|v| v.to_str()).unwrap_or("");
+                HackFormat::detect(extension, &content)
+            }
+            Input::Stdin => HackFormat::detect("", &content),
+        });
+        let rom = hack_format::decode(&content, format);
+
+        let dst_path = output.path(&format!("{}.asm", input.name()), input.dir().as_deref());
+        print_output(&dst_path);
+
+        save_file(
+            &disassemble_rom(&rom, matches.get_flag("extended")),
+            &dst_path,
+        )
+        .unwrap();
+    }
+}
+
+fn vm_translate(matches: &clap::ArgMatches) {
+    let paths: Vec<PathBuf> = matches
+        .get_many::<OsString>("path")
+        .unwrap()
+        .map(PathBuf::from)
+        .collect();
+    let inputs = inputs(matches, "vm");
+    let output = output_options(matches, 1);
+
+    // all the files make up one program, named after the first path
+    let (name, dir) = if paths[0].is_dir() {
+        let dir = paths[0].canonicalize().unwrap();
+        (
+            dir.file_name().unwrap().to_str().unwrap().to_owned(),
+            Some(dir),
+        )
+    } else {
+        (inputs[0].name(), inputs[0].dir())
+    };
+    let dst_path = output.path(&format!("{}.asm", name), dir.as_deref());
+    if matches.get_flag("map") && is_stdio(&dst_path) {
+        panic!("invalid: --map needs an output file");
+    }
+
+    let vm_sources: Vec<(String, String)> = inputs
+        .iter()
+        .map(|input| (input.name(), input.read()))
+        .collect();
+
+    let annotate = matches.get_flag("annotate");
+    let os = OsOptions {
+        link: !matches.get_flag("no-os"),
+        dir:  matches
+            .get_one::<OsString>("os-dir")
+            .map(|v| PathBuf::from(v).canonicalize().unwrap()),
+    };
+    let eliminate = !matches.get_flag("keep-unused");
+    let bootstrap = if matches.get_flag("bootstrap") {
+        true
+    } else if matches.get_flag("no-bootstrap") {
+        false
+    } else {
+        paths[0].is_dir() || vm_sources.len() > 1
+    };
+    let bootstrap = bootstrap.then(|| Bootstrap {
+        sp:    *matches.get_one::<u16>("sp").unwrap(),
+        entry: matches.get_one::<String>("entry").unwrap().to_owned(),
+    });
+    let mut vm_translator = or_exit(VmTranslator::new(
+        vm_sources, annotate, os, eliminate, bootstrap,
+    ));
+    vm_translator.set_extended(matches.get_flag("extended"));
+    // the Jack source lines recorded by `compile -g map`
+    if annotate || matches.get_flag("map") {
+        for input in inputs.iter() {
+            if let Input::File(path) = input {
+                if let Ok(map) = read_to_string(path.with_extension("vm.map")) {
+                    vm_translator.set_jack_map(&input.name(), &map);
+                }
+            }
+        }
+    }
+
+    report_diagnostics(vm_translator.diagnostics());
+    if let Some(reachability) = vm_translator.reachability() {
+        if !reachability.removed.is_empty() {
+            eprintln!(
+                "removed {} unused functions: {}",
+                reachability.removed.len(),
+                reachability.removed.join(", ")
+            );
+        }
+    }
+    or_exit(vm_translator.run());
+
+    print_output(&dst_path);
+    vm_translator.save_file(&dst_path);
+
+    if matches.get_flag("map") {
+        vm_translator.save_map(&dst_path.with_extension("asm.map"));
+    }
+}
+
+// Print the error of invalid input and exit
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    })
+}
+
+// Print the problems found when linking *.vm files and exit on errors
+fn report_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{}", diagnostic);
+    }
+    let errors = diagnostics.iter().filter(|v| v.is_error()).count();
+    if errors > 0 {
+        eprintln!("linking failed with {} errors", errors);
+        std::process::exit(1);
+    }
+}
+
+fn tokenize(matches: &clap::ArgMatches) {
+    let inputs = inputs(matches, "jack");
+    let output = output_options(matches, inputs.len());
+
+    for input in inputs.iter() {
+        let mut tokenizer = JackTokenizer::from_source(&input.read());
+        or_exit(tokenizer.run());
+
+        let dst_path = output.path(
+            &format!("{}.token.xml", input.name()),
+            input.dir().as_deref(),
+        );
+        print_output(&dst_path);
+
+        tokenizer.save_file(&dst_path);
+    }
+}
+
+fn parse(matches: &clap::ArgMatches) {
+    let inputs = inputs(matches, "jack");
+    let output = output_options(matches, inputs.len());
+
+    for input in inputs.iter() {
+        let mut tokenizer = JackTokenizer::from_source(&input.read());
+        or_exit(tokenizer.run());
+
+        let mut parser = JackParser::new(tokenizer.tokens());
+        or_exit(parser.run());
+
+        let dst_path = output.path(
+            &format!("{}.tree.xml", input.name()),
+            input.dir().as_deref(),
+        );
+        print_output(&dst_path);
+
+        parser.save_file(&dst_path);
+    }
+}
+
+fn compile(matches: &clap::ArgMatches) {
+    let inputs = inputs(matches, "jack");
+    let output = output_options(matches, inputs.len());
+
+    let optimize = matches.get_flag("optimize");
+    let debug_info = match matches.get_one::<String>("debug").map(|v| v.as_str()) {
+        Some("comments") => Some(DebugInfo::Comments),
+        Some("map") => Some(DebugInfo::Map),
+        _ => None,
+    };
+
+    let sources: Vec<String> = inputs.iter().map(|input| input.read()).collect();
+    let sources: Vec<&str> = sources.iter().map(|v| v.as_str()).collect();
+
+    for (input, vm_writer) in inputs
+        .iter()
+        .zip(or_exit(compile_to_vm(&sources, optimize, debug_info)))
+    {
+        // the standard input is named after its class
+        let name = match input {
+            Input::Stdin => vm_writer.class_name().to_owned(),
+            Input::File(_) => input.name(),
+        };
+        let dst_path = output.path(&format!("{}.vm", name), input.dir().as_deref());
+        if debug_info == Some(DebugInfo::Map) && is_stdio(&dst_path) {
+            panic!("invalid: -g map needs an output file");
+        }
+        print_output(&dst_path);
+
+        vm_writer.save_file(dst_path);
+    }
+}
+
+fn emulate(matches: &clap::ArgMatches) {
+    let path = matches.get_one::<OsString>("path").unwrap();
+    let path = PathBuf::from(path).canonicalize().unwrap();
+    let max_cycles = *matches.get_one::<u64>("cycles").unwrap();
+    let collapsed = matches.get_one::<OsString>("collapsed").map(PathBuf::from);
+    let profile = matches.get_flag("profile") || collapsed.is_some();
+
+    // labels are only known when assembling the program here
+    let (rom, labels) = if path.extension().unwrap() == "asm" {
+        let mut assembler = Assembler::new(path.clone());
+        assembler.set_extended(matches.get_flag("extended"));
+        or_exit(assembler.run());
+        (assembler.binary(), assembler.labels().clone())
+    } else {
+        (
+            Emulator::load_rom(path, hack_format(matches)),
+            HashMap::new(),
+        )
+    };
+    if profile && labels.is_empty() {
+        panic!("profiling needs the *.asm file to find the function labels");
+    }
+
+    let mut emulator = Emulator::new(rom);
+    emulator.set_extended(matches.get_flag("extended"));
+
+    if profile {
+        let mut profiler = Profiler::new(&labels);
+        while !emulator.is_halted() && emulator.cycles < max_cycles {
+            profiler.record(emulator.pc, emulator.cycles);
+            emulator.step();
+        }
+        profiler.finish(emulator.cycles);
+
+        println!("{}", String::from_utf8_lossy(&profiler.report()));
+        if let Some(collapsed) = collapsed {
+            println!("output: {}", collapsed.to_str().unwrap());
+            save_file(&profiler.collapsed_stacks(), &collapsed).unwrap();
+        }
+    } else {
+        emulator.run(max_cycles);
+    }
+
+    println!(
+        "{} after {} cycles",
+        if emulator.is_halted() {
+            "halted"
+        } else {
+            "stopped"
+        },
+        emulator.cycles
+    );
+}
+
+fn vm_run(matches: &clap::ArgMatches) {
+    let path = matches.get_one::<OsString>("path").unwrap();
+    let path = PathBuf::from(path).canonicalize().unwrap();
+    let max_cycles = *matches.get_one::<u64>("cycles").unwrap();
+    let os_dir = matches
+        .get_one::<OsString>("os-dir")
+        .map(|v| PathBuf::from(v).canonicalize().unwrap());
+    let jack: Vec<&String> = matches
+        .get_many::<String>("jack")
+        .map_or(vec![], |v| v.collect());
+
+    // a function runs in Jack when it or its class is listed
+    let mut natives = native_os::NATIVES.to_vec();
+    for name in jack.iter() {
+        let count = natives.len();
+        natives.retain(|(native, _)| {
+            native != name && native.split('.').next().unwrap() != name.as_str()
+        });
+        if natives.len() == count {
+            panic!("invalid: {}, not a native OS function or class", name);
+        }
+    }
+
+    let mut vm_sources = read_vm_sources(&path);
+    let entry = path.is_dir().then_some("Sys.init");
+    or_exit(link_os(&mut vm_sources, entry, os_dir.as_deref()));
+
+    report_diagnostics(&check(&vm_sources, entry));
+
+    let mut interpreter = VmInterpreter::new(&vm_sources, &natives);
+    interpreter.run(max_cycles);
+
+    println!(
+        "{} after {} commands",
+        if interpreter.is_halted() {
+            "halted"
+        } else {
+            "stopped"
+        },
+        interpreter.cycles
+    );
+}
+
+fn hdl(matches: &clap::ArgMatches) {
+    let path = matches.get_one::<OsString>("path").unwrap();
+    let path = PathBuf::from(path).canonicalize().unwrap();
+    let search_dirs = matches
+        .get_many::<OsString>("search-dir")
+        .map_or(vec![], |v| v.map(PathBuf::from).collect());
+
+    if path.extension().is_some_and(|v| v == "tst") {
+        hdl_test(&path, search_dirs);
+        return;
+    }
+
+    let mut library = ChipLibrary::new(search_dirs);
+    let chip = library.load_file(&path);
+    if matches.get_flag("stats") {
+        let stats = stats::analyze(chip, &mut library);
+        print!("{}", String::from_utf8_lossy(&stats.report()));
+        return;
+    }
+    let mut simulator = Simulator::new(chip, &mut library);
+
+    for assignment in matches.get_many::<String>("set").into_iter().flatten() {
+        let (pin, value) = assignment
+            .split_once('=')
+            .unwrap_or_else(|| panic!("invalid: {}, expected pin=value", assignment));
+        let value = parse_pin_value(value.trim())
+            .unwrap_or_else(|| panic!("invalid: {}, not a number", assignment));
+        simulator.set(pin.trim(), value);
+    }
+    if let Some(rom) = matches.get_one::<OsString>("rom") {
+        let rom = Emulator::load_rom(PathBuf::from(rom), hack_format(matches));
+        simulator.load_rom(&rom);
+    }
+
+    simulator.eval();
+    for _ in 0..*matches.get_one::<u64>("cycles").unwrap() {
+        simulator.tick();
+        simulator.tock();
+    }
+
+    for pin in simulator.chip().outputs.iter() {
+        print_pin_value(&pin.name, simulator.get(&pin.name), pin.width);
+    }
+    for peek in matches.get_many::<String>("peek").into_iter().flatten() {
+        // RAM16K[3], or ARegister and ARegister[] for a register
+        let (part, address) = match peek.split_once('[') {
+            Some((part, index)) => {
+                let index = index
+                    .strip_suffix(']')
+                    .unwrap_or_else(|| panic!("invalid: {}", peek));
+                let address = if index.is_empty() {
+                    0
+                } else {
+                    index
+                        .parse()
+                        .unwrap_or_else(|_| panic!("invalid: {}", peek))
+                };
+                (part, address)
+            }
+            None => (peek.as_str(), 0),
+        };
+        print_pin_value(peek, simulator.peek(part, address), 16);
+    }
+}
+
+fn hdl_test(path: &Path, search_dirs: Vec<PathBuf>) {
+    let source = read_to_string(path).unwrap();
+    let result = TestScript::new(path, search_dirs).run(&source);
+
+    if let Some(output_file) = &result.output_file {
+        print_output(output_file);
+    }
+    if let Some(mismatch) = result.mismatch {
+        eprintln!("{}", mismatch);
+        std::process::exit(1);
+    }
+    if result.compared {
+        println!("comparison ended successfully");
+    }
+}
+
+fn export_verilog(matches: &clap::ArgMatches) {
+    let path = matches.get_one::<OsString>("path").unwrap();
+    let path = PathBuf::from(path).canonicalize().unwrap();
+    let search_dirs = matches
+        .get_many::<OsString>("search-dir")
+        .map_or(vec![], |v| v.map(PathBuf::from).collect());
+    let vectors = matches.get_one::<usize>("testbench").copied();
+    let output = output_options(matches, if vectors.is_some() { 2 } else { 1 });
+
+    let mut library = ChipLibrary::new(search_dirs);
+    let chip = library.load_file(&path);
+    // report the errors of the chip like the simulator does
+    Simulator::new(chip.clone(), &mut library);
+
+    let dir = path.parent();
+    let dst_path = output.path(&format!("{}.v", chip.name), dir);
+    print_output(&dst_path);
+    let design = verilog::export(&chip, &mut library, !matches.get_flag("no-primitives"));
+    save_file(&design, &dst_path).unwrap();
+
+    if let Some(vectors) = vectors {
+        let dst_path = output.path(&format!("{}_tb.v", chip.name), dir);
+        print_output(&dst_path);
+        save_file(&verilog::testbench(chip, &mut library, vectors), &dst_path).unwrap();
+    }
+}
+
+// Single bits as 0 or 1, buses in binary followed by their signed value
+fn print_pin_value(name: &str, value: u64, width: usize) {
+    if width == 1 {
+        println!("{} = {}", name, value);
+    } else {
+        // sign extend for the decimal value
+        let signed = ((value << (64 - width)) as i64) >> (64 - width);
+        println!("{} = {:0width$b} ({})", name, value, signed, width = width);
+    }
+}
+
+// Decimal, negative in two's complement, 0x hexadecimal or 0b binary
+fn parse_pin_value(value: &str) -> Option<u64> {
+    if let Some(digits) = value.strip_prefix("0x") {
+        u64::from_str_radix(digits, 16).ok()
+    } else if let Some(digits) = value.strip_prefix("0b") {
+        u64::from_str_radix(digits, 2).ok()
+    } else {
+        value.parse::<i64>().ok().map(|v| v as u64)
+    }
+}
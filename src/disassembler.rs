@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use once_cell::sync::OnceCell;
+
+use crate::assembler::{init_tables, COMP_TABLE, DEST_TABLE, JMP_TABLE, SHIFT_TABLE};
+
+// The assembler tables the other way round: bits -> mnemonic
+static COMP_NAMES: OnceCell<HashMap<&str, &str>> = OnceCell::new();
+static SHIFT_NAMES: OnceCell<HashMap<&str, &str>> = OnceCell::new();
+static DEST_NAMES: OnceCell<HashMap<&str, &str>> = OnceCell::new();
+static JMP_NAMES: OnceCell<HashMap<&str, &str>> = OnceCell::new();
+
+fn reverse(
+    table: &OnceCell<HashMap<&'static str, &'static str>>,
+) -> HashMap<&'static str, &'static str> {
+    table
+        .get()
+        .unwrap()
+        .iter()
+        .map(|(name, bits)| (*bits, *name))
+        .collect()
+}
+
+/// The Hack assembly of a machine code word, with the shift instructions of
+/// the extended instruction set when `extended`. Words that no instruction
+/// encodes are written as a comment.
+pub fn disassemble(word: u16, extended: bool) -> String {
+    init_tables();
+    let comp_names = COMP_NAMES.get_or_init(|| reverse(&COMP_TABLE));
+    let shift_names = SHIFT_NAMES.get_or_init(|| reverse(&SHIFT_TABLE));
+    let dest_names = DEST_NAMES.get_or_init(|| reverse(&DEST_TABLE));
+    let jmp_names = JMP_NAMES.get_or_init(|| reverse(&JMP_TABLE));
+
+    if word & 0x8000 == 0 {
+        return format!("@{}", word);
+    }
+
+    let bits = format!("{:016b}", word);
+    let comp = match &bits[..3] {
+        "111" => comp_names.get(&bits[3..10]),
+        "101" if extended => shift_names.get(&bits[3..10]),
+        _ => None,
+    };
+    let Some(comp) = comp else {
+        return format!("// unknown: {}", bits);
+    };
+
+    let mut command = String::new();
+    match dest_names[&bits[10..13]] {
+        "null" => {}
+        dest => command.push_str(&format!("{}=", dest)),
+    }
+    command.push_str(comp);
+    match jmp_names[&bits[13..]] {
+        "null" => {}
+        jmp => command.push_str(&format!(";{}", jmp)),
+    }
+    command
+}
+
+/// The Hack assembly of a program, one instruction per line
+pub fn disassemble_rom(rom: &[u16], extended: bool) -> Vec<u8> {
+    let mut output = vec![];
+    for word in rom.iter() {
+        writeln!(&mut output, "{}", disassemble(*word, extended)).unwrap();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn assemble(source: &str, extended: bool) -> Vec<u16> {
+        let mut assembler = Assembler::from_source(source);
+        assembler.set_extended(extended);
+        assembler.run().unwrap();
+        assembler.binary()
+    }
+
+    #[test]
+    fn round_trip_every_instruction() {
+        init_tables();
+        for (table, extended) in [(&COMP_TABLE, false), (&SHIFT_TABLE, true)] {
+            let mut source = String::new();
+            for comp in table.get().unwrap().keys() {
+                for dest in DEST_TABLE.get().unwrap().keys() {
+                    for jmp in JMP_TABLE.get().unwrap().keys() {
+                        match (*dest, *jmp) {
+                            ("null", "null") => source.push_str(comp),
+                            ("null", jmp) => source.push_str(&format!("{};{}", comp, jmp)),
+                            (dest, "null") => source.push_str(&format!("{}={}", dest, comp)),
+                            (dest, jmp) => source.push_str(&format!("{}={};{}", dest, comp, jmp)),
+                        }
+                        source.push('\n');
+                    }
+                }
+            }
+
+            let rom = assemble(&source, extended);
+            let disassembled = String::from_utf8(disassemble_rom(&rom, extended)).unwrap();
+            assert_eq!(disassembled, source);
+            assert_eq!(assemble(&disassembled, extended), rom);
+        }
+    }
+
+    #[test]
+    fn shifts_only_when_extended() {
+        let rom = assemble("D=D<<\nAM=M>>;JLT\n", true);
+        assert_eq!(rom, [0b1010110000010000, 0b1011000000101100]);
+        assert_eq!(disassemble(rom[1], true), "AM=M>>;JLT");
+        assert_eq!(disassemble(rom[1], false), "// unknown: 1011000000101100");
+        assert_eq!(disassemble(0b1110000000000000, true), "D&A");
+        assert_eq!(
+            disassemble(0b1011111110000000, true),
+            "// unknown: 1011111110000000"
+        );
+    }
+}
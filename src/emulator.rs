@@ -0,0 +1,245 @@
+use std::fs::read;
+use std::path::PathBuf;
+
+use crate::hack_format::{self, HackFormat};
+
+pub const RAM_SIZE: usize = 32768;
+
+/// Executes Hack machine code one instruction per cycle.
+pub struct Emulator {
+    rom:        Vec<u16>,
+    pub ram:    Vec<u16>,
+    pub a:      u16,
+    pub d:      u16,
+    pub pc:     u16,
+    pub cycles: u64,
+    halted:     bool,
+    // execute the shift instructions
+    extended:   bool,
+}
+
+impl Emulator {
+    pub fn new(rom: Vec<u16>) -> Self {
+        Self {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            halted: false,
+            extended: false,
+        }
+    }
+
+    /// Execute the `101a cccc ccdd djjj` shift instructions of the extended
+    /// instruction set, which are C instructions otherwise
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    /// Read the machine code of a file in `format`, or in the format guessed
+    /// from its extension and content
+    pub fn load_rom(path: PathBuf, format: Option<HackFormat>) -> Vec<u16> {
+        let content = read(&path).unwrap();
+        let format = format.unwrap_or_else(|| {
+            let extension = path.extension().and_then(|v| v.to_str()).unwrap_or("");
+            HackFormat::detect(extension, &content)
+        });
+        hack_format::decode(&content, format)
+    }
+
+    /// The program has halted when it runs past the end of the ROM or spins in
+    /// the `(END) @END 0;JMP` loop that ends a Hack program.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Run until the program halts or `max_cycles` instructions have been
+    /// executed in total.
+    pub fn run(&mut self, max_cycles: u64) {
+        while !self.halted && self.cycles < max_cycles {
+            self.step();
+        }
+    }
+
+    pub fn step(&mut self) {
+        let pc = self.pc as usize;
+        if pc >= self.rom.len() {
+            self.halted = true;
+            return;
+        }
+
+        let instruction = self.rom[pc];
+        self.cycles += 1;
+
+        // A instruction: @value
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc += 1;
+            return;
+        }
+
+        // C instruction: 111a cccc ccdd djjj
+        let address = self.a as usize;
+        let y = if instruction & 0x1000 != 0 {
+            self.ram[address % RAM_SIZE]
+        } else {
+            self.a
+        };
+        let out = if self.extended && instruction & 0xe000 == 0xa000 {
+            shift(self.d, y, (instruction >> 6) & 0x3f)
+        } else {
+            alu(self.d, y, (instruction >> 6) & 0x3f)
+        };
+
+        if instruction & 0x08 != 0 && address < RAM_SIZE {
+            self.ram[address] = out;
+        }
+        if instruction & 0x20 != 0 {
+            self.a = out;
+        }
+        if instruction & 0x10 != 0 {
+            self.d = out;
+        }
+
+        let out = out as i16;
+        let jump = match instruction & 0x07 {
+            0 => false,
+            1 => out > 0,
+            2 => out == 0,
+            3 => out >= 0,
+            4 => out < 0,
+            5 => out != 0,
+            6 => out <= 0,
+            _ => true,
+        };
+
+        if jump {
+            // `@END` followed by `0;JMP` jumping back to that `@END`
+            let target = address as u16;
+            if target + 1 == self.pc && self.rom[target as usize] == target {
+                self.halted = true;
+            }
+            self.pc = target;
+        } else {
+            self.pc += 1;
+        }
+    }
+}
+
+/// The shifter of the extended instruction set, `control` being the six bits
+/// c1..c6 of which c1 shifts left and c2 shifts `x` rather than `y`
+fn shift(x: u16, y: u16, control: u16) -> u16 {
+    let operand = if control & 0x10 != 0 { x } else { y };
+    if control & 0x20 != 0 {
+        operand << 1
+    } else {
+        ((operand as i16) >> 1) as u16
+    }
+}
+
+/// The Hack ALU, `control` being the six bits zx nx zy ny f no
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let mut x = x;
+    let mut y = y;
+
+    if control & 0x20 != 0 {
+        x = 0;
+    }
+    if control & 0x10 != 0 {
+        x = !x;
+    }
+    if control & 0x08 != 0 {
+        y = 0;
+    }
+    if control & 0x04 != 0 {
+        y = !y;
+    }
+    let mut out = if control & 0x02 != 0 {
+        x.wrapping_add(y)
+    } else {
+        x & y
+    };
+    if control & 0x01 != 0 {
+        out = !out;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn emulator(source: &str) -> Emulator {
+        let mut assembler = Assembler::from_source(source);
+        assembler.run().unwrap();
+        Emulator::new(assembler.binary())
+    }
+
+    #[test]
+    fn halt_on_end_loop() {
+        let mut emulator = emulator("@2\nD=A\n@R0\nM=D\n(END)\n@END\n0;JMP\n");
+        emulator.run(1000);
+        assert!(emulator.is_halted());
+        assert_eq!(emulator.ram[0], 2);
+        // the jump back to `@END` is the last instruction executed
+        assert_eq!(emulator.cycles, 6);
+        assert_eq!(emulator.pc, 4);
+    }
+
+    #[test]
+    fn halt_past_rom() {
+        let mut emulator = emulator("@7\nD=A\n");
+        emulator.run(1000);
+        assert!(emulator.is_halted());
+        assert_eq!((emulator.d, emulator.cycles), (7, 2));
+    }
+
+    #[test]
+    fn loop_is_not_halt() {
+        // a jump to another instruction than the `@LOOP` before it
+        let mut emulator = emulator("(LOOP)\n@R0\nM=M+1\n@LOOP\n0;JMP\n");
+        emulator.run(100);
+        assert!(!emulator.is_halted());
+        assert_eq!(emulator.cycles, 100);
+        assert_eq!(emulator.ram[0], 25);
+    }
+
+    #[test]
+    fn count_cycles_of_conditional_jumps() {
+        // R1 = 3 + 2 + 1 by a loop of 7 instructions run three times
+        let mut emulator = emulator(
+            "@3\nD=A\n@R0\nM=D\n\
+             (LOOP)\n@R0\nD=M\n@R1\nM=D+M\n@R0\nMD=M-1\n@LOOP\nD;JGT\n\
+             (END)\n@END\n0;JMP\n",
+        );
+        emulator.run(1000);
+        assert!(emulator.is_halted());
+        assert_eq!(emulator.ram[1], 6);
+        assert_eq!(emulator.cycles, 4 + 3 * 8 + 2);
+    }
+
+    #[test]
+    fn map_screen_and_keyboard() {
+        let mut emulator = emulator("@KBD\nD=M\n@SCREEN\nM=D\n@24575\nM=-1\n");
+        emulator.ram[24576] = 65;
+        emulator.run(1000);
+        assert_eq!(emulator.ram[16384], 65);
+        // the last word of the screen
+        assert_eq!(emulator.ram[16384 + 8191], 0xffff);
+    }
+
+    #[test]
+    fn shift_when_extended() {
+        let source = "@5\nD=A\nD=D<<\n@R0\nM=D\nM=M>>\nM=M>>\n";
+        let mut assembler = Assembler::from_source(source);
+        assembler.set_extended(true);
+        assembler.run().unwrap();
+        let mut emulator = Emulator::new(assembler.binary());
+        emulator.set_extended(true);
+        emulator.run(1000);
+        assert_eq!((emulator.d, emulator.ram[0]), (10, 2));
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write;
+
+/// File formats of Hack machine code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HackFormat {
+    /// One instruction per line written in binary, the *.hack format of the
+    /// course
+    Text,
+    /// Big-endian 16-bit words
+    Raw,
+    /// Intel HEX records of 16 bytes, with big-endian words at byte addresses
+    IntelHex,
+    /// Logisim `v2.0 raw` memory image, one hexadecimal word per value
+    Logisim,
+}
+
+const LOGISIM_HEADER: &str = "v2.0 raw";
+
+impl HackFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(HackFormat::Text),
+            "raw" => Some(HackFormat::Raw),
+            "hex" => Some(HackFormat::IntelHex),
+            "logisim" => Some(HackFormat::Logisim),
+            _ => None,
+        }
+    }
+
+    /// Extension of the files written in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            HackFormat::Text => "hack",
+            HackFormat::Raw => "bin",
+            HackFormat::IntelHex => "hex",
+            HackFormat::Logisim => "rom",
+        }
+    }
+
+    /// Guess the format of a file from its extension, or from its content for
+    /// a Logisim image, which has no usual extension
+    pub fn detect(extension: &str, content: &[u8]) -> Self {
+        if content.starts_with(LOGISIM_HEADER.as_bytes()) {
+            return HackFormat::Logisim;
+        }
+        match extension {
+            "bin" => HackFormat::Raw,
+            "hex" => HackFormat::IntelHex,
+            _ => HackFormat::Text,
+        }
+    }
+}
+
+pub fn encode(words: &[u16], format: HackFormat) -> Vec<u8> {
+    match format {
+        HackFormat::Text => words
+            .iter()
+            .map(|word| format!("{:016b}\n", word))
+            .collect::<String>()
+            .into_bytes(),
+        HackFormat::Raw => words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+        HackFormat::IntelHex => encode_intel_hex(words).into_bytes(),
+        HackFormat::Logisim => encode_logisim(words).into_bytes(),
+    }
+}
+
+pub fn decode(content: &[u8], format: HackFormat) -> Vec<u16> {
+    match format {
+        HackFormat::Text => String::from_utf8_lossy(content)
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                u16::from_str_radix(line, 2).unwrap_or_else(|_| panic!("invalid: {}", line))
+            })
+            .collect(),
+        HackFormat::Raw => {
+            if !content.len().is_multiple_of(2) {
+                panic!("invalid: odd number of bytes: {}", content.len());
+            }
+            content
+                .chunks(2)
+                .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                .collect()
+        }
+        HackFormat::IntelHex => decode_intel_hex(&String::from_utf8_lossy(content)),
+        HackFormat::Logisim => decode_logisim(&String::from_utf8_lossy(content)),
+    }
+}
+
+// The 32K words of the ROM fit in the 64K byte addresses of data records, so
+// no extended address record is needed
+fn encode_intel_hex(words: &[u16]) -> String {
+    let mut output = String::new();
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+
+    for (i, data) in bytes.chunks(16).enumerate() {
+        let address = (i * 16) as u16;
+        let mut record = vec![data.len() as u8];
+        record.extend(address.to_be_bytes());
+        record.push(0x00);
+        record.extend(data);
+        write_record(&mut output, &record);
+    }
+    write_record(&mut output, &[0x00, 0x00, 0x00, 0x01]);
+
+    output
+}
+
+// Write `:`, the bytes of a record in hexadecimal and its checksum
+fn write_record(output: &mut String, record: &[u8]) {
+    let sum = record.iter().fold(0u8, |sum, v| sum.wrapping_add(*v));
+    output.push(':');
+    for byte in record.iter().chain([sum.wrapping_neg()].iter()) {
+        write!(output, "{:02X}", byte).unwrap();
+    }
+    output.push('\n');
+}
+
+fn decode_intel_hex(content: &str) -> Vec<u16> {
+    let mut bytes: Vec<u8> = vec![];
+    // base address set by extended segment and linear address records
+    let mut base = 0usize;
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let line_no = i + 1;
+        if line.is_empty() {
+            continue;
+        }
+
+        let record: Vec<u8> = line
+            .strip_prefix(':')
+            .filter(|v| v.len() % 2 == 0)
+            .and_then(|v| {
+                (0..v.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&v[i..i + 2], 16).ok())
+                    .collect()
+            })
+            .unwrap_or_else(|| panic!("invalid: {}, line: {}", line, line_no));
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            panic!("invalid: {}, line: {}", line, line_no);
+        }
+        if record.iter().fold(0u8, |sum, v| sum.wrapping_add(*v)) != 0 {
+            panic!("invalid checksum: {}, line: {}", line, line_no);
+        }
+
+        let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => {
+                let start = base + address;
+                if bytes.len() < start + data.len() {
+                    bytes.resize(start + data.len(), 0);
+                }
+                bytes[start..start + data.len()].copy_from_slice(data);
+            }
+            0x01 => break,
+            0x02 if data.len() == 2 => base = u16::from_be_bytes([data[0], data[1]]) as usize * 16,
+            0x04 if data.len() == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16
+            }
+            // start address records do not matter for a ROM
+            0x03 | 0x05 => {}
+            _ => panic!("invalid: {}, line: {}", line, line_no),
+        }
+    }
+
+    if !bytes.len().is_multiple_of(2) {
+        bytes.push(0);
+    }
+    decode(&bytes, HackFormat::Raw)
+}
+
+// Runs of the same word are written as `count*word` like Logisim does
+fn encode_logisim(words: &[u16]) -> String {
+    let mut values = vec![];
+    let mut i = 0;
+    while i < words.len() {
+        let run = words[i..].iter().take_while(|v| **v == words[i]).count();
+        if run > 1 {
+            values.push(format!("{}*{:x}", run, words[i]));
+        } else {
+            values.push(format!("{:x}", words[i]));
+        }
+        i += run;
+    }
+
+    let mut output = format!("{}\n", LOGISIM_HEADER);
+    for line in values.chunks(8) {
+        output.push_str(&line.join(" "));
+        output.push('\n');
+    }
+    output
+}
+
+fn decode_logisim(content: &str) -> Vec<u16> {
+    let mut lines = content.lines();
+    if lines.next().map(|v| v.trim()) != Some(LOGISIM_HEADER) {
+        panic!(
+            "invalid: Logisim image without a `{}` header",
+            LOGISIM_HEADER
+        );
+    }
+
+    let mut words = vec![];
+    for line in lines {
+        // comments run to the end of the line
+        let line = line.split('#').next().unwrap();
+        for value in line.split_whitespace() {
+            let (count, word) = match value.split_once('*') {
+                Some((count, word)) => (count.parse::<usize>().ok(), word),
+                None => (Some(1), value),
+            };
+            match (count, u16::from_str_radix(word, 16).ok()) {
+                (Some(count), Some(word)) => words.extend(std::iter::repeat_n(word, count)),
+                _ => panic!("invalid: {}", value),
+            }
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a run for Logisim and more than 16 bytes for Intel HEX
+    const WORDS: [u16; 11] = [
+        0x0002, 0xec10, 0x0003, 0xe090, 0x0000, 0xe308, 0, 0, 0, 0xffff, 0x7fff,
+    ];
+
+    fn round_trip(format: HackFormat) {
+        assert_eq!(decode(&encode(&WORDS, format), format), WORDS);
+        assert_eq!(decode(&encode(&[], format), format), []);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        round_trip(HackFormat::Text);
+        assert!(
+            encode(&WORDS, HackFormat::Text).starts_with(b"0000000000000010\n1110110000010000\n")
+        );
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        round_trip(HackFormat::Raw);
+        assert_eq!(encode(&[0x1234], HackFormat::Raw), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn intel_hex_round_trip() {
+        round_trip(HackFormat::IntelHex);
+        let hex = String::from_utf8(encode(&WORDS, HackFormat::IntelHex)).unwrap();
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with(":06001000"));
+        assert_eq!(lines[2], ":00000001FF");
+    }
+
+    #[test]
+    fn intel_hex_extended_address() {
+        // an extended segment address record of 0x0001 moves the data to 0x10
+        let hex = ":020000020001FB\n:020000001234B8\n:00000001FF\n";
+        let words = decode(hex.as_bytes(), HackFormat::IntelHex);
+        assert_eq!(words.len(), 9);
+        assert_eq!(words[8], 0x1234);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid checksum")]
+    fn intel_hex_checksum() {
+        decode(b":020000001234B9\n", HackFormat::IntelHex);
+    }
+
+    #[test]
+    fn logisim_round_trip() {
+        round_trip(HackFormat::Logisim);
+        let image = String::from_utf8(encode(&WORDS, HackFormat::Logisim)).unwrap();
+        assert_eq!(image, "v2.0 raw\n2 ec10 3 e090 0 e308 3*0 ffff\n7fff\n");
+        let words = decode(b"v2.0 raw\n2*a b # comment\n", HackFormat::Logisim);
+        assert_eq!(words, [0xa, 0xa, 0xb]);
+    }
+
+    #[test]
+    fn detect() {
+        let logisim = encode(&WORDS, HackFormat::Logisim);
+        assert_eq!(HackFormat::detect("hack", &logisim), HackFormat::Logisim);
+        assert_eq!(HackFormat::detect("", &logisim), HackFormat::Logisim);
+        assert_eq!(HackFormat::detect("bin", &[0x00, 0x02]), HackFormat::Raw);
+        assert_eq!(
+            HackFormat::detect("hex", b":00000001FF\n"),
+            HackFormat::IntelHex
+        );
+        assert_eq!(
+            HackFormat::detect("hack", b"0000000000000010\n"),
+            HackFormat::Text
+        );
+        assert_eq!(
+            HackFormat::detect("rom", b"0000000000000010\n"),
+            HackFormat::Text
+        );
+    }
+}
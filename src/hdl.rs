@@ -0,0 +1,9 @@
+// The hardware projects of the course: chips described in the HDL of the
+// book, simulated down to Nand gates.
+
+pub mod builtin;
+pub mod parser;
+pub mod simulator;
+pub mod stats;
+pub mod test_script;
+pub mod verilog;
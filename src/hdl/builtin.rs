@@ -0,0 +1,298 @@
+use crate::hdl::parser::{parse, Chip};
+
+// The declarations of the chips built into the simulator, as in the
+// `builtInChips` directory of the course
+const DECLARATIONS: [&str; 16] = [
+    "CHIP Nand { IN a, b; OUT out; BUILTIN Nand; }",
+    "CHIP DFF { IN in; OUT out; BUILTIN DFF; CLOCKED in; }",
+    "CHIP Bit { IN in, load; OUT out; BUILTIN Bit; CLOCKED in, load; }",
+    "CHIP Register { IN in[16], load; OUT out[16]; BUILTIN Register; CLOCKED in, load; }",
+    "CHIP ARegister { IN in[16], load; OUT out[16]; BUILTIN ARegister; CLOCKED in, load; }",
+    "CHIP DRegister { IN in[16], load; OUT out[16]; BUILTIN DRegister; CLOCKED in, load; }",
+    "CHIP RAM8 { IN in[16], load, address[3]; OUT out[16]; BUILTIN RAM8; CLOCKED in, load; }",
+    "CHIP RAM64 { IN in[16], load, address[6]; OUT out[16]; BUILTIN RAM64; CLOCKED in, load; }",
+    "CHIP RAM512 { IN in[16], load, address[9]; OUT out[16]; BUILTIN RAM512; CLOCKED in, load; }",
+    "CHIP RAM4K { IN in[16], load, address[12]; OUT out[16]; BUILTIN RAM4K; CLOCKED in, load; }",
+    "CHIP RAM16K { IN in[16], load, address[14]; OUT out[16]; BUILTIN RAM16K; CLOCKED in, load; }",
+    "CHIP PC { IN in[16], load, inc, reset; OUT out[16]; BUILTIN PC; CLOCKED in, load, inc, reset; }",
+    "CHIP ROM32K { IN address[15]; OUT out[16]; BUILTIN ROM32K; }",
+    "CHIP Screen { IN in[16], load, address[13]; OUT out[16]; BUILTIN Screen; CLOCKED in, load; }",
+    "CHIP Keyboard { OUT out[16]; BUILTIN Keyboard; }",
+    "CHIP Memory { IN in[16], load, address[15]; OUT out[16]; BUILTIN Memory; CLOCKED in, load; }",
+];
+
+/// The declaration of a chip built into the simulator
+pub fn declaration(name: &str) -> Option<Chip> {
+    let prefix = format!("CHIP {} ", name);
+    DECLARATIONS
+        .iter()
+        .find(|source| source.starts_with(&prefix))
+        .map(|source| parse(source, &format!("{}.hdl", name)))
+}
+
+/// The state of a built-in chip other than Nand, whose single output `out`
+/// depends on the state and the inputs that are not clocked.
+///
+/// Inputs are the values of the pins in the order of the declaration.
+pub enum Builtin {
+    /// DFF, Bit, Register, ARegister and DRegister. A DFF has no `load` and
+    /// always stores its input.
+    Register {
+        value: u64,
+        next:  u64,
+    },
+    Pc {
+        value: u64,
+        next:  u64,
+    },
+    /// RAM8 up to RAM16K and Screen, the words written on the clock when
+    /// `load`. Memory maps the Screen at 16384 and the Keyboard at 24576 after
+    /// the 16K words of RAM.
+    Ram {
+        words:  Vec<u16>,
+        write:  Option<(usize, u16)>,
+        memory: bool,
+    },
+    Rom {
+        words: Vec<u16>,
+    },
+    Keyboard {
+        key: u16,
+    },
+}
+
+// address of the keyboard in Memory
+const KEYBOARD: usize = 24576;
+
+impl Builtin {
+    pub fn new(name: &str) -> Option<Self> {
+        let ram = |size: usize, memory: bool| Builtin::Ram {
+            words: vec![0; size],
+            write: None,
+            memory,
+        };
+        Some(match name {
+            "DFF" | "Bit" | "Register" | "ARegister" | "DRegister" => {
+                Builtin::Register { value: 0, next: 0 }
+            }
+            "PC" => Builtin::Pc { value: 0, next: 0 },
+            "RAM8" => ram(8, false),
+            "RAM64" => ram(64, false),
+            "RAM512" => ram(512, false),
+            "RAM4K" => ram(4096, false),
+            "RAM16K" => ram(16384, false),
+            "Screen" => ram(8192, false),
+            "Memory" => ram(KEYBOARD + 1, true),
+            "ROM32K" => Builtin::Rom {
+                words: vec![0; 32768],
+            },
+            "Keyboard" => Builtin::Keyboard { key: 0 },
+            _ => return None,
+        })
+    }
+
+    pub fn eval(&self, inputs: &[u64]) -> u64 {
+        match self {
+            Builtin::Register { value, .. } | Builtin::Pc { value, .. } => *value,
+            Builtin::Ram { words, memory, .. } => {
+                let address = inputs[2] as usize;
+                if *memory && address > KEYBOARD {
+                    0
+                } else {
+                    words[address] as u64
+                }
+            }
+            Builtin::Rom { words } => words[inputs[0] as usize] as u64,
+            Builtin::Keyboard { key } => *key as u64,
+        }
+    }
+
+    /// The rising edge of the clock: latch the inputs
+    pub fn tick(&mut self, inputs: &[u64]) {
+        match self {
+            Builtin::Register { value, next } => {
+                let load = inputs.get(1).is_none_or(|load| *load != 0);
+                *next = if load { inputs[0] } else { *value };
+            }
+            Builtin::Pc { value, next } => {
+                *next = match inputs[1..] {
+                    [_, _, reset] if reset != 0 => 0,
+                    [load, _, _] if load != 0 => inputs[0],
+                    [_, inc, _] if inc != 0 => (*value + 1) & 0xffff,
+                    _ => *value,
+                };
+            }
+            Builtin::Ram {
+                words,
+                write,
+                memory,
+            } => {
+                let address = inputs[2] as usize;
+                // the keyboard and the addresses after it are read-only
+                let writable = !*memory || address < KEYBOARD;
+                *write = (inputs[1] != 0 && writable && address < words.len())
+                    .then_some((address, inputs[0] as u16));
+            }
+            Builtin::Rom { .. } | Builtin::Keyboard { .. } => {}
+        }
+    }
+
+    /// The falling edge of the clock: update the state
+    pub fn tock(&mut self) {
+        match self {
+            Builtin::Register { value, next } | Builtin::Pc { value, next } => *value = *next,
+            Builtin::Ram { words, write, .. } => {
+                if let Some((address, word)) = write.take() {
+                    words[address] = word;
+                }
+            }
+            Builtin::Rom { .. } | Builtin::Keyboard { .. } => {}
+        }
+    }
+
+    /// The register or the word at `address` of a memory, None when out of
+    /// range
+    pub fn peek(&self, address: usize) -> Option<u64> {
+        match self {
+            Builtin::Register { value, .. } | Builtin::Pc { value, .. } => Some(*value),
+            Builtin::Ram { words, .. } | Builtin::Rom { words } => {
+                words.get(address).map(|word| *word as u64)
+            }
+            Builtin::Keyboard { key } => Some(*key as u64),
+        }
+    }
+
+    /// Overwrite the register or the word at `address` of a memory, returning
+    /// false when out of range
+    pub fn poke(&mut self, address: usize, word: u64) -> bool {
+        match self {
+            Builtin::Register { value, next } | Builtin::Pc { value, next } => {
+                *value = word;
+                *next = word;
+            }
+            Builtin::Ram { words, .. } | Builtin::Rom { words } => match words.get_mut(address) {
+                Some(v) => *v = word as u16,
+                None => return false,
+            },
+            Builtin::Keyboard { key } => *key = word as u16,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::hdl::simulator::{ChipLibrary, Simulator};
+
+    // A built-in chip simulated alone
+    fn simulator(name: &str) -> Simulator {
+        let chip = Rc::new(declaration(name).unwrap());
+        Simulator::new(chip, &mut ChipLibrary::new(vec![]))
+    }
+
+    // `set` the inputs, then a whole clock cycle
+    fn clock(simulator: &mut Simulator, inputs: &[(&str, u64)]) -> u64 {
+        for (pin, value) in inputs.iter() {
+            simulator.set(pin, *value);
+        }
+        simulator.tick();
+        simulator.tock();
+        simulator.get("out")
+    }
+
+    #[test]
+    fn dff_outputs_its_input_after_the_clock() {
+        let mut dff = simulator("DFF");
+        dff.set("in", 1);
+        dff.eval();
+        assert_eq!(dff.get("out"), 0);
+        dff.tick();
+        assert_eq!(dff.get("out"), 0);
+        dff.tock();
+        assert_eq!(dff.get("out"), 1);
+        assert_eq!(clock(&mut dff, &[("in", 0)]), 0);
+    }
+
+    #[test]
+    fn registers_store_on_load() {
+        let mut bit = simulator("Bit");
+        assert_eq!(clock(&mut bit, &[("in", 1), ("load", 0)]), 0);
+        assert_eq!(clock(&mut bit, &[("in", 1), ("load", 1)]), 1);
+        assert_eq!(clock(&mut bit, &[("in", 0), ("load", 0)]), 1);
+
+        for name in ["Register", "ARegister", "DRegister"] {
+            let mut register = simulator(name);
+            assert_eq!(clock(&mut register, &[("in", 0xbeef), ("load", 1)]), 0xbeef);
+            assert_eq!(clock(&mut register, &[("in", 7), ("load", 0)]), 0xbeef);
+            assert_eq!(register.peek(name, 0), 0xbeef);
+        }
+    }
+
+    #[test]
+    fn rams_write_on_load_and_read_any_time() {
+        for (name, size) in [
+            ("RAM8", 8),
+            ("RAM64", 64),
+            ("RAM512", 512),
+            ("RAM4K", 4096),
+            ("RAM16K", 16384),
+            ("Screen", 8192),
+        ] {
+            let mut ram = simulator(name);
+            let last = size - 1;
+            assert_eq!(
+                clock(&mut ram, &[("in", 1234), ("load", 1), ("address", last)]),
+                1234
+            );
+            assert_eq!(
+                clock(&mut ram, &[("in", 99), ("load", 0), ("address", 1)]),
+                0
+            );
+            assert_eq!(
+                clock(&mut ram, &[("in", 5), ("load", 1), ("address", 1)]),
+                5
+            );
+
+            // the output follows the address without the clock
+            ram.set("address", last);
+            ram.eval();
+            assert_eq!(ram.get("out"), 1234);
+            assert_eq!(ram.peek(name, last as usize), 1234);
+            assert_eq!(ram.peek(name, 0), 0);
+        }
+    }
+
+    #[test]
+    fn memory_maps_the_screen_and_the_keyboard() {
+        let mut memory = simulator("Memory");
+        assert_eq!(
+            clock(&mut memory, &[("in", 3), ("load", 1), ("address", 16384)]),
+            3
+        );
+        memory.poke("Memory", KEYBOARD, 75);
+        // the keyboard is read-only, and there is nothing after it
+        assert_eq!(
+            clock(&mut memory, &[("in", 1), ("address", KEYBOARD as u64)]),
+            75
+        );
+        assert_eq!(clock(&mut memory, &[("in", 1), ("address", 24577)]), 0);
+        assert_eq!(memory.peek("Memory", KEYBOARD), 75);
+    }
+
+    #[test]
+    fn pc_resets_loads_or_increments() {
+        let mut pc = simulator("PC");
+        assert_eq!(clock(&mut pc, &[("inc", 1)]), 1);
+        assert_eq!(clock(&mut pc, &[]), 2);
+        assert_eq!(clock(&mut pc, &[("in", 0xfffe), ("load", 1)]), 0xfffe);
+        assert_eq!(clock(&mut pc, &[("load", 0)]), 0xffff);
+        assert_eq!(clock(&mut pc, &[]), 0);
+        // reset takes precedence over load, which takes precedence over inc
+        assert_eq!(clock(&mut pc, &[("in", 9), ("load", 1), ("reset", 1)]), 0);
+        assert_eq!(clock(&mut pc, &[("reset", 0)]), 9);
+        assert_eq!(clock(&mut pc, &[("load", 0), ("inc", 0)]), 9);
+    }
+}
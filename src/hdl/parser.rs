@@ -0,0 +1,467 @@
+use std::fmt;
+
+/// A chip declared in a *.hdl file
+pub struct Chip {
+    pub name:    String,
+    pub inputs:  Vec<Pin>,
+    pub outputs: Vec<Pin>,
+    pub body:    Body,
+}
+
+/// An input or output pin, a bus when `width` is more than 1
+#[derive(Clone)]
+pub struct Pin {
+    pub name:  String,
+    pub width: usize,
+}
+
+pub enum Body {
+    /// The parts the chip is made of
+    Parts(Vec<Part>),
+    /// A chip implemented in Rust, with the input pins that only matter on
+    /// the clock
+    Builtin {
+        name:    String,
+        clocked: Vec<String>,
+    },
+}
+
+/// A chip used as a part, like `Nand(a=x, b=y, out=z)`
+pub struct Part {
+    pub name:        String,
+    pub connections: Vec<Connection>,
+    pub line:        usize,
+}
+
+/// `pin=value`, a pin of the part connected to a pin of the chip, an
+/// internal pin or a constant
+pub struct Connection {
+    pub pin:   PinRef,
+    pub value: PinRef,
+}
+
+/// A pin or a sub-bus like `a[3]` or `a[0..7]`
+#[derive(Clone)]
+pub struct PinRef {
+    pub name:  String,
+    /// The first and last bit, both included
+    pub range: Option<(usize, usize)>,
+}
+
+impl PinRef {
+    /// Number of bits of the sub-bus, or of the whole pin of `width` bits
+    pub fn width(&self, width: usize) -> usize {
+        match self.range {
+            Some((first, last)) => last - first + 1,
+            None => width,
+        }
+    }
+}
+
+impl fmt::Display for PinRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.range {
+            Some((first, last)) if first == last => write!(f, "{}[{}]", self.name, first),
+            Some((first, last)) => write!(f, "{}[{}..{}]", self.name, first, last),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl Chip {
+    pub fn pin(&self, name: &str) -> Option<&Pin> {
+        self.inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .find(|pin| pin.name == name)
+    }
+
+    pub fn is_input(&self, name: &str) -> bool {
+        self.inputs.iter().any(|pin| pin.name == name)
+    }
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(usize),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Identifier(v) => write!(f, "{}", v),
+            Token::Number(v) => write!(f, "{}", v),
+            Token::Symbol(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 11] = ["..", "{", "}", "(", ")", "[", "]", ",", ";", "=", ":"];
+
+/// Parse the source of a *.hdl file. `file` names the file in the errors.
+pub fn parse(source: &str, file: &str) -> Chip {
+    let mut parser = Parser {
+        tokens:   tokenize(source, file),
+        position: 0,
+        file:     file.to_owned(),
+    };
+    parser.chip()
+}
+
+// (line number, token) of a source without its comments
+fn tokenize(source: &str, file: &str) -> Vec<(usize, Token)> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if rest == "//" {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if rest == "/*" {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((line, Token::Identifier(chars[start..i].iter().collect())));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            tokens.push((line, Token::Number(number.parse().unwrap())));
+        } else if let Some(symbol) = SYMBOLS.iter().find(|v| rest.starts_with(*v)) {
+            tokens.push((line, Token::Symbol(symbol)));
+            i += symbol.len();
+        } else {
+            panic!("invalid: {}, file: {}, line: {}", c, file, line);
+        }
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens:   Vec<(usize, Token)>,
+    position: usize,
+    file:     String,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position) {
+            Some((line, _)) => *line,
+            None => self.tokens.last().map_or(1, |(line, _)| *line),
+        }
+    }
+
+    fn error(&self, expected: &str) -> ! {
+        let found = match self.tokens.get(self.position) {
+            Some((_, token)) => token.to_string(),
+            None => "end of file".to_owned(),
+        };
+        panic!(
+            "invalid: expected {}, found {}, file: {}, line: {}",
+            expected,
+            found,
+            self.file,
+            self.line()
+        );
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some((_, Token::Symbol(v))) if *v == symbol)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some((_, Token::Identifier(v))) if v == keyword)
+    }
+
+    fn symbol(&mut self, symbol: &str) {
+        if !self.peek_symbol(symbol) {
+            self.error(symbol);
+        }
+        self.position += 1;
+    }
+
+    fn keyword(&mut self, keyword: &str) {
+        if !self.peek_keyword(keyword) {
+            self.error(keyword);
+        }
+        self.position += 1;
+    }
+
+    fn identifier(&mut self) -> String {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Identifier(v))) => {
+                self.position += 1;
+                v.clone()
+            }
+            _ => self.error("a name"),
+        }
+    }
+
+    fn number(&mut self) -> usize {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Number(v))) => {
+                self.position += 1;
+                *v
+            }
+            _ => self.error("a number"),
+        }
+    }
+
+    // CHIP name { IN pins; OUT pins; PARTS: parts | BUILTIN name; CLOCKED pins; }
+    fn chip(&mut self) -> Chip {
+        self.keyword("CHIP");
+        let name = self.identifier();
+        self.symbol("{");
+
+        let mut inputs = vec![];
+        if self.peek_keyword("IN") {
+            self.position += 1;
+            inputs = self.pin_declarations();
+        }
+        let mut outputs = vec![];
+        if self.peek_keyword("OUT") {
+            self.position += 1;
+            outputs = self.pin_declarations();
+        }
+
+        let body = if self.peek_keyword("BUILTIN") {
+            self.position += 1;
+            let builtin = self.identifier();
+            self.symbol(";");
+            let mut clocked = vec![];
+            if self.peek_keyword("CLOCKED") {
+                self.position += 1;
+                clocked = self.pin_list();
+            }
+            Body::Builtin {
+                name: builtin,
+                clocked,
+            }
+        } else {
+            self.keyword("PARTS");
+            self.symbol(":");
+            let mut parts = vec![];
+            while !self.peek_symbol("}") {
+                parts.push(self.part());
+            }
+            Body::Parts(parts)
+        };
+
+        self.symbol("}");
+        if self.position < self.tokens.len() {
+            self.error("end of file");
+        }
+
+        Chip {
+            name,
+            inputs,
+            outputs,
+            body,
+        }
+    }
+
+    // a, b[16];
+    fn pin_declarations(&mut self) -> Vec<Pin> {
+        let mut pins = vec![];
+        loop {
+            let name = self.identifier();
+            let mut width = 1;
+            if self.peek_symbol("[") {
+                self.position += 1;
+                width = self.number();
+                self.symbol("]");
+            }
+            if width == 0 || width > 64 {
+                panic!(
+                    "invalid: width of {}: {}, file: {}, line: {}",
+                    name,
+                    width,
+                    self.file,
+                    self.line()
+                );
+            }
+            pins.push(Pin { name, width });
+
+            if self.peek_symbol(";") {
+                self.position += 1;
+                return pins;
+            }
+            self.symbol(",");
+        }
+    }
+
+    // in, load;
+    fn pin_list(&mut self) -> Vec<String> {
+        let mut names = vec![self.identifier()];
+        while self.peek_symbol(",") {
+            self.position += 1;
+            names.push(self.identifier());
+        }
+        self.symbol(";");
+        names
+    }
+
+    // Name(pin=value, ...);
+    fn part(&mut self) -> Part {
+        let line = self.line();
+        let name = self.identifier();
+        self.symbol("(");
+        let mut connections = vec![];
+        loop {
+            let pin = self.pin_ref();
+            self.symbol("=");
+            let value = self.pin_ref();
+            connections.push(Connection { pin, value });
+
+            if self.peek_symbol(")") {
+                break;
+            }
+            self.symbol(",");
+        }
+        self.symbol(")");
+        self.symbol(";");
+
+        Part {
+            name,
+            connections,
+            line,
+        }
+    }
+
+    // name, name[i] or name[i..j]
+    fn pin_ref(&mut self) -> PinRef {
+        let name = self.identifier();
+        let mut range = None;
+        if self.peek_symbol("[") {
+            self.position += 1;
+            let first = self.number();
+            let mut last = first;
+            if self.peek_symbol("..") {
+                self.position += 1;
+                last = self.number();
+            }
+            self.symbol("]");
+            if last < first {
+                panic!(
+                    "invalid: {}[{}..{}], file: {}, line: {}",
+                    name,
+                    first,
+                    last,
+                    self.file,
+                    self.line()
+                );
+            }
+            range = Some((first, last));
+        }
+        PinRef { name, range }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts() {
+        let chip = parse(
+            "/* a chip */
+            CHIP Test {
+                IN a[16], b; // pins
+                OUT out[4];
+                PARTS:
+                Not16(in=a, out[0..3]=out, out[15]=x);
+                And(a=b, b=true, out=y);
+            }",
+            "Test.hdl",
+        );
+        assert_eq!(chip.name, "Test");
+        assert_eq!(chip.inputs.len(), 2);
+        assert_eq!(chip.inputs[0].width, 16);
+        assert_eq!(chip.outputs[0].width, 4);
+        let Body::Parts(parts) = &chip.body else {
+            panic!("Test should be made of parts");
+        };
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].line, 6);
+        let connections: Vec<String> = parts[0]
+            .connections
+            .iter()
+            .map(|v| format!("{}={}", v.pin, v.value))
+            .collect();
+        assert_eq!(connections, ["in=a", "out[0..3]=out", "out[15]=x"]);
+        assert_eq!(parts[0].connections[1].pin.width(16), 4);
+        assert_eq!(parts[0].connections[0].pin.width(16), 16);
+    }
+
+    #[test]
+    fn builtin() {
+        let chip = parse(
+            "CHIP Bit { IN in, load; OUT out; BUILTIN Bit; CLOCKED in, load; }",
+            "Bit.hdl",
+        );
+        let Body::Builtin { name, clocked } = &chip.body else {
+            panic!("Bit should be built-in");
+        };
+        assert_eq!(name, "Bit");
+        assert_eq!(clocked, &["in", "load"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: #, file: Test.hdl, line: 2")]
+    fn unknown_character() {
+        parse("CHIP Test {\n    IN a#;\n}", "Test.hdl");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: expected {, found IN, file: Test.hdl, line: 2")]
+    fn missing_symbol() {
+        parse("CHIP Test\n    IN a;\n    OUT out;\n}", "Test.hdl");
+    }
+
+    #[test]
+    #[should_panic(expected = "found end of file, file: Test.hdl, line: 2")]
+    fn end_of_file() {
+        parse("CHIP Test {\n    IN a;", "Test.hdl");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: width of a: 0, file: Test.hdl, line: 1")]
+    fn zero_width() {
+        parse("CHIP Test { IN a[0]; OUT out; PARTS: }", "Test.hdl");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: a[3..1], file: Test.hdl, line: 3")]
+    fn reversed_range() {
+        parse(
+            "CHIP Test {\n    IN a[4]; OUT out;\n    PARTS: Not(in=a[3..1], out=out);\n}",
+            "Test.hdl",
+        );
+    }
+}
@@ -0,0 +1,831 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::hdl::builtin::{declaration, Builtin};
+use crate::hdl::parser::{parse, Body, Chip, Pin, PinRef};
+
+/// Nets of the constants `false` and `true`
+pub const FALSE: usize = 0;
+pub const TRUE: usize = 1;
+
+/// Finds the chips used as parts in `Name.hdl` files of the search path,
+/// falling back to the chips built into the simulator
+pub struct ChipLibrary {
+    search_path: Vec<PathBuf>,
+    chips:       HashMap<String, Rc<Chip>>,
+}
+
+impl ChipLibrary {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            chips: HashMap::new(),
+        }
+    }
+
+    /// Parse a *.hdl file, whose directory is searched first for its parts
+    pub fn load_file(&mut self, path: &Path) -> Rc<Chip> {
+        let source = read_to_string(path)
+            .unwrap_or_else(|_| panic!("invalid: {}, no such file", path.display()));
+        let chip = Rc::new(parse(&source, &path.file_name().unwrap().to_string_lossy()));
+
+        if let Some(dir) = path.parent() {
+            self.search_path.insert(0, dir.to_path_buf());
+        }
+        self.chips.insert(chip.name.clone(), chip.clone());
+        chip
+    }
+
+    pub fn get(&mut self, name: &str) -> Option<Rc<Chip>> {
+        if let Some(chip) = self.chips.get(name) {
+            return Some(chip.clone());
+        }
+
+        let file = format!("{}.hdl", name);
+        let chip = match self
+            .search_path
+            .iter()
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+        {
+            Some(path) => parse(&read_to_string(path).unwrap(), &file),
+            None => declaration(name)?,
+        };
+        if chip.name != name {
+            panic!("invalid: {} declares CHIP {}", file, chip.name);
+        }
+
+        let chip = Rc::new(chip);
+        self.chips.insert(name.to_owned(), chip.clone());
+        Some(chip)
+    }
+}
+
+/// `out = !(a & b)` between three nets
+#[derive(Clone, Copy)]
+pub struct Nand {
+    pub a:   usize,
+    pub b:   usize,
+    pub out: usize,
+}
+
+/// A built-in chip other than Nand between the nets of its pins
+pub struct BuiltinPart {
+    /// Name of the chip, to refer to its state
+    pub name:    String,
+    pub state:   Builtin,
+    /// Nets of each input pin, in the order of the built-in declaration
+    pub inputs:  Vec<Vec<usize>>,
+    /// Whether each input only matters on the clock
+    pub clocked: Vec<bool>,
+    pub out:     Vec<usize>,
+}
+
+impl BuiltinPart {
+    fn input_values(&self, values: &[bool]) -> Vec<u64> {
+        self.inputs
+            .iter()
+            .map(|nets| bus_value(nets, values))
+            .collect()
+    }
+}
+
+/// A gate or a built-in chip of a flattened chip
+pub enum Node {
+    Nand(Nand),
+    Builtin(BuiltinPart),
+}
+
+impl Node {
+    fn map_nets(&mut self, f: &mut impl FnMut(usize) -> usize) {
+        match self {
+            Node::Nand(nand) => {
+                nand.a = f(nand.a);
+                nand.b = f(nand.b);
+                nand.out = f(nand.out);
+            }
+            Node::Builtin(part) => {
+                for net in part.inputs.iter_mut().flatten().chain(part.out.iter_mut()) {
+                    *net = f(*net);
+                }
+            }
+        }
+    }
+
+    /// The nets whose values the outputs depend on before the clock
+    pub fn dependencies(&self) -> Vec<usize> {
+        match self {
+            Node::Nand(nand) => vec![nand.a, nand.b],
+            Node::Builtin(part) => part
+                .inputs
+                .iter()
+                .zip(part.clocked.iter())
+                .filter(|(_, clocked)| !**clocked)
+                .flat_map(|(nets, _)| nets.iter().copied())
+                .collect(),
+        }
+    }
+
+    pub fn outputs(&self) -> Vec<usize> {
+        match self {
+            Node::Nand(nand) => vec![nand.out],
+            Node::Builtin(part) => part.out.clone(),
+        }
+    }
+}
+
+/// A chip flattened into Nand gates and built-in chips between nets of one
+/// bit each
+pub struct Simulator {
+    chip:    Rc<Chip>,
+    // nets of each input and output pin of the chip, bit 0 first
+    pins:    HashMap<String, Vec<usize>>,
+    values:  Vec<bool>,
+    // in the order of evaluation, each node after the nodes it depends on
+    nodes:   Vec<Node>,
+    // the part each node comes from
+    origins: Vec<String>,
+}
+
+impl Simulator {
+    pub fn new(chip: Rc<Chip>, library: &mut ChipLibrary) -> Self {
+        let mut netlist = Netlist {
+            parent:  vec![FALSE, TRUE],
+            nodes:   vec![],
+            origins: vec![],
+            path:    vec![],
+        };
+
+        let mut pins = HashMap::new();
+        for pin in chip.inputs.iter().chain(chip.outputs.iter()) {
+            let nets = (0..pin.width).map(|_| netlist.new_net()).collect();
+            pins.insert(pin.name.clone(), nets);
+        }
+        netlist.instantiate(&chip, &pins, library);
+
+        let pins = pins
+            .into_iter()
+            .map(|(name, nets)| (name, nets.iter().map(|net| netlist.find(*net)).collect()))
+            .collect();
+        let mut nodes = std::mem::take(&mut netlist.nodes);
+        for node in nodes.iter_mut() {
+            node.map_nets(&mut |net| netlist.find(net));
+        }
+        let origins = std::mem::take(&mut netlist.origins);
+        let (nodes, origins) = sort_nodes(&chip.name, nodes, origins);
+
+        let mut values = vec![false; netlist.parent.len()];
+        values[TRUE] = true;
+
+        Self {
+            chip,
+            pins,
+            values,
+            nodes,
+            origins,
+        }
+    }
+
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    /// The gates and built-in chips of the chip, each after the nodes it
+    /// depends on
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The part the node at `index` of `nodes` comes from, the instances
+    /// from the chip down to it like `u6:Add16/u15:FullAdder/u2:Or/u2:Nand`,
+    /// `ui` being the i-th part of its parent. Empty for a built-in chip
+    /// simulated alone.
+    pub fn origin(&self, index: usize) -> &str {
+        &self.origins[index]
+    }
+
+    /// The nets of an input or output pin, bit 0 first
+    pub fn pin_nets(&self, pin: &str) -> &[usize] {
+        self.pins
+            .get(pin)
+            .unwrap_or_else(|| panic!("invalid: {} is not a pin of {}", pin, self.chip.name))
+    }
+
+    /// Set an input pin to the low bits of `value`
+    pub fn set(&mut self, pin: &str, value: u64) {
+        if !self.chip.is_input(pin) {
+            panic!("invalid: {} is not an input pin of {}", pin, self.chip.name);
+        }
+        for (i, net) in self.pins[pin].iter().enumerate() {
+            self.values[*net] = (value >> i) & 1 != 0;
+        }
+    }
+
+    /// The value of an input or output pin
+    pub fn get(&self, pin: &str) -> u64 {
+        let nets = self
+            .pins
+            .get(pin)
+            .unwrap_or_else(|| panic!("invalid: {} is not a pin of {}", pin, self.chip.name));
+        bus_value(nets, &self.values)
+    }
+
+    /// Propagate the inputs through the gates and built-in chips
+    pub fn eval(&mut self) {
+        for node in self.nodes.iter() {
+            match node {
+                Node::Nand(nand) => {
+                    self.values[nand.out] = !(self.values[nand.a] && self.values[nand.b]);
+                }
+                Node::Builtin(part) => {
+                    let out = part.state.eval(&part.input_values(&self.values));
+                    for (i, net) in part.out.iter().enumerate() {
+                        self.values[*net] = (out >> i) & 1 != 0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The rising edge of the clock: the clocked chips latch their inputs,
+    /// their outputs keep their values until the tock
+    pub fn tick(&mut self) {
+        self.eval();
+        for node in self.nodes.iter_mut() {
+            if let Node::Builtin(part) = node {
+                let inputs = part.input_values(&self.values);
+                part.state.tick(&inputs);
+            }
+        }
+    }
+
+    /// The falling edge of the clock: the clocked chips update their outputs
+    pub fn tock(&mut self) {
+        for node in self.nodes.iter_mut() {
+            if let Node::Builtin(part) = node {
+                part.state.tock();
+            }
+        }
+        self.eval();
+    }
+
+    /// The value of the built-in part `part`, a register or the word at
+    /// `address` of a memory. The first part of that chip is used when there
+    /// are several.
+    pub fn peek(&self, part: &str, address: usize) -> u64 {
+        let state = self
+            .nodes
+            .iter()
+            .find_map(|node| match node {
+                Node::Builtin(v) if v.name == part => Some(&v.state),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("invalid: {} has no built-in part {}", self.chip.name, part));
+        state
+            .peek(address)
+            .unwrap_or_else(|| panic!("invalid: {}[{}], out of range", part, address))
+    }
+
+    /// Overwrite the value of the built-in part `part` like `peek` reads it
+    pub fn poke(&mut self, part: &str, address: usize, value: u64) {
+        let state = self
+            .nodes
+            .iter_mut()
+            .find_map(|node| match node {
+                Node::Builtin(v) if v.name == part => Some(&mut v.state),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("invalid: {} has no built-in part {}", self.chip.name, part));
+        if !state.poke(address, value) {
+            panic!("invalid: {}[{}], out of range", part, address);
+        }
+    }
+
+    /// Load a program into every ROM32K part
+    pub fn load_rom(&mut self, rom: &[u16]) {
+        if rom.len() > 32768 {
+            panic!("invalid: {} words do not fit in ROM32K", rom.len());
+        }
+        for node in self.nodes.iter_mut() {
+            if let Node::Builtin(v) = node {
+                if let Builtin::Rom { words } = &mut v.state {
+                    words.fill(0);
+                    words[..rom.len()].copy_from_slice(rom);
+                }
+            }
+        }
+    }
+}
+
+// The value of a bus, bit 0 first
+fn bus_value(nets: &[usize], values: &[bool]) -> u64 {
+    nets.iter()
+        .enumerate()
+        .fold(0, |value, (i, net)| value | (values[*net] as u64) << i)
+}
+
+// Nets joined by the connections of the parts, `parent` being the union-find
+// forest of the nets, and the nodes between them with the parts they come
+// from. `path` holds the instances of the part being instantiated.
+struct Netlist {
+    parent:  Vec<usize>,
+    nodes:   Vec<Node>,
+    origins: Vec<String>,
+    path:    Vec<String>,
+}
+
+impl Netlist {
+    fn new_net(&mut self) -> usize {
+        self.parent.push(self.parent.len());
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, net: usize) -> usize {
+        let mut root = net;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut net = net;
+        while self.parent[net] != root {
+            let next = self.parent[net];
+            self.parent[net] = root;
+            net = next;
+        }
+        root
+    }
+
+    // the constants stay the roots of their sets
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if b <= TRUE {
+            self.parent[a] = b;
+        } else {
+            self.parent[b] = a;
+        }
+    }
+
+    // Add the gates of `chip` between the nets of its pins
+    fn instantiate(
+        &mut self,
+        chip: &Chip,
+        pins: &HashMap<String, Vec<usize>>,
+        library: &mut ChipLibrary,
+    ) {
+        let parts = match &chip.body {
+            Body::Builtin { name, .. } => {
+                self.add_builtin(chip, name, pins);
+                return;
+            }
+            Body::Parts(parts) => parts,
+        };
+        let file = format!("{}.hdl", chip.name);
+
+        let part_chips: Vec<Rc<Chip>> = parts
+            .iter()
+            .map(|part| {
+                library.get(&part.name).unwrap_or_else(|| {
+                    panic!(
+                        "invalid: unknown chip {}, file: {}, line: {}",
+                        part.name, file, part.line
+                    )
+                })
+            })
+            .collect();
+
+        // the internal pins, which are outputs of parts
+        let mut internal: HashMap<String, Vec<usize>> = HashMap::new();
+        for (part, part_chip) in parts.iter().zip(part_chips.iter()) {
+            for connection in part.connections.iter() {
+                let value = &connection.value;
+                if part_chip.is_input(&connection.pin.name) || chip.pin(&value.name).is_some() {
+                    continue;
+                }
+                let part_pin = pin_of(part_chip, &connection.pin, &file, part.line);
+                if value.range.is_some() || is_constant(&value.name) {
+                    panic!(
+                        "invalid: {} cannot be driven by {}, file: {}, line: {}",
+                        value, connection.pin, file, part.line
+                    );
+                }
+
+                let width = connection.pin.width(part_pin.width);
+                match internal.get(&value.name) {
+                    Some(nets) if nets.len() != width => panic!(
+                        "invalid: {} is {} bits wide and {} bits wide, file: {}, line: {}",
+                        value.name,
+                        nets.len(),
+                        width,
+                        file,
+                        part.line
+                    ),
+                    Some(_) => {}
+                    None => {
+                        let nets = (0..width).map(|_| self.new_net()).collect();
+                        internal.insert(value.name.clone(), nets);
+                    }
+                }
+            }
+        }
+
+        // (pin, bit) of the chip already driven by a part
+        let mut driven: HashSet<(String, usize)> = HashSet::new();
+        for (index, (part, part_chip)) in parts.iter().zip(part_chips.iter()).enumerate() {
+            // unconnected inputs are false
+            let mut part_pins: HashMap<String, Vec<usize>> = HashMap::new();
+            for pin in part_chip.inputs.iter() {
+                part_pins.insert(pin.name.clone(), vec![FALSE; pin.width]);
+            }
+            for pin in part_chip.outputs.iter() {
+                let nets = (0..pin.width).map(|_| self.new_net()).collect();
+                part_pins.insert(pin.name.clone(), nets);
+            }
+
+            for connection in part.connections.iter() {
+                let part_pin = pin_of(part_chip, &connection.pin, &file, part.line);
+                let (first, _) = connection.pin.range.unwrap_or((0, part_pin.width - 1));
+                let width = connection.pin.width(part_pin.width);
+                let value = &connection.value;
+                let is_input = part_chip.is_input(&part_pin.name);
+
+                let nets: Vec<usize> = match value.name.as_str() {
+                    "true" if is_input => vec![TRUE; width],
+                    "false" if is_input => vec![FALSE; width],
+                    name => {
+                        let (nets, first) = match (chip.pin(name), internal.get(name)) {
+                            (Some(_), _) => {
+                                if !is_input && chip.is_input(name) {
+                                    panic!(
+                                        "invalid: input pin {} cannot be driven by {}, file: {}, line: {}",
+                                        name, connection.pin, file, part.line
+                                    );
+                                }
+                                let pin_ref = pin_of(chip, value, &file, part.line);
+                                let (first, _) = value.range.unwrap_or((0, pin_ref.width - 1));
+                                (&pins[name], first)
+                            }
+                            (None, Some(nets)) => {
+                                let (first, last) = value.range.unwrap_or((0, nets.len() - 1));
+                                if last >= nets.len() {
+                                    panic!(
+                                        "invalid: {} is out of the {} bits of {}, file: {}, line: {}",
+                                        value,
+                                        nets.len(),
+                                        name,
+                                        file,
+                                        part.line
+                                    );
+                                }
+                                (nets, first)
+                            }
+                            (None, None) => panic!(
+                                "invalid: {} is not driven by any part, file: {}, line: {}",
+                                name, file, part.line
+                            ),
+                        };
+                        let value_width = value.width(nets.len());
+                        if value_width != width {
+                            panic!(
+                                "invalid: {} is {} bits wide and {} is {}, file: {}, line: {}",
+                                connection.pin, width, value, value_width, file, part.line
+                            );
+                        }
+                        nets[first..first + width].to_vec()
+                    }
+                };
+
+                let part_nets = part_pins.get_mut(&part_pin.name).unwrap();
+                if is_input {
+                    part_nets[first..first + width].copy_from_slice(&nets);
+                    continue;
+                }
+                for (i, net) in nets.iter().enumerate() {
+                    let bit = value.range.map_or(0, |(first, _)| first) + i;
+                    if !driven.insert((value.name.clone(), bit)) {
+                        panic!(
+                            "invalid: {} is driven by several parts, file: {}, line: {}",
+                            value, file, part.line
+                        );
+                    }
+                    self.join(part_nets[first + i], *net);
+                }
+            }
+
+            self.path.push(format!("u{}:{}", index, part.name));
+            self.instantiate(part_chip, &part_pins, library);
+            self.path.pop();
+        }
+    }
+
+    // Add the built-in chip `name` implementing `chip`, whose pins must be
+    // those of the built-in declaration. The clocked pins are those of the
+    // simulator, whatever `chip` declares.
+    fn add_builtin(&mut self, chip: &Chip, name: &str, pins: &HashMap<String, Vec<usize>>) {
+        let builtin = declaration(name).unwrap_or_else(|| {
+            panic!(
+                "invalid: unknown built-in chip {}, file: {}.hdl",
+                name, chip.name
+            )
+        });
+        let same_pins = |a: &[Pin], b: &[Pin]| {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|pin| b.iter().any(|v| v.name == pin.name && v.width == pin.width))
+        };
+        if !same_pins(&chip.inputs, &builtin.inputs) || !same_pins(&chip.outputs, &builtin.outputs)
+        {
+            panic!(
+                "invalid: the pins of {} are not those of the built-in {}, file: {}.hdl",
+                chip.name, name, chip.name
+            );
+        }
+
+        self.origins.push(self.path.join("/"));
+        if name == "Nand" {
+            self.nodes.push(Node::Nand(Nand {
+                a:   pins["a"][0],
+                b:   pins["b"][0],
+                out: pins["out"][0],
+            }));
+            return;
+        }
+
+        let Body::Builtin { clocked, .. } = &builtin.body else {
+            unreachable!()
+        };
+        self.nodes.push(Node::Builtin(BuiltinPart {
+            name:    chip.name.clone(),
+            state:   Builtin::new(name).unwrap(),
+            inputs:  builtin
+                .inputs
+                .iter()
+                .map(|pin| pins[&pin.name].clone())
+                .collect(),
+            clocked: builtin
+                .inputs
+                .iter()
+                .map(|pin| clocked.contains(&pin.name))
+                .collect(),
+            out:     pins["out"].clone(),
+        }));
+    }
+}
+
+fn is_constant(name: &str) -> bool {
+    name == "true" || name == "false"
+}
+
+// The pin of `chip` a sub-bus refers to, checking its bits
+fn pin_of<'a>(chip: &'a Chip, pin_ref: &PinRef, file: &str, line: usize) -> &'a Pin {
+    let pin = chip.pin(&pin_ref.name).unwrap_or_else(|| {
+        panic!(
+            "invalid: {} has no pin {}, file: {}, line: {}",
+            chip.name, pin_ref.name, file, line
+        )
+    });
+    if let Some((_, last)) = pin_ref.range {
+        if last >= pin.width {
+            panic!(
+                "invalid: {} is out of the {} bits of {}, file: {}, line: {}",
+                pin_ref, pin.width, pin.name, file, line
+            );
+        }
+    }
+    pin
+}
+
+// Order the nodes and their origins so that each node comes after the nodes
+// driving the inputs it depends on before the clock
+fn sort_nodes(chip: &str, nodes: Vec<Node>, origins: Vec<String>) -> (Vec<Node>, Vec<String>) {
+    let mut driver: HashMap<usize, usize> = HashMap::new();
+    for (i, node) in nodes.iter().enumerate() {
+        for net in node.outputs() {
+            driver.insert(net, i);
+        }
+    }
+    let dependencies: Vec<Vec<usize>> = nodes.iter().map(|node| node.dependencies()).collect();
+
+    // 0: not visited, 1: being visited, 2: done
+    let mut state = vec![0u8; nodes.len()];
+    let mut order = vec![];
+    for root in 0..nodes.len() {
+        let mut stack = vec![(root, false)];
+        while let Some((i, expanded)) = stack.pop() {
+            if expanded {
+                state[i] = 2;
+                order.push(i);
+                continue;
+            }
+            match state[i] {
+                2 => continue,
+                1 => panic!("invalid: combinational loop in {}", chip),
+                _ => {}
+            }
+            state[i] = 1;
+            stack.push((i, true));
+            for net in dependencies[i].iter() {
+                if let Some(&j) = driver.get(net) {
+                    match state[j] {
+                        0 => stack.push((j, false)),
+                        1 => panic!("invalid: combinational loop in {}", chip),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    let mut nodes: Vec<Option<(Node, String)>> = nodes.into_iter().zip(origins).map(Some).collect();
+    order.into_iter().map(|i| nodes[i].take().unwrap()).unzip()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Simulate the first chip, the others being its parts along with the
+    // chips of data/hdl
+    fn simulator(sources: &[&str]) -> Simulator {
+        let mut library =
+            ChipLibrary::new(vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("data/hdl")]);
+        let chips: Vec<Rc<Chip>> = sources
+            .iter()
+            .map(|source| Rc::new(parse(source, "Test.hdl")))
+            .collect();
+        for chip in chips.iter() {
+            library.chips.insert(chip.name.clone(), chip.clone());
+        }
+        Simulator::new(chips[0].clone(), &mut library)
+    }
+
+    #[test]
+    fn internal_sub_bus() {
+        let mut simulator = simulator(&["CHIP Test {
+            IN a[4];
+            OUT out[2];
+            PARTS:
+            Not16(in[0..3]=a, out[0..3]=x);
+            And(a=x[2], b=true, out=out[0]);
+            And(a=x[3], b=true, out=out[1]);
+        }"]);
+        simulator.set("a", 0b0011);
+        simulator.eval();
+        assert_eq!(simulator.get("out"), 0b11);
+        simulator.set("a", 0b0111);
+        simulator.eval();
+        assert_eq!(simulator.get("out"), 0b10);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: x[4] is out of the 4 bits of x, file: Test.hdl, line: 6")]
+    fn internal_sub_bus_out_of_range() {
+        simulator(&["CHIP Test {
+            IN a[4];
+            OUT out;
+            PARTS:
+            Not16(in[0..3]=a, out[0..3]=x);
+            Not(in=x[4], out=out);
+        }"]);
+    }
+
+    #[test]
+    fn joined_nets() {
+        let simulator = simulator(&[
+            "CHIP Test {
+                IN a;
+                OUT out, copy, same;
+                PARTS:
+                Buffer(in=a, out=x);
+                Not(in=x, out=out, out=copy);
+                Buffer(in=a, out=same);
+            }",
+            "CHIP Buffer {
+                IN in;
+                OUT out;
+                PARTS:
+                Not(in=in, out=x);
+                Not(in=x, out=out);
+            }",
+        ]);
+        assert_eq!(simulator.pin_nets("out"), simulator.pin_nets("copy"));
+        assert_ne!(simulator.pin_nets("out"), simulator.pin_nets("same"));
+        // Not is a Nand whose inputs are joined
+        let Node::Nand(nand) = &simulator.nodes()[0] else {
+            panic!("Not should be a Nand gate");
+        };
+        assert_eq!(nand.a, nand.b);
+        assert_eq!(simulator.nodes().len(), 5);
+    }
+
+    #[test]
+    fn unconnected_inputs() {
+        let mut simulator = simulator(&["CHIP Test {
+            IN a;
+            OUT out;
+            PARTS:
+            Or(a=a, out=out);
+        }"]);
+        simulator.set("a", 1);
+        simulator.eval();
+        assert_eq!(simulator.get("out"), 1);
+        simulator.set("a", 0);
+        simulator.eval();
+        assert_eq!(simulator.get("out"), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: combinational loop in Test")]
+    fn combinational_loop() {
+        simulator(&["CHIP Test {
+            IN a;
+            OUT out;
+            PARTS:
+            And(a=a, b=y, out=x);
+            Not(in=x, out=y, out=out);
+        }"]);
+    }
+
+    #[test]
+    fn clocked_loop() {
+        let mut simulator = simulator(&["CHIP Test {
+            IN in;
+            OUT out;
+            PARTS:
+            Not(in=x, out=y);
+            DFF(in=y, out=x, out=out);
+        }"]);
+        simulator.tick();
+        simulator.tock();
+        assert_eq!(simulator.get("out"), 1);
+        simulator.tick();
+        simulator.tock();
+        assert_eq!(simulator.get("out"), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: out is driven by several parts, file: Test.hdl, line: 6")]
+    fn multiple_drivers() {
+        simulator(&["CHIP Test {
+            IN a;
+            OUT out;
+            PARTS:
+            Not(in=a, out=out);
+            Not(in=a, out=out);
+        }"]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid: out[1] is driven by several parts, file: Test.hdl, line: 6"
+    )]
+    fn multiple_drivers_of_a_bit() {
+        simulator(&["CHIP Test {
+            IN a[2];
+            OUT out[2];
+            PARTS:
+            Not16(in[0..1]=a, out[0..1]=out);
+            Not(in=a[0], out=out[1]);
+        }"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: in is 16 bits wide and a is 4, file: Test.hdl, line: 5")]
+    fn width_of_a_chip_pin() {
+        simulator(&["CHIP Test {
+            IN a[4];
+            OUT out[16];
+            PARTS:
+            Not16(in=a, out=out);
+        }"]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid: x is 16 bits wide and 1 bits wide, file: Test.hdl, line: 6"
+    )]
+    fn width_of_an_internal_pin() {
+        simulator(&["CHIP Test {
+            IN a[16];
+            OUT out;
+            PARTS:
+            Not16(in=a, out=x);
+            Not(in=a[0], out=x);
+            Not(in=x[0], out=out);
+        }"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid: x is not driven by any part, file: Test.hdl, line: 5")]
+    fn undriven_internal_pin() {
+        simulator(&["CHIP Test {
+            IN a;
+            OUT out;
+            PARTS:
+            And(a=a, b=x, out=out);
+        }"]);
+    }
+}
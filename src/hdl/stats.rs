@@ -0,0 +1,433 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::hdl::builtin::declaration;
+use crate::hdl::parser::{Body, Chip};
+use crate::hdl::simulator::{ChipLibrary, Node, Simulator, FALSE, TRUE};
+
+/// The cost of one type of part of a chip
+pub struct PartStats {
+    pub name:    String,
+    /// Instances in the whole hierarchy of the chip
+    pub count:   usize,
+    /// Built-in chip other than Nand, which has no gates
+    pub builtin: bool,
+    /// Nand gates of one instance
+    pub nands:   usize,
+    /// Nand gates on the longest combinational path of one instance
+    pub depth:   usize,
+}
+
+/// The longest combinational path of a chip, from an input pin, a constant
+/// or the output of a clocked chip to an output pin or a clocked input
+pub struct CriticalPath {
+    pub from:  String,
+    pub to:    String,
+    /// The gates and built-in chips on the path, in the order of
+    /// propagation, as given by `Simulator::origin`
+    pub parts: Vec<String>,
+}
+
+/// Gate counts and combinational depths of a chip flattened to Nand gates
+pub struct ChipStats {
+    pub name:          String,
+    pub nands:         usize,
+    /// Built-in chips other than Nand
+    pub builtins:      usize,
+    pub depth:         usize,
+    /// Sorted by total Nand gates
+    pub parts:         Vec<PartStats>,
+    /// Depth of each output pin, the deepest of its bits
+    pub outputs:       Vec<(String, usize)>,
+    pub critical_path: CriticalPath,
+}
+
+/// Flatten a chip and each type of part it is made of to Nand gates, and
+/// find their longest paths. Built-in chips other than Nand add no gates,
+/// their outputs being as deep as their inputs that are not clocked.
+pub fn analyze(chip: Rc<Chip>, library: &mut ChipLibrary) -> ChipStats {
+    let simulator = Simulator::new(chip.clone(), library);
+    let timing = Timing::new(&simulator);
+
+    let mut counts = HashMap::new();
+    count_parts(&chip, 1, library, &mut counts);
+    let mut parts: Vec<PartStats> = counts
+        .into_iter()
+        .map(|(name, count)| {
+            let part_chip = library.get(&name).unwrap();
+            let builtin = matches!(&part_chip.body, Body::Builtin { name, .. } if name != "Nand");
+            let simulator = Simulator::new(part_chip, library);
+            let (nands, _) = node_counts(&simulator);
+            PartStats {
+                name,
+                count,
+                builtin,
+                nands,
+                depth: Timing::new(&simulator).depth(&simulator),
+            }
+        })
+        .collect();
+    parts.sort_by_key(|part| (Reverse(part.count * part.nands), part.name.clone()));
+
+    let outputs = chip
+        .outputs
+        .iter()
+        .map(|pin| {
+            let depth = simulator
+                .pin_nets(&pin.name)
+                .iter()
+                .map(|net| timing.arrival(*net))
+                .max()
+                .unwrap_or(0);
+            (pin.name.clone(), depth)
+        })
+        .collect();
+
+    let (nands, builtins) = node_counts(&simulator);
+    ChipStats {
+        name: chip.name.clone(),
+        nands,
+        builtins,
+        depth: timing.depth(&simulator),
+        parts,
+        outputs,
+        critical_path: timing.critical_path(&simulator),
+    }
+}
+
+impl ChipStats {
+    /// The totals, the parts, the depth of the outputs and the critical path
+    pub fn report(&self) -> Vec<u8> {
+        let mut output = vec![];
+
+        write!(&mut output, "{}: {} Nand gates", self.name, self.nands).unwrap();
+        if self.builtins > 0 {
+            write!(&mut output, " and {} built-in chips", self.builtins).unwrap();
+        }
+        writeln!(&mut output, ", depth {}", self.depth).unwrap();
+
+        if !self.parts.is_empty() {
+            writeln!(
+                &mut output,
+                "\n{:<16} {:>8} {:>8} {:>8} {:>6}",
+                "part", "count", "Nands", "total", "depth"
+            )
+            .unwrap();
+        }
+        for part in self.parts.iter() {
+            if part.builtin {
+                writeln!(
+                    &mut output,
+                    "{:<16} {:>8} {:>8} {:>8} {:>6}",
+                    part.name, part.count, "built-in", "-", "-"
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    &mut output,
+                    "{:<16} {:>8} {:>8} {:>8} {:>6}",
+                    part.name,
+                    part.count,
+                    part.nands,
+                    part.count * part.nands,
+                    part.depth
+                )
+                .unwrap();
+            }
+        }
+
+        if !self.outputs.is_empty() {
+            writeln!(&mut output, "\n{:<16} {:>6}", "output", "depth").unwrap();
+        }
+        for (name, depth) in self.outputs.iter() {
+            writeln!(&mut output, "{:<16} {:>6}", name, depth).unwrap();
+        }
+
+        let path = &self.critical_path;
+        writeln!(
+            &mut output,
+            "\nCritical path ({} Nand gates) from {} to {}:",
+            self.depth, path.from, path.to
+        )
+        .unwrap();
+        for part in path.parts.iter() {
+            writeln!(&mut output, "    {}", part).unwrap();
+        }
+        output
+    }
+}
+
+// Add the instances of each type of part of `chip`, which is used `count`
+// times, down to the built-in chips
+fn count_parts(
+    chip: &Chip,
+    count: usize,
+    library: &mut ChipLibrary,
+    counts: &mut HashMap<String, usize>,
+) {
+    let Body::Parts(parts) = &chip.body else {
+        return;
+    };
+    for part in parts.iter() {
+        *counts.entry(part.name.clone()).or_default() += count;
+        let part_chip = library.get(&part.name).unwrap();
+        count_parts(&part_chip, count, library, counts);
+    }
+}
+
+// Nand gates and other built-in chips of a flattened chip
+fn node_counts(simulator: &Simulator) -> (usize, usize) {
+    let nands = simulator
+        .nodes()
+        .iter()
+        .filter(|node| matches!(node, Node::Nand(_)))
+        .count();
+    (nands, simulator.nodes().len() - nands)
+}
+
+// The Nand gates before each net, along the longest path, and the node
+// driving it
+struct Timing {
+    arrival: HashMap<usize, usize>,
+    driver:  HashMap<usize, usize>,
+}
+
+impl Timing {
+    fn new(simulator: &Simulator) -> Self {
+        let mut timing = Timing {
+            arrival: HashMap::new(),
+            driver:  HashMap::new(),
+        };
+        // the nodes are in the order of evaluation
+        for (i, node) in simulator.nodes().iter().enumerate() {
+            let gates = match node {
+                Node::Nand(_) => 1,
+                Node::Builtin(_) => 0,
+            };
+            let arrival = node
+                .dependencies()
+                .iter()
+                .map(|net| timing.arrival(*net))
+                .max()
+                .map_or(0, |arrival| arrival + gates);
+            for net in node.outputs() {
+                timing.arrival.insert(net, arrival);
+                timing.driver.insert(net, i);
+            }
+        }
+        timing
+    }
+
+    fn arrival(&self, net: usize) -> usize {
+        self.arrival.get(&net).copied().unwrap_or(0)
+    }
+
+    // the ends of the combinational paths with their names, the output pins
+    // then the clocked inputs of the built-in chips
+    fn ends(simulator: &Simulator) -> Vec<(usize, String)> {
+        let mut ends = vec![];
+        for pin in simulator.chip().outputs.iter() {
+            for (i, net) in simulator.pin_nets(&pin.name).iter().enumerate() {
+                ends.push((*net, bit_name(&pin.name, pin.width, i)));
+            }
+        }
+        for (index, node) in simulator.nodes().iter().enumerate() {
+            let Node::Builtin(part) = node else {
+                continue;
+            };
+            let names: Vec<String> = declaration(&part.name).map_or(vec![], |chip| {
+                chip.inputs.iter().map(|pin| pin.name.clone()).collect()
+            });
+            let inputs = part.inputs.iter().zip(part.clocked.iter()).enumerate();
+            for (pin, (nets, _)) in inputs.filter(|(_, (_, clocked))| **clocked) {
+                let name = names.get(pin).map_or("in", |name| name.as_str());
+                for (i, net) in nets.iter().enumerate() {
+                    let name = bit_name(name, nets.len(), i);
+                    ends.push((*net, format!("{}.{}", origin(simulator, index), name)));
+                }
+            }
+        }
+        ends
+    }
+
+    fn depth(&self, simulator: &Simulator) -> usize {
+        Timing::ends(simulator)
+            .iter()
+            .map(|(net, _)| self.arrival(*net))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Follow the deepest inputs back from the deepest end
+    fn critical_path(&self, simulator: &Simulator) -> CriticalPath {
+        let (mut net, to) = Timing::ends(simulator)
+            .into_iter()
+            .rev()
+            .max_by_key(|(net, _)| self.arrival(*net))
+            .unwrap_or((FALSE, String::new()));
+
+        let mut parts = vec![];
+        let from = loop {
+            let Some(&index) = self.driver.get(&net) else {
+                break start_name(simulator, net);
+            };
+            let node = &simulator.nodes()[index];
+            let deepest = node
+                .dependencies()
+                .into_iter()
+                .rev()
+                .max_by_key(|net| self.arrival(*net));
+            match (node, deepest) {
+                (Node::Builtin(part), None) => {
+                    let bit = part.out.iter().position(|v| *v == net).unwrap();
+                    let name = bit_name("out", part.out.len(), bit);
+                    break format!("{}.{}", origin(simulator, index), name);
+                }
+                (_, Some(deepest)) => {
+                    parts.push(origin(simulator, index));
+                    net = deepest;
+                }
+                (Node::Nand(_), None) => unreachable!(),
+            }
+        };
+        parts.reverse();
+
+        CriticalPath { from, to, parts }
+    }
+}
+
+// The origin of a node, the chip itself for a built-in chip alone
+fn origin(simulator: &Simulator, index: usize) -> String {
+    match simulator.origin(index) {
+        "" => simulator.chip().name.clone(),
+        origin => origin.to_owned(),
+    }
+}
+
+// The name of a net driven by no node
+fn start_name(simulator: &Simulator, net: usize) -> String {
+    match net {
+        FALSE => return "false".to_owned(),
+        TRUE => return "true".to_owned(),
+        _ => {}
+    }
+    for pin in simulator.chip().inputs.iter() {
+        if let Some(i) = simulator.pin_nets(&pin.name).iter().position(|v| *v == net) {
+            return bit_name(&pin.name, pin.width, i);
+        }
+    }
+    "an unconnected pin".to_owned()
+}
+
+// `a` for a single bit, `x[3]` for a bit of a bus
+fn bit_name(name: &str, width: usize, bit: usize) -> String {
+    if width == 1 {
+        name.to_owned()
+    } else {
+        format!("{}[{}]", name, bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    // Not and And of 1 and 2 Nand gates, a chip made of them, and a clocked one
+    const CHIPS: [(&str, &str); 4] = [
+        (
+            "MyNot",
+            "CHIP MyNot { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }",
+        ),
+        (
+            "MyAnd",
+            "CHIP MyAnd { IN a, b; OUT out; PARTS: Nand(a=a, b=b, out=x); MyNot(in=x, out=out); }",
+        ),
+        (
+            "Test",
+            "CHIP Test {
+            IN a, b;
+            OUT out, n;
+            PARTS:
+            MyAnd(a=a, b=b, out=x);
+            MyNot(in=x, out=out);
+            MyNot(in=a, out=n);
+        }",
+        ),
+        (
+            "Toggle",
+            "CHIP Toggle {
+            IN in;
+            OUT out;
+            PARTS:
+            MyNot(in=q, out=d);
+            DFF(in=d, out=q, out=out);
+        }",
+        ),
+    ];
+
+    fn analyze_chip(name: &str) -> ChipStats {
+        let dir = std::env::temp_dir().join(format!(
+            "nand2tetris-rs-stats-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, source) in CHIPS.iter() {
+            fs::write(dir.join(format!("{}.hdl", name)), source).unwrap();
+        }
+        let mut library = ChipLibrary::new(vec![]);
+        let chip = library.load_file(&dir.join(format!("{}.hdl", name)));
+        let stats = analyze(chip, &mut library);
+        fs::remove_dir_all(dir).unwrap();
+        stats
+    }
+
+    #[test]
+    fn count_nand_gates() {
+        let stats = analyze_chip("Test");
+        assert_eq!((stats.nands, stats.builtins, stats.depth), (4, 0, 3));
+        assert_eq!(stats.outputs, [("out".to_owned(), 3), ("n".to_owned(), 1)]);
+        assert_eq!(
+            String::from_utf8(stats.report()).unwrap(),
+            "\
+Test: 4 Nand gates, depth 3
+
+part                count    Nands    total  depth
+Nand                    4        1        4      1
+MyNot                   3        1        3      1
+MyAnd                   1        2        2      2
+
+output            depth
+out                   3
+n                     1
+
+Critical path (3 Nand gates) from a to out:
+    u0:MyAnd/u0:Nand
+    u0:MyAnd/u1:MyNot/u0:Nand
+    u1:MyNot/u0:Nand
+"
+        );
+    }
+
+    #[test]
+    fn stop_paths_at_clocked_inputs() {
+        let stats = analyze_chip("Toggle");
+        assert_eq!((stats.nands, stats.builtins, stats.depth), (1, 1, 1));
+        assert_eq!(stats.outputs, [("out".to_owned(), 0)]);
+
+        let dff = stats.parts.iter().find(|part| part.name == "DFF").unwrap();
+        assert!(dff.builtin);
+        assert_eq!(dff.nands, 0);
+
+        let path = &stats.critical_path;
+        assert_eq!(
+            (path.from.as_str(), path.to.as_str()),
+            ("u1:DFF.out", "u1:DFF.in")
+        );
+        assert_eq!(path.parts, ["u0:MyNot/u0:Nand"]);
+    }
+}
@@ -0,0 +1,665 @@
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Emulator;
+use crate::hdl::simulator::{ChipLibrary, Simulator};
+use crate::utils::save_file;
+
+// A command of a *.tst script
+enum Command {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, String),
+    Eval,
+    Tick,
+    Tock,
+    Output,
+    Echo(String),
+    ClearEcho,
+    // `ROM32K load Xxx.hack`
+    RomLoad(String),
+    Repeat(usize, Vec<(usize, Command)>),
+}
+
+// `name%B1.16.1`: the value of `name` in binary, decimal, hexadecimal or as
+// a string, `width` characters wide between `left` and `right` spaces
+#[derive(Clone)]
+struct Column {
+    name:   String,
+    format: char,
+    left:   usize,
+    width:  usize,
+    right:  usize,
+}
+
+impl Column {
+    fn parse(spec: &str, file: &str, line: usize) -> Self {
+        let invalid = || -> ! {
+            panic!(
+                "invalid: output-list {}, file: {}, line: {}",
+                spec, file, line
+            )
+        };
+        let (name, format) = spec.split_once('%').unwrap_or_else(|| invalid());
+        let mut chars = format.chars();
+        let format = chars
+            .next()
+            .filter(|v| "BDXS".contains(*v))
+            .unwrap_or_else(|| invalid());
+        let sizes: Vec<usize> = chars
+            .as_str()
+            .split('.')
+            .map(|v| v.parse().unwrap_or_else(|_| invalid()))
+            .collect();
+        let [left, width, right] = sizes[..] else {
+            invalid()
+        };
+        Self {
+            name: name.to_owned(),
+            format,
+            left,
+            width,
+            right,
+        }
+    }
+
+    // Characters of the column. Like in the course, string columns have no
+    // right padding.
+    fn size(&self) -> usize {
+        match self.format {
+            'S' => self.left + self.width,
+            _ => self.left + self.width + self.right,
+        }
+    }
+
+    // The name centered in the column, cut to fit
+    fn header(&self) -> String {
+        let size = self.size();
+        let name: String = self.name.chars().take(size).collect();
+        let space = size - name.chars().count();
+        format!(
+            "{}{}{}",
+            " ".repeat(space / 2),
+            name,
+            " ".repeat(space - space / 2)
+        )
+    }
+
+    // `value` of a pin `bits` wide
+    fn cell(&self, value: u64, bits: usize) -> String {
+        let width = self.width;
+        match self.format {
+            'B' => self.pad(&format!("{:0width$b}", mask(value, width), width = width)),
+            'X' => self.pad(&format!(
+                "{:0width$X}",
+                mask(value, width * 4),
+                width = width
+            )),
+            'D' => self.pad(&format!("{:>width$}", signed(value, bits), width = width)),
+            _ => self.pad(&signed(value, bits).to_string()),
+        }
+    }
+
+    // Numbers are right-aligned to the width already, strings are left-aligned
+    fn pad(&self, text: &str) -> String {
+        match self.format {
+            'S' => format!(
+                "{}{:<width$}",
+                " ".repeat(self.left),
+                text,
+                width = self.width
+            ),
+            _ => format!(
+                "{}{}{}",
+                " ".repeat(self.left),
+                text,
+                " ".repeat(self.right)
+            ),
+        }
+    }
+}
+
+fn mask(value: u64, bits: usize) -> u64 {
+    if bits >= 64 {
+        value
+    } else {
+        value & ((1 << bits) - 1)
+    }
+}
+
+// Buses of 16 bits or more are 16-bit two's complement numbers like in the
+// course, narrower ones are unsigned
+fn signed(value: u64, bits: usize) -> i64 {
+    if bits >= 16 {
+        value as u16 as i16 as i64
+    } else {
+        value as i64
+    }
+}
+
+/// The first row of the output that differs from the compare file
+pub struct Mismatch {
+    /// Line of the output and compare files, from 1
+    pub line:     usize,
+    /// Column of the output list, from 1, 0 when the whole line differs
+    pub column:   usize,
+    pub name:     String,
+    pub expected: String,
+    pub found:    String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.column == 0 {
+            write!(
+                f,
+                "comparison failure at line {}: expected {:?}, found {:?}",
+                self.line, self.expected, self.found
+            )
+        } else {
+            write!(
+                f,
+                "comparison failure at line {}, column {} ({}): expected {:?}, found {:?}",
+                self.line, self.column, self.name, self.expected, self.found
+            )
+        }
+    }
+}
+
+/// The result of a test script
+pub struct TestResult {
+    /// The *.out file written, if the script names one
+    pub output_file: Option<PathBuf>,
+    /// Whether the script compares its output to a *.cmp file
+    pub compared:    bool,
+    /// The first difference with the *.cmp file, which ends the script
+    pub mismatch:    Option<Mismatch>,
+}
+
+/// Runs the *.tst scripts of the course's hardware simulator: `load Xxx.hdl`,
+/// `output-file`, `compare-to`, `output-list`, `set`, `eval`, `tick`, `tock`,
+/// `output`, `echo`, `clear-echo`, `repeat n { ... }` and `ROM32K load
+/// Xxx.hack`. Files are relative to the script.
+///
+/// The output is formatted like the course's, so that it can be compared to
+/// its *.cmp files line by line. Cells of `*` in a *.cmp file match anything.
+pub struct TestScript {
+    dir:         PathBuf,
+    file:        String,
+    search_path: Vec<PathBuf>,
+    simulator:   Option<Simulator>,
+    columns:     Vec<Column>,
+    // completed clock cycles, and whether the clock is between tick and tock
+    time:        usize,
+    ticked:      bool,
+    output:      Vec<String>,
+    output_file: Option<PathBuf>,
+    compare:     Option<Vec<String>>,
+    mismatch:    Option<Mismatch>,
+}
+
+impl TestScript {
+    /// `search_path` is searched for the parts of the loaded chip after the
+    /// directory of the chip
+    pub fn new(path: &Path, search_path: Vec<PathBuf>) -> Self {
+        Self {
+            dir: path.parent().map_or(PathBuf::new(), |v| v.to_path_buf()),
+            file: path.file_name().unwrap().to_string_lossy().into_owned(),
+            search_path,
+            simulator: None,
+            columns: vec![],
+            time: 0,
+            ticked: false,
+            output: vec![],
+            output_file: None,
+            compare: None,
+            mismatch: None,
+        }
+    }
+
+    pub fn run(mut self, source: &str) -> TestResult {
+        let tokens = tokenize(source, &self.file);
+        let mut position = 0;
+        let commands = parse_commands(&tokens, &mut position, &self.file);
+        if position < tokens.len() {
+            panic!(
+                "invalid: }}, file: {}, line: {}",
+                self.file, tokens[position].0
+            );
+        }
+
+        self.execute(&commands);
+
+        if let Some(path) = &self.output_file {
+            let mut content = self.output.join("\n");
+            content.push('\n');
+            save_file(content.as_bytes(), path).unwrap();
+        }
+        TestResult {
+            output_file: self.output_file,
+            compared:    self.compare.is_some(),
+            mismatch:    self.mismatch,
+        }
+    }
+
+    // false once the output differs from the compare file
+    fn execute(&mut self, commands: &[(usize, Command)]) -> bool {
+        for (line, command) in commands.iter() {
+            match command {
+                Command::Load(file) => {
+                    let mut library = ChipLibrary::new(self.search_path.clone());
+                    let chip = library.load_file(&self.dir.join(file));
+                    self.simulator = Some(Simulator::new(chip, &mut library));
+                    self.time = 0;
+                    self.ticked = false;
+                }
+                Command::OutputFile(file) => self.output_file = Some(self.dir.join(file)),
+                Command::CompareTo(file) => {
+                    let path = self.dir.join(file);
+                    let source = read_to_string(&path).unwrap_or_else(|_| {
+                        panic!("invalid: {}, no such file, line: {}", path.display(), line)
+                    });
+                    self.compare = Some(source.lines().map(|v| v.to_owned()).collect());
+                }
+                Command::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    let header = self
+                        .columns
+                        .iter()
+                        .map(|column| column.header())
+                        .collect::<Vec<String>>();
+                    if !self.write_line(format!("|{}|", header.join("|"))) {
+                        return false;
+                    }
+                }
+                Command::Set(target, value) => {
+                    let value = parse_value(value).unwrap_or_else(|| {
+                        panic!("invalid: {}, file: {}, line: {}", value, self.file, line)
+                    });
+                    let simulator = self.simulator(*line);
+                    match part_ref(target) {
+                        Some((part, address)) => simulator.poke(part, address, value & 0xffff),
+                        None => simulator.set(target, value),
+                    }
+                }
+                Command::Eval => self.simulator(*line).eval(),
+                Command::Tick => {
+                    self.simulator(*line).tick();
+                    self.ticked = true;
+                }
+                Command::Tock => {
+                    self.simulator(*line).tock();
+                    self.time += 1;
+                    self.ticked = false;
+                }
+                Command::Output => {
+                    let cells: Vec<String> = (0..self.columns.len())
+                        .map(|i| self.cell(i, *line))
+                        .collect();
+                    if !self.write_line(format!("|{}|", cells.join("|"))) {
+                        return false;
+                    }
+                }
+                Command::Echo(text) => println!("{}", text),
+                Command::ClearEcho => {}
+                Command::RomLoad(file) => {
+                    let rom = Emulator::load_rom(self.dir.join(file), None);
+                    self.simulator(*line).load_rom(&rom);
+                }
+                Command::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        if !self.execute(body) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn simulator(&mut self, line: usize) -> &mut Simulator {
+        let file = &self.file;
+        self.simulator
+            .as_mut()
+            .unwrap_or_else(|| panic!("invalid: no chip loaded, file: {}, line: {}", file, line))
+    }
+
+    // The i-th cell of an output line
+    fn cell(&self, i: usize, line: usize) -> String {
+        let column = &self.columns[i];
+        if column.name == "time" {
+            let time = format!("{}{}", self.time, if self.ticked { "+" } else { "" });
+            return column.pad(&time);
+        }
+
+        let simulator = self.simulator.as_ref().unwrap_or_else(|| {
+            panic!(
+                "invalid: no chip loaded, file: {}, line: {}",
+                self.file, line
+            )
+        });
+        let (value, bits) = match part_ref(&column.name) {
+            Some((part, address)) => (simulator.peek(part, address), 16),
+            None => {
+                let pin = simulator.chip().pin(&column.name).unwrap_or_else(|| {
+                    panic!(
+                        "invalid: {} is not a pin of {}, file: {}, line: {}",
+                        column.name,
+                        simulator.chip().name,
+                        self.file,
+                        line
+                    )
+                });
+                (simulator.get(&column.name), pin.width)
+            }
+        };
+        self.columns[i].cell(value, bits)
+    }
+
+    // Add a line to the output, comparing it to the same line of the compare
+    // file. false when they differ.
+    fn write_line(&mut self, line: String) -> bool {
+        self.output.push(line);
+        let Some(compare) = &self.compare else {
+            return true;
+        };
+
+        let number = self.output.len();
+        let found = &self.output[number - 1];
+        let expected = compare.get(number - 1).map_or("", |v| v.trim_end());
+        let expected_cells: Vec<&str> = expected.split('|').collect();
+        let found_cells: Vec<&str> = found.split('|').collect();
+
+        if expected_cells.len() != found_cells.len() {
+            self.mismatch = Some(Mismatch {
+                line:     number,
+                column:   0,
+                name:     String::new(),
+                expected: expected.to_owned(),
+                found:    found.clone(),
+            });
+            return false;
+        }
+        for (i, (expected, found)) in expected_cells.iter().zip(found_cells.iter()).enumerate() {
+            let wildcard = !expected.is_empty() && expected.chars().all(|c| c == '*');
+            if expected != found && !wildcard {
+                self.mismatch = Some(Mismatch {
+                    line:     number,
+                    column:   i,
+                    name:     self
+                        .columns
+                        .get(i.wrapping_sub(1))
+                        .map_or(String::new(), |v| v.name.clone()),
+                    expected: expected.trim().to_owned(),
+                    found:    found.trim().to_owned(),
+                });
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// `RAM16K[3]`, or `ARegister[]` for a register: a built-in part and an address
+fn part_ref(name: &str) -> Option<(&str, usize)> {
+    let (part, index) = name.split_once('[')?;
+    let index = index.strip_suffix(']')?;
+    if index.is_empty() {
+        return Some((part, 0));
+    }
+    Some((part, index.parse().ok()?))
+}
+
+// `%B0101`, `%X1F`, `%D-3` or a decimal number
+fn parse_value(value: &str) -> Option<u64> {
+    if let Some(digits) = value.strip_prefix("%B") {
+        u64::from_str_radix(digits, 2).ok()
+    } else if let Some(digits) = value.strip_prefix("%X") {
+        u64::from_str_radix(digits, 16).ok()
+    } else {
+        let digits = value.strip_prefix("%D").unwrap_or(value);
+        digits.parse::<i64>().ok().map(|v| v as u64)
+    }
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Symbol(char),
+}
+
+// (line number, token) of a script without its comments
+fn tokenize(source: &str, file: &str) -> Vec<(usize, Token)> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if ",;{}".contains(c) {
+            tokens.push((line, Token::Symbol(c)));
+            i += 1;
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\n' {
+                    panic!(
+                        "invalid: unterminated string, file: {}, line: {}",
+                        file, line
+                    );
+                }
+                i += 1;
+            }
+            tokens.push((line, Token::Text(chars[start..i].iter().collect())));
+            i += 1;
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && !",;{}\"".contains(chars[i]) {
+                i += 1;
+            }
+            tokens.push((line, Token::Word(chars[start..i].iter().collect())));
+        }
+    }
+
+    tokens
+}
+
+// Commands up to a `}` or the end of the script
+fn parse_commands(
+    tokens: &[(usize, Token)],
+    position: &mut usize,
+    file: &str,
+) -> Vec<(usize, Command)> {
+    let mut commands = vec![];
+    while let Some((line, token)) = tokens.get(*position) {
+        let line = *line;
+        if *token == Token::Symbol('}') {
+            break;
+        }
+
+        // the words of the command up to its separator
+        let mut words = vec![];
+        while let Some((_, token)) = tokens.get(*position) {
+            match token {
+                Token::Word(v) | Token::Text(v) => words.push(v.as_str()),
+                _ => break,
+            }
+            *position += 1;
+        }
+        let invalid = || -> ! {
+            panic!(
+                "invalid: {}, file: {}, line: {}",
+                words.join(" "),
+                file,
+                line
+            )
+        };
+
+        let command = match words[..] {
+            [] => invalid(),
+            ["load", file] => Command::Load(file.to_owned()),
+            ["output-file", file] => Command::OutputFile(file.to_owned()),
+            ["compare-to", file] => Command::CompareTo(file.to_owned()),
+            ["output-list", ref columns @ ..] => Command::OutputList(
+                columns
+                    .iter()
+                    .map(|v| Column::parse(v, file, line))
+                    .collect(),
+            ),
+            ["set", target, value] => Command::Set(target.to_owned(), value.to_owned()),
+            ["eval"] => Command::Eval,
+            ["tick"] => Command::Tick,
+            ["tock"] => Command::Tock,
+            ["output"] => Command::Output,
+            ["echo", text] => Command::Echo(text.to_owned()),
+            ["clear-echo"] => Command::ClearEcho,
+            ["ROM32K", "load", file] => Command::RomLoad(file.to_owned()),
+            ["repeat", count] => {
+                let count = count.parse().unwrap_or_else(|_| invalid());
+                if tokens.get(*position).map(|v| &v.1) != Some(&Token::Symbol('{')) {
+                    invalid();
+                }
+                *position += 1;
+                let body = parse_commands(tokens, position, file);
+                if tokens.get(*position).map(|v| &v.1) != Some(&Token::Symbol('}')) {
+                    panic!("invalid: repeat without }}, file: {}, line: {}", file, line);
+                }
+                *position += 1;
+                commands.push((line, Command::Repeat(count, body)));
+                continue;
+            }
+            _ => invalid(),
+        };
+        commands.push((line, command));
+
+        match tokens.get(*position) {
+            Some((_, Token::Symbol(',' | ';'))) => *position += 1,
+            Some((_, Token::Symbol('}'))) | None => {}
+            Some((line, _)) => panic!("invalid: expected , or ;, file: {}, line: {}", file, line),
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const HDL: &str = "CHIP Acc {
+    IN in[16], load;
+    OUT out[16], zero;
+    PARTS:
+    Add16(a=in, b=x, out=sum);
+    Register(in=sum, load=load, out=x, out=out, out[0..7]=low, out[8..15]=high);
+    Or8Way(in=low, out=lowSet);
+    Or8Way(in=high, out=highSet);
+    Or(a=lowSet, b=highSet, out=nonZero);
+    Not(in=nonZero, out=zero);
+}
+";
+
+    const TST: &str = "load Acc.hdl, output-file Acc.out, compare-to Acc.cmp,
+output-list time%S1.4.1 in%D1.6.1 load%B1.1.1 out%X1.4.1 zero%B1.1.1 Register[]%D1.6.1;
+
+set in 5, set load 1, eval, output;
+tick, output, tock, output;
+repeat 2 { tick, tock, output; }
+set in %B1111111111110001, set load 1, tick, tock, output;
+set Register[] 7, set load 0, eval, output;
+";
+
+    const OUT: &str = "\
+|time |   in   |loa| out  |zer|Register|
+| 0   |      5 | 1 | 0000 | 1 |      0 |
+| 0+  |      5 | 1 | 0000 | 1 |      0 |
+| 1   |      5 | 1 | 0005 | 0 |      5 |
+| 2   |      5 | 1 | 000A | 0 |     10 |
+| 3   |      5 | 1 | 000F | 0 |     15 |
+| 4   |    -15 | 1 | 0000 | 1 |      0 |
+| 4   |    -15 | 0 | 0007 | 0 |      7 |
+";
+
+    // Run the script in a directory of its own with the chip and the compare
+    // file, returning the result and the output file
+    fn run(name: &str, cmp: &str) -> (TestResult, String) {
+        let dir = std::env::temp_dir().join(format!(
+            "nand2tetris-rs-tst-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Acc.hdl"), HDL).unwrap();
+        fs::write(dir.join("Acc.cmp"), cmp).unwrap();
+
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/hdl");
+        let result = TestScript::new(&dir.join("Acc.tst"), vec![data]).run(TST);
+        let output = fs::read_to_string(dir.join("Acc.out")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        (result, output)
+    }
+
+    #[test]
+    fn set_eval_clock_and_output() {
+        let (result, output) = run("pass", OUT);
+        assert_eq!(output, OUT);
+        assert!(result.compared);
+        assert!(result.mismatch.is_none());
+        assert!(result.output_file.unwrap().ends_with("Acc.out"));
+    }
+
+    #[test]
+    fn report_the_first_mismatch() {
+        // cells of `*` match anything
+        let cmp = OUT
+            .replace(
+                "| 1   |      5 | 1 | 0005 | 0 |",
+                "|*****|      5 | 1 | 0005 |***|",
+            )
+            .replace("| 000A | 0 |", "| 000B | 0 |")
+            .replace("000F", "0010");
+        let (result, output) = run("fail", &cmp);
+
+        // the output stops at the first difference
+        assert_eq!(output.lines().count(), 5);
+        let mismatch = result.mismatch.unwrap();
+        assert_eq!(
+            mismatch.to_string(),
+            "comparison failure at line 5, column 4 (out): expected \"000B\", found \"000A\""
+        );
+
+        let (result, _) = run(
+            "short",
+            &OUT.replace("| 0+  |      5 | 1 | 0000 | 1 |", "|"),
+        );
+        let mismatch = result.mismatch.unwrap();
+        assert_eq!((mismatch.line, mismatch.column), (3, 0));
+    }
+}
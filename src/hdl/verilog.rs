@@ -0,0 +1,947 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::hdl::builtin::declaration;
+use crate::hdl::parser::{Body, Chip, Part, PinRef};
+use crate::hdl::simulator::{ChipLibrary, Simulator};
+
+// Verilog keywords that may be HDL names, like the internal pin `and`
+const KEYWORDS: [&str; 40] = [
+    "always",
+    "and",
+    "assign",
+    "begin",
+    "buf",
+    "case",
+    "default",
+    "else",
+    "end",
+    "endcase",
+    "endfunction",
+    "endmodule",
+    "endtask",
+    "for",
+    "function",
+    "if",
+    "initial",
+    "inout",
+    "input",
+    "integer",
+    "module",
+    "nand",
+    "negedge",
+    "nor",
+    "not",
+    "or",
+    "output",
+    "parameter",
+    "posedge",
+    "reg",
+    "repeat",
+    "task",
+    "time",
+    "tri",
+    "wait",
+    "while",
+    "wire",
+    "wor",
+    "xnor",
+    "xor",
+];
+
+// An HDL name as a Verilog identifier, escaped when it is a keyword
+fn ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("\\{} ", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+// `[15:0] ` for a bus, nothing for a single bit
+fn range(width: usize) -> String {
+    if width == 1 {
+        String::new()
+    } else {
+        format!("[{}:0] ", width - 1)
+    }
+}
+
+// `name`, `name[3]` or `name[7:0]` of a signal `width` bits wide
+fn slice(name: &str, width: usize, bits: Option<(usize, usize)>) -> String {
+    match bits {
+        _ if width == 1 => ident(name),
+        Some((first, last)) if first == last => format!("{}[{}]", ident(name), first),
+        Some((first, last)) => format!("{}[{}:{}]", ident(name), last, first),
+        None => ident(name),
+    }
+}
+
+/// The Verilog modules of a chip and of the chips it is made of, each module
+/// after the modules it uses. Buses and sub-buses keep their bits, and the
+/// modules containing clocked chips take a `clk` input.
+///
+/// Built-in chips are instances of modules of the same name and pins, whose
+/// synthesizable definitions follow when `primitives`. Without them, the
+/// toolchain must provide them.
+pub fn export(chip: &Chip, library: &mut ChipLibrary, primitives: bool) -> Vec<u8> {
+    let mut exporter = Exporter::new(library);
+    exporter.module(chip);
+
+    if primitives {
+        for name in exporter.builtins.clone() {
+            writeln!(&mut exporter.output, "{}", primitive(&name)).unwrap();
+        }
+    }
+    exporter.output
+}
+
+/// Whether a chip contains clocked chips, its module taking a `clk` input
+pub fn is_clocked(chip: &Chip, library: &mut ChipLibrary) -> bool {
+    let mut exporter = Exporter::new(library);
+    exporter.is_clocked(chip)
+}
+
+struct Exporter<'a> {
+    library:  &'a mut ChipLibrary,
+    output:   Vec<u8>,
+    // chips whose module is written
+    done:     HashSet<String>,
+    clocked:  HashMap<String, bool>,
+    // the built-in chips used, in order of use
+    builtins: Vec<String>,
+}
+
+impl<'a> Exporter<'a> {
+    fn new(library: &'a mut ChipLibrary) -> Self {
+        Self {
+            library,
+            output: vec![],
+            done: HashSet::new(),
+            clocked: HashMap::new(),
+            builtins: vec![],
+        }
+    }
+
+    fn part_chip(&mut self, part: &Part) -> Rc<Chip> {
+        self.library
+            .get(&part.name)
+            .unwrap_or_else(|| panic!("invalid: unknown chip {}, line: {}", part.name, part.line))
+    }
+
+    fn is_clocked(&mut self, chip: &Chip) -> bool {
+        if let Some(clocked) = self.clocked.get(&chip.name) {
+            return *clocked;
+        }
+        let clocked = match &chip.body {
+            Body::Builtin { name, .. } => match declaration(name).map(|v| v.body) {
+                Some(Body::Builtin { clocked, .. }) => !clocked.is_empty(),
+                _ => panic!("invalid: unknown built-in chip {}", name),
+            },
+            Body::Parts(parts) => parts.iter().any(|part| {
+                let part_chip = self.part_chip(part);
+                self.is_clocked(&part_chip)
+            }),
+        };
+        self.clocked.insert(chip.name.clone(), clocked);
+        clocked
+    }
+
+    fn module(&mut self, chip: &Chip) {
+        if !self.done.insert(chip.name.clone()) {
+            return;
+        }
+        let parts = match &chip.body {
+            Body::Builtin { name, .. } => {
+                if !self.builtins.contains(name) {
+                    self.builtins.push(name.clone());
+                }
+                return;
+            }
+            Body::Parts(parts) => parts,
+        };
+
+        let part_chips: Vec<Rc<Chip>> = parts.iter().map(|part| self.part_chip(part)).collect();
+        for part_chip in part_chips.iter() {
+            self.module(part_chip);
+        }
+
+        // the internal pins, which are outputs of parts
+        let mut widths: HashMap<&str, usize> = HashMap::new();
+        let mut internal = vec![];
+        for (part, part_chip) in parts.iter().zip(part_chips.iter()) {
+            for connection in part.connections.iter() {
+                let name = connection.value.name.as_str();
+                if part_chip.is_input(&connection.pin.name) || chip.pin(name).is_some() {
+                    continue;
+                }
+                let width = connection
+                    .pin
+                    .width(part_chip.pin(&connection.pin.name).unwrap().width);
+                if widths.insert(name, width).is_none() {
+                    internal.push((name, width));
+                }
+            }
+        }
+        for pin in chip.inputs.iter().chain(chip.outputs.iter()) {
+            widths.insert(&pin.name, pin.width);
+        }
+
+        let mut wires = vec![];
+        let mut instances = vec![];
+        let mut assigns = vec![];
+        for (i, (part, part_chip)) in parts.iter().zip(part_chips.iter()).enumerate() {
+            let mut ports = vec![];
+            if self.is_clocked(part_chip) {
+                ports.push(".clk(clk)".to_owned());
+            }
+
+            for pin in part_chip.inputs.iter() {
+                // (first, last, value) of the connected bits, highest first
+                let mut segments: Vec<(usize, usize, String)> = part
+                    .connections
+                    .iter()
+                    .filter(|connection| connection.pin.name == pin.name)
+                    .map(|connection| {
+                        let (first, last) = connection.pin.range.unwrap_or((0, pin.width - 1));
+                        let value = value(&connection.value, last - first + 1, &widths);
+                        (first, last, value)
+                    })
+                    .collect();
+                segments.sort_by_key(|segment| Reverse(segment.0));
+
+                // unconnected bits are false
+                let mut bits = vec![];
+                let mut next = pin.width;
+                for (first, last, value) in segments {
+                    if last + 1 < next {
+                        bits.push(format!("{}'b0", next - last - 1));
+                    }
+                    bits.push(value);
+                    next = first;
+                }
+                if next > 0 {
+                    bits.push(format!("{}'b0", next));
+                }
+                let value = match &bits[..] {
+                    [value] => value.clone(),
+                    _ => format!("{{{}}}", bits.join(", ")),
+                };
+                ports.push(format!(".{}({})", ident(&pin.name), value));
+            }
+
+            for pin in part_chip.outputs.iter() {
+                let connections: Vec<(&PinRef, &PinRef)> = part
+                    .connections
+                    .iter()
+                    .filter(|connection| connection.pin.name == pin.name)
+                    .map(|connection| (&connection.pin, &connection.value))
+                    .collect();
+                match connections[..] {
+                    [] => {}
+                    // the whole pin to a signal or sub-bus of the same width
+                    [(part_pin, value)] if part_pin.range.is_none() => {
+                        let width = widths[value.name.as_str()];
+                        ports.push(format!(
+                            ".{}({})",
+                            ident(&pin.name),
+                            slice(&value.name, width, value.range)
+                        ))
+                    }
+                    // through a wire of the whole pin
+                    _ => {
+                        let wire = format!("u{}_{}", i, pin.name);
+                        wires.push(format!("    wire {}{};", range(pin.width), wire));
+                        ports.push(format!(".{}({})", ident(&pin.name), wire));
+                        for (part_pin, value) in connections {
+                            assigns.push(format!(
+                                "    assign {} = {};",
+                                slice(&value.name, widths[value.name.as_str()], value.range),
+                                slice(&wire, pin.width, part_pin.range)
+                            ));
+                        }
+                    }
+                }
+            }
+
+            instances.push(format!(
+                "    {} u{} ({});",
+                ident(module_name(part_chip)),
+                i,
+                ports.join(", ")
+            ));
+        }
+
+        let clocked = self.is_clocked(chip);
+        writeln!(&mut self.output, "{}", header(chip, clocked, false)).unwrap();
+        for (name, width) in internal {
+            writeln!(
+                &mut self.output,
+                "    wire {}{};",
+                range(width),
+                ident(name)
+            )
+            .unwrap();
+        }
+        for line in wires.iter().chain(instances.iter()).chain(assigns.iter()) {
+            writeln!(&mut self.output, "{}", line).unwrap();
+        }
+        writeln!(&mut self.output, "endmodule\n").unwrap();
+    }
+}
+
+// The module of a chip, the built-in one for a BUILTIN chip
+fn module_name(chip: &Chip) -> &str {
+    match &chip.body {
+        Body::Builtin { name, .. } => name,
+        Body::Parts(_) => &chip.name,
+    }
+}
+
+// The value connected to `width` bits of an input pin
+fn value(value: &PinRef, width: usize, widths: &HashMap<&str, usize>) -> String {
+    match value.name.as_str() {
+        "true" if width == 1 => "1'b1".to_owned(),
+        "true" => format!("{{{}{{1'b1}}}}", width),
+        "false" => format!("{}'b0", width),
+        name => slice(name, widths[name], value.range),
+    }
+}
+
+// `module Name (` and the ports, the outputs being `reg` when `registers`
+fn header(chip: &Chip, clocked: bool, registers: bool) -> String {
+    let mut ports = vec![];
+    if clocked {
+        ports.push("    input clk".to_owned());
+    }
+    for pin in chip.inputs.iter() {
+        ports.push(format!(
+            "    input {}{}",
+            range(pin.width),
+            ident(&pin.name)
+        ));
+    }
+    for pin in chip.outputs.iter() {
+        ports.push(format!(
+            "    output {}{}{}",
+            if registers { "reg " } else { "" },
+            range(pin.width),
+            ident(&pin.name)
+        ));
+    }
+    format!(
+        "module {} (\n{}\n);",
+        ident(module_name(chip)),
+        ports.join(",\n")
+    )
+}
+
+// The definition of a built-in chip
+fn primitive(name: &str) -> String {
+    let chip = declaration(name).unwrap();
+    let ram = |size: usize| {
+        format!(
+            "    reg [15:0] memory [0:{last}];
+    integer i;
+    initial for (i = 0; i <= {last}; i = i + 1) memory[i] = 16'b0;
+    always @(posedge clk)
+        if (load) memory[address] <= in;
+    assign out = memory[address];",
+            last = size - 1
+        )
+    };
+
+    let (registers, body) = match name {
+        "Nand" => (false, "    assign out = ~(a & b);".to_owned()),
+        "DFF" => (
+            true,
+            "    initial out = 1'b0;
+    always @(posedge clk)
+        out <= in;"
+                .to_owned(),
+        ),
+        "Bit" | "Register" | "ARegister" | "DRegister" => (
+            true,
+            format!(
+                "    initial out = {}'b0;
+    always @(posedge clk)
+        if (load) out <= in;",
+                chip.outputs[0].width
+            ),
+        ),
+        "PC" => (
+            true,
+            "    initial out = 16'b0;
+    always @(posedge clk)
+        if (reset) out <= 16'b0;
+        else if (load) out <= in;
+        else if (inc) out <= out + 16'd1;"
+                .to_owned(),
+        ),
+        "RAM8" => (false, ram(8)),
+        "RAM64" => (false, ram(64)),
+        "RAM512" => (false, ram(512)),
+        "RAM4K" => (false, ram(4096)),
+        "RAM16K" => (false, ram(16384)),
+        "Screen" => (false, ram(8192)),
+        "ROM32K" => (
+            false,
+            "    // the program, a *.hack file of the assembler
+    parameter PROGRAM = \"program.hack\";
+    reg [15:0] memory [0:32767];
+    integer i;
+    initial begin
+        for (i = 0; i <= 32767; i = i + 1) memory[i] = 16'b0;
+        $readmemb(PROGRAM, memory);
+    end
+    assign out = memory[address];"
+                .to_owned(),
+        ),
+        "Keyboard" => (
+            false,
+            "    // the scan code of the key pressed, to connect to a keyboard
+    assign out = 16'b0;"
+                .to_owned(),
+        ),
+        "Memory" => (
+            false,
+            "    reg [15:0] ram [0:16383];
+    reg [15:0] screen [0:8191];
+    // the scan code of the key pressed, to connect to a keyboard
+    wire [15:0] keyboard = 16'b0;
+    integer i;
+    initial begin
+        for (i = 0; i <= 16383; i = i + 1) ram[i] = 16'b0;
+        for (i = 0; i <= 8191; i = i + 1) screen[i] = 16'b0;
+    end
+    always @(posedge clk)
+        if (load && !address[14]) ram[address[13:0]] <= in;
+        else if (load && address[14:13] == 2'b10) screen[address[12:0]] <= in;
+    assign out = !address[14] ? ram[address[13:0]]
+        : address[14:13] == 2'b10 ? screen[address[12:0]]
+        : address == 15'd24576 ? keyboard
+        : 16'b0;"
+                .to_owned(),
+        ),
+        _ => panic!("invalid: unknown built-in chip {}", name),
+    };
+
+    let Body::Builtin { clocked, .. } = &chip.body else {
+        unreachable!()
+    };
+    format!(
+        "{}\n{}\nendmodule\n",
+        header(&chip, !clocked.is_empty(), registers),
+        body
+    )
+}
+
+// xorshift64, for reproducible test vectors
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// A Verilog testbench of the module of `chip`, checking its outputs against
+/// those of the simulator for `vectors` random input vectors, or for all of
+/// them when there are no more. When the chip is clocked, the outputs are
+/// checked again after a clock cycle of each vector.
+pub fn testbench(chip: Rc<Chip>, library: &mut ChipLibrary, vectors: usize) -> Vec<u8> {
+    let clocked = is_clocked(&chip, library);
+    let mut simulator = Simulator::new(chip.clone(), library);
+    let mut output = vec![];
+
+    let bits: usize = chip.inputs.iter().map(|pin| pin.width).sum();
+    let exhaustive = bits < 64 && (1u64 << bits) <= vectors as u64;
+    let vectors = if exhaustive { 1 << bits } else { vectors };
+
+    writeln!(&mut output, "`timescale 1ns / 1ps\n").unwrap();
+    writeln!(
+        &mut output,
+        "// Checks {} against {} input vectors run through the HDL simulator",
+        chip.name, vectors
+    )
+    .unwrap();
+    writeln!(&mut output, "module {}_tb;", chip.name).unwrap();
+    if clocked {
+        writeln!(&mut output, "    reg clk = 1'b0;").unwrap();
+    }
+    for pin in chip.inputs.iter() {
+        writeln!(
+            &mut output,
+            "    reg {}{};",
+            range(pin.width),
+            ident(&pin.name)
+        )
+        .unwrap();
+    }
+    for pin in chip.outputs.iter() {
+        writeln!(
+            &mut output,
+            "    wire {}{};",
+            range(pin.width),
+            ident(&pin.name)
+        )
+        .unwrap();
+    }
+    writeln!(&mut output, "    integer errors = 0;\n").unwrap();
+
+    let mut ports = vec![];
+    if clocked {
+        ports.push(".clk(clk)".to_owned());
+    }
+    for pin in chip.inputs.iter().chain(chip.outputs.iter()) {
+        ports.push(format!(".{}({})", ident(&pin.name), ident(&pin.name)));
+    }
+    writeln!(
+        &mut output,
+        "    {} dut ({});\n",
+        ident(module_name(&chip)),
+        ports.join(", ")
+    )
+    .unwrap();
+
+    // all the outputs as one bus, the first one in the highest bits
+    let names: Vec<String> = chip.outputs.iter().map(|pin| ident(&pin.name)).collect();
+    let outputs = format!("{{{}}}", names.join(", "));
+    let width: usize = chip.outputs.iter().map(|pin| pin.width).sum();
+    let expected = |simulator: &Simulator| -> String {
+        let bits: String = chip
+            .outputs
+            .iter()
+            .map(|pin| format!("{:0width$b}", simulator.get(&pin.name), width = pin.width))
+            .collect();
+        format!("{}'b{}", width, bits)
+    };
+    let check = |output: &mut Vec<u8>, expected: String, when: String| {
+        if width == 0 {
+            return;
+        }
+        writeln!(output, "        if ({} !== {}) begin", outputs, expected).unwrap();
+        writeln!(
+            output,
+            "            $display(\"{}: {} = %b, expected %b\", {}, {});",
+            when,
+            outputs.replace('\\', "\\\\"),
+            outputs,
+            expected
+        )
+        .unwrap();
+        writeln!(output, "            errors = errors + 1;\n        end").unwrap();
+    };
+
+    writeln!(&mut output, "    initial begin").unwrap();
+    let mut random = 0x2545f4914f6cdd1d;
+    for vector in 0..vectors {
+        let mut assignments = vec![];
+        let mut shift = 0;
+        for pin in chip.inputs.iter() {
+            let value = if exhaustive {
+                (vector as u64 >> shift) & ((1 << pin.width) - 1)
+            } else if pin.width == 64 {
+                next_random(&mut random)
+            } else {
+                next_random(&mut random) & ((1 << pin.width) - 1)
+            };
+            shift += pin.width;
+            simulator.set(&pin.name, value);
+            assignments.push(format!(
+                "{} = {}'b{:0width$b};",
+                ident(&pin.name),
+                pin.width,
+                value,
+                width = pin.width
+            ));
+        }
+
+        simulator.eval();
+        writeln!(&mut output, "        {}", assignments.join(" ")).unwrap();
+        writeln!(&mut output, "        #1;").unwrap();
+        check(
+            &mut output,
+            expected(&simulator),
+            format!("vector {}", vector),
+        );
+
+        if clocked {
+            simulator.tick();
+            simulator.tock();
+            writeln!(&mut output, "        clk = 1'b1; #1; clk = 1'b0;").unwrap();
+            check(
+                &mut output,
+                expected(&simulator),
+                format!("vector {} after the clock", vector),
+            );
+        }
+    }
+    writeln!(&mut output, "        $display(\"%0d errors\", errors);").unwrap();
+    writeln!(&mut output, "        $finish;").unwrap();
+    writeln!(&mut output, "    end").unwrap();
+    writeln!(&mut output, "endmodule").unwrap();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    // The modules written by `export` without primitives: ports, wires,
+    // instances with named ports and assigns of bits, sub-buses,
+    // concatenations and constants
+    struct Module {
+        ports:     Vec<(String, usize)>,
+        wires:     Vec<(String, usize)>,
+        instances: Vec<(String, Vec<(String, Expr)>)>,
+        assigns:   Vec<(Expr, Expr)>,
+    }
+
+    enum Expr {
+        // name and (last, first) bits
+        Signal(String, Option<(usize, usize)>),
+        // bits, the highest first
+        Constant(String),
+        Concat(Vec<Expr>),
+        Repeat(usize, Box<Expr>),
+    }
+
+    fn tokenize(source: &str) -> Vec<String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            match chars[i] {
+                c if c.is_whitespace() => {
+                    i += 1;
+                    continue;
+                }
+                '\\' => {
+                    while !chars[i].is_whitespace() {
+                        i += 1;
+                    }
+                    tokens.push(chars[start + 1..i].iter().collect());
+                    continue;
+                }
+                c if c.is_ascii_alphanumeric() || c == '_' || c == '\'' => {
+                    while chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '\'' {
+                        i += 1;
+                    }
+                }
+                _ => i += 1,
+            }
+            tokens.push(chars[start..i].iter().collect());
+        }
+        tokens
+    }
+
+    struct Parser {
+        tokens:   Vec<String>,
+        position: usize,
+    }
+
+    impl Parser {
+        fn next(&mut self) -> String {
+            self.position += 1;
+            self.tokens[self.position - 1].clone()
+        }
+
+        fn expect(&mut self, token: &str) {
+            assert_eq!(self.next(), token);
+        }
+
+        fn peek(&self, token: &str) -> bool {
+            self.tokens.get(self.position).is_some_and(|v| v == token)
+        }
+
+        fn number(&mut self) -> usize {
+            self.next().parse().unwrap()
+        }
+
+        // `[last:first]` or `[bit]`
+        fn bits(&mut self) -> Option<(usize, usize)> {
+            if !self.peek("[") {
+                return None;
+            }
+            self.expect("[");
+            let last = self.number();
+            let mut first = last;
+            if self.peek(":") {
+                self.expect(":");
+                first = self.number();
+            }
+            self.expect("]");
+            Some((last, first))
+        }
+
+        fn width(&mut self) -> usize {
+            self.bits().map_or(1, |(last, first)| last - first + 1)
+        }
+
+        fn expr(&mut self) -> Expr {
+            let token = self.next();
+            if token == "{" {
+                if !self.peek("{") && self.tokens[self.position + 1] == "{" {
+                    let count = self.number();
+                    self.expect("{");
+                    let expr = self.expr();
+                    self.expect("}");
+                    self.expect("}");
+                    return Expr::Repeat(count, Box::new(expr));
+                }
+                let mut exprs = vec![self.expr()];
+                while self.peek(",") {
+                    self.expect(",");
+                    exprs.push(self.expr());
+                }
+                self.expect("}");
+                return Expr::Concat(exprs);
+            }
+            match token.split_once("'b") {
+                Some((width, bits)) => {
+                    let width: usize = width.parse().unwrap();
+                    Expr::Constant(format!("{:0>width$}", bits, width = width))
+                }
+                None => Expr::Signal(token, self.bits()),
+            }
+        }
+
+        fn module(&mut self) -> (String, Module) {
+            self.expect("module");
+            let name = self.next();
+            let mut module = Module {
+                ports:     vec![],
+                wires:     vec![],
+                instances: vec![],
+                assigns:   vec![],
+            };
+            self.expect("(");
+            while !self.peek(")") {
+                self.next();
+                let width = self.width();
+                module.ports.push((self.next(), width));
+                if self.peek(",") {
+                    self.expect(",");
+                }
+            }
+            self.expect(")");
+            self.expect(";");
+
+            loop {
+                match self.next().as_str() {
+                    "endmodule" => return (name, module),
+                    "wire" => {
+                        let width = self.width();
+                        module.wires.push((self.next(), width));
+                    }
+                    "assign" => {
+                        let lhs = self.expr();
+                        self.expect("=");
+                        module.assigns.push((lhs, self.expr()));
+                    }
+                    part => {
+                        self.next();
+                        self.expect("(");
+                        let mut ports = vec![];
+                        while !self.peek(")") {
+                            self.expect(".");
+                            let port = self.next();
+                            self.expect("(");
+                            ports.push((port, self.expr()));
+                            self.expect(")");
+                            if self.peek(",") {
+                                self.expect(",");
+                            }
+                        }
+                        self.expect(")");
+                        module.instances.push((part.to_owned(), ports));
+                    }
+                }
+                self.expect(";");
+            }
+        }
+    }
+
+    // The modules flattened to Nand gates, with nets joined by ports and
+    // assigns, 0 and 1 being the constants
+    struct Netlist {
+        modules: HashMap<String, Module>,
+        parent:  Vec<usize>,
+        nands:   Vec<[usize; 3]>,
+    }
+
+    impl Netlist {
+        fn new_nets(&mut self, width: usize) -> Vec<usize> {
+            let start = self.parent.len();
+            self.parent.extend(start..start + width);
+            (start..start + width).collect()
+        }
+
+        fn find(&mut self, net: usize) -> usize {
+            let parent = self.parent[net];
+            if parent == net {
+                return net;
+            }
+            let root = self.find(parent);
+            self.parent[net] = root;
+            root
+        }
+
+        fn join(&mut self, a: usize, b: usize) {
+            let (a, b) = (self.find(a), self.find(b));
+            // the constants stay roots
+            self.parent[a.max(b)] = a.min(b);
+        }
+
+        // the nets of an expression, bit 0 first
+        fn nets(&self, expr: &Expr, signals: &HashMap<String, Vec<usize>>) -> Vec<usize> {
+            match expr {
+                Expr::Signal(name, None) => signals[name].clone(),
+                Expr::Signal(name, Some((last, first))) => signals[name][*first..=*last].to_vec(),
+                Expr::Constant(bits) => bits.chars().rev().map(|v| (v == '1') as usize).collect(),
+                Expr::Concat(exprs) => exprs
+                    .iter()
+                    .rev()
+                    .flat_map(|expr| self.nets(expr, signals))
+                    .collect(),
+                Expr::Repeat(count, expr) => self.nets(expr, signals).repeat(*count),
+            }
+        }
+
+        fn instantiate(&mut self, name: &str, mut signals: HashMap<String, Vec<usize>>) {
+            if name == "Nand" {
+                self.nands
+                    .push([signals["a"][0], signals["b"][0], signals["out"][0]]);
+                return;
+            }
+            let module = self.modules.remove(name).unwrap();
+            // unconnected outputs are left out of the instance
+            for (port, width) in module.ports.iter() {
+                if !signals.contains_key(port) {
+                    let nets = self.new_nets(*width);
+                    signals.insert(port.clone(), nets);
+                }
+            }
+            for (wire, width) in module.wires.iter() {
+                let nets = self.new_nets(*width);
+                signals.insert(wire.clone(), nets);
+            }
+            for (part, ports) in module.instances.iter() {
+                let ports = ports
+                    .iter()
+                    .map(|(port, expr)| (port.clone(), self.nets(expr, &signals)))
+                    .collect();
+                self.instantiate(part, ports);
+            }
+            for (lhs, rhs) in module.assigns.iter() {
+                let nets = self.nets(lhs, &signals).into_iter();
+                for (a, b) in nets.zip(self.nets(rhs, &signals)) {
+                    self.join(a, b);
+                }
+            }
+            self.modules.insert(name.to_owned(), module);
+        }
+
+        fn eval(&mut self, values: &mut [bool]) {
+            let nands: Vec<[usize; 3]> = self
+                .nands
+                .clone()
+                .iter()
+                .map(|nand| nand.map(|net| self.find(net)))
+                .collect();
+            loop {
+                let mut changed = false;
+                for [a, b, out] in nands.iter() {
+                    let value = !(values[*a] && values[*b]);
+                    changed |= values[*out] != value;
+                    values[*out] = value;
+                }
+                if !changed {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Evaluate the exported modules of a chip and compare their outputs to
+    // those of the simulator for `vectors` random input vectors
+    fn compare(file: &str, vectors: usize) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/hdl");
+        let mut library = ChipLibrary::new(vec![]);
+        let chip = library.load_file(&dir.join(file));
+        let verilog = String::from_utf8(export(&chip, &mut library, false)).unwrap();
+        let mut simulator = Simulator::new(chip.clone(), &mut library);
+
+        let mut parser = Parser {
+            tokens:   tokenize(&verilog),
+            position: 0,
+        };
+        let mut netlist = Netlist {
+            modules: HashMap::new(),
+            parent:  vec![0, 1],
+            nands:   vec![],
+        };
+        while parser.position < parser.tokens.len() {
+            let (name, module) = parser.module();
+            netlist.modules.insert(name, module);
+        }
+        let mut pins = HashMap::new();
+        for (name, width) in netlist.modules[&chip.name].ports.clone() {
+            pins.insert(name, netlist.new_nets(width));
+        }
+        netlist.instantiate(&chip.name, pins.clone());
+
+        let mut random = 0x2545f4914f6cdd1d;
+        for _ in 0..vectors {
+            let mut values = vec![false; netlist.parent.len()];
+            values[1] = true;
+            for pin in chip.inputs.iter() {
+                let value = next_random(&mut random) & ((1 << pin.width) - 1);
+                simulator.set(&pin.name, value);
+                for (i, net) in pins[&pin.name].iter().enumerate() {
+                    let net = netlist.find(*net);
+                    values[net] = (value >> i) & 1 != 0;
+                }
+            }
+            simulator.eval();
+            netlist.eval(&mut values);
+
+            for pin in chip.outputs.iter() {
+                let value = pins[&pin.name]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, net)| (values[netlist.find(*net)] as u64) << i)
+                    .sum::<u64>();
+                assert_eq!(
+                    value,
+                    simulator.get(&pin.name),
+                    "{} of {}",
+                    pin.name,
+                    chip.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sub_buses() {
+        compare("SubBus.hdl", 64);
+    }
+
+    #[test]
+    fn alu() {
+        compare("ALU.hdl", 200);
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(ident("and"), "\\and ");
+        assert_eq!(slice("and", 4, Some((1, 2))), "\\and [2:1]");
+        assert_eq!(slice("x", 1, Some((0, 0))), "x");
+    }
+}
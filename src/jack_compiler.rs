@@ -1,26 +1,59 @@
-use std::path::PathBuf;
-
+use crate::ast::Class;
 use crate::jack_parser::*;
 use crate::jack_tokenizer::JackTokenizer;
-use crate::vm_writer::VmWriter;
+use crate::optimizer::{resolve_constants, Optimizer};
+use crate::vm_writer::{DebugInfo, VmWriter};
 
-pub fn compile_to_vm(path: PathBuf) {
-    let mut tokenizer = JackTokenizer::new(path.clone());
-    tokenizer.run();
+/// Compile the source code of Jack classes into their VM writers, in the same
+/// order. All classes are parsed before any code is generated so that
+/// `Class.NAME` constants can be resolved across classes.
+pub fn compile_to_vm(
+    sources: &[&str],
+    optimize: bool,
+    debug_info: Option<DebugInfo>,
+) -> Result<Vec<VmWriter>, String> {
+    let classes = sources
+        .iter()
+        .map(|source| parse(source))
+        .collect::<Result<Vec<Class>, String>>()?;
 
-    let mut parser = JackParser::new(tokenizer.tokens());
-    parser.run();
+    let constants = resolve_constants(&classes.iter().collect::<Vec<_>>())?;
+
+    let mut vm_writers = vec![];
+    for mut ast in classes.into_iter() {
+        if optimize {
+            let mut optimizer = Optimizer::new(ast, constants.clone());
+            optimizer.run();
+            ast = optimizer.ast();
+        }
+
+        let mut vm_writer = VmWriter::new(ast, constants.clone(), debug_info);
+        vm_writer.run()?;
+        vm_writers.push(vm_writer);
+    }
+
+    Ok(vm_writers)
+}
+
+/// Compile the source code of a single class, returning the class name and
+/// its VM commands
+pub fn compile_source(source: &str) -> Result<(String, Vec<u8>), String> {
+    let ast = parse(source)?;
+    let constants = resolve_constants(&[&ast])?;
+    let name = ast.name.clone();
 
-    let mut vm_writer = VmWriter::new(parser.ast());
-    vm_writer.run();
+    let mut vm_writer = VmWriter::new(ast, constants, None);
+    vm_writer.run()?;
 
-    let mut dst_path = PathBuf::from(path.parent().unwrap());
-    dst_path.push(format!(
-        "output/{}",
-        path.file_name().unwrap().to_str().unwrap()
-    ));
-    dst_path.set_extension("vm");
-    println!("\noutput: {}", dst_path.to_str().unwrap());
+    Ok((name, vm_writer.output()))
+}
 
-    vm_writer.save_file(dst_path);
+/// Parse the source code of a single class into its AST
+pub fn parse(source: &str) -> Result<Class, String> {
+    let mut tokenizer = JackTokenizer::from_source(source);
+    tokenizer.run()?;
+
+    let mut parser = JackParser::new(tokenizer.tokens());
+    parser.run()?;
+    Ok(parser.ast())
 }
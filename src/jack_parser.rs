@@ -22,20 +22,15 @@ pub struct JackParser {
 
 impl JackParser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        CLASS_DEC.set(HashSet::from(["static", "field"])).unwrap();
+        CLASS_DEC.get_or_init(|| HashSet::from(["static", "field", "const", "enum"]));
 
-        FUNC_DEC
-            .set(HashSet::from(["constructor", "function", "method"]))
-            .unwrap();
+        FUNC_DEC.get_or_init(|| HashSet::from(["constructor", "function", "method"]));
 
-        STATEMENTS
-            .set(HashSet::from(["let", "if", "while", "do", "return"]))
-            .unwrap();
+        STATEMENTS.get_or_init(|| HashSet::from(["let", "if", "while", "do", "return"]));
 
-        OP.set(HashSet::from(['+', '-', '*', '/', '&', '|', '<', '>', '=']))
-            .unwrap();
+        OP.get_or_init(|| HashSet::from(['+', '-', '*', '/', '&', '|', '<', '>', '=']));
 
-        UNARY_OP.set(HashSet::from(['-', '~'])).unwrap();
+        UNARY_OP.get_or_init(|| HashSet::from(['-', '~']));
 
         Self {
             tokens:           TokenStream::new(tokens),
@@ -48,104 +43,122 @@ impl JackParser {
         self.class
     }
 
-    pub fn run(&mut self) {
-        self.compile_class();
+    /// Parse the tokens of a class. Errors read `invalid: ..., line: N`.
+    pub fn run(&mut self) -> Result<(), String> {
+        self.compile_class()
     }
 
-    fn step(&mut self, expected: &str) {
-        let token = self.tokens.next();
+    fn step(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.tokens.next()?;
         match token.category {
-            TokenType::Keyword | TokenType::Symbol => {
-                if token.value != expected {
-                    panic!("invalid: {}, line: {}", token.value, token.line);
-                }
+            TokenType::Keyword | TokenType::Symbol if token.value != expected => {
+                return Err(invalid(&token));
             }
             _ => {}
         }
         self.completed_tokens.push(token);
+        Ok(())
+    }
+
+    // The next token if it is a symbol, so that string constants such as "("
+    // are not mistaken for one
+    fn peek_symbol(&self) -> Option<char> {
+        let token = self.tokens.peek();
+        if token.category == TokenType::Symbol {
+            token.value.chars().next()
+        } else {
+            None
+        }
     }
 
-    fn step_identifier(&mut self) -> String {
-        let token = self.tokens.next();
+    fn step_identifier(&mut self) -> Result<String, String> {
+        let token = self.tokens.next()?;
         if token.category != TokenType::Identifier {
-            panic!("invalid: {}, line: {}", token.value, token.line);
+            return Err(invalid(&token));
         }
-        assert_eq!(token.category, TokenType::Identifier);
         let value = token.value.clone();
         self.completed_tokens.push(token);
-        value
+        Ok(value)
     }
 
-    fn step_type(&mut self) -> VarType {
-        let token = self.tokens.next();
+    fn step_type(&mut self) -> Result<VarType, String> {
+        let token = self.tokens.next()?;
         if token.category != TokenType::Keyword && token.category != TokenType::Identifier {
-            panic!("invalid: {}, line: {}", token.value, token.line);
+            return Err(invalid(&token));
         }
         let value = token.value.clone();
         self.completed_tokens.push(token);
 
-        match value.as_str() {
+        Ok(match value.as_str() {
             "int" => VarType::Int,
             "char" => VarType::Char,
             "boolean" => VarType::Boolean,
             _ => VarType::Class(value),
-        }
+        })
     }
 
-    fn compile_class(&mut self) {
+    fn compile_class(&mut self) -> Result<(), String> {
         self.completed_tokens.push(Token::unterminal("class", true));
 
-        self.step("class"); // class
-        self.class.name = self.step_identifier(); // className
-        self.step("{"); // {
+        self.step("class")?; // class
+        self.class.name = self.step_identifier()?; // className
+        self.step("{")?; // {
         while CLASS_DEC
             .get()
             .unwrap()
             .contains(self.tokens.peek().value.as_str())
         {
-            self.compile_class_var_dec();
+            match self.tokens.peek().value.as_str() {
+                "const" => self.compile_class_const_dec()?,
+                "enum" => self.compile_enum_dec()?,
+                _ => self.compile_class_var_dec()?,
+            }
         }
         while FUNC_DEC
             .get()
             .unwrap()
             .contains(self.tokens.peek().value.as_str())
         {
-            self.compile_subroutine();
+            self.compile_subroutine()?;
         }
-        self.step("}"); // }
+        self.step("}")?; // }
 
         self.completed_tokens
             .push(Token::unterminal("class", false));
+        Ok(())
     }
 
-    fn compile_class_var_dec(&mut self) {
+    fn compile_class_var_dec(&mut self) -> Result<(), String> {
         self.completed_tokens
             .push(Token::unterminal("classVarDec", true));
 
         // static | field
-        let token = self.tokens.next();
-        let scope = if token.value == "static" {
-            ClassScope::Static
-        } else if token.value == "field" {
-            ClassScope::Field
-        } else {
-            panic!("invalid: {}, should be `static | field`", token.value);
+        let token = self.tokens.next()?;
+        let scope = match token.value.as_str() {
+            "static" => ClassScope::Static,
+            "field" => ClassScope::Field,
+            _ => {
+                return Err(format!(
+                    "invalid: {}, should be `static | field`, line: {}",
+                    token.value, token.line
+                ))
+            }
         };
         self.completed_tokens.push(token);
 
         // type
-        let type_ = self.step_type();
+        let type_ = self.step_type()?;
 
         // varName*
         let mut names = vec![];
-        names.push(self.step_identifier()); // varName
+        names.push(self.step_identifier()?); // varName
         while self.tokens.peek().value.as_str() == "," {
-            self.step(","); // ,
-            names.push(self.step_identifier()); // varName
+            self.step(",")?; // ,
+            names.push(self.step_identifier()?); // varName
         }
 
         // ;
-        self.step(";");
+        self.step(";")?;
 
         self.class.vars.push(ClassVarDec {
             kind: scope,
@@ -155,27 +168,96 @@ impl JackParser {
 
         self.completed_tokens
             .push(Token::unterminal("classVarDec", false));
+        Ok(())
+    }
+
+    fn compile_class_const_dec(&mut self) -> Result<(), String> {
+        self.completed_tokens
+            .push(Token::unterminal("classConstDec", true));
+
+        self.step("const")?; // const
+        self.step_type()?; // type
+        let line = self.tokens.peek().line;
+        let name = self.step_identifier()?; // constName
+        self.step("=")?; // =
+        let value = self.compile_expression()?;
+        self.step(";")?; // ;
+
+        self.class.consts.push(ConstDec { name, value, line });
+
+        self.completed_tokens
+            .push(Token::unterminal("classConstDec", false));
+        Ok(())
+    }
+
+    // Members of an enum become constants of the class. A member without an
+    // explicit value is one more than the previous member, starting at 0.
+    fn compile_enum_dec(&mut self) -> Result<(), String> {
+        self.completed_tokens
+            .push(Token::unterminal("enumDec", true));
+
+        self.step("enum")?; // enum
+        self.step_identifier()?; // enumName
+        self.step("{")?; // {
+
+        let mut prev: Option<String> = None;
+        while self.tokens.peek().value.as_str() != "}" {
+            let line = self.tokens.peek().line;
+            let name = self.step_identifier()?; // memberName
+            let value = if self.tokens.peek().value.as_str() == "=" {
+                self.step("=")?; // =
+                self.compile_expression()?
+            } else if let Some(prev) = prev {
+                Expression {
+                    term:     Box::new(Term::VarName(prev)),
+                    op_terms: vec![OpTerm {
+                        op:   Op::Add,
+                        term: Term::IntegerConst(1),
+                    }],
+                }
+            } else {
+                Expression {
+                    term:     Box::new(Term::IntegerConst(0)),
+                    op_terms: vec![],
+                }
+            };
+
+            prev = Some(name.clone());
+            self.class.consts.push(ConstDec { name, value, line });
+
+            if self.tokens.peek().value.as_str() == "," {
+                self.step(",")?; // ,
+            }
+        }
+        self.step("}")?; // }
+
+        self.completed_tokens
+            .push(Token::unterminal("enumDec", false));
+        Ok(())
     }
 
-    fn compile_subroutine(&mut self) {
+    fn compile_subroutine(&mut self) -> Result<(), String> {
         self.completed_tokens
             .push(Token::unterminal("subroutineDec", true));
 
         // constructor | function | method
-        let token = self.tokens.next();
+        let token = self.tokens.next()?;
+        let line = token.line;
         let kind = match token.value.as_str() {
             "constructor" => SubroutineKind::Constructor,
             "function" => SubroutineKind::Function,
             "method" => SubroutineKind::Method,
-            _ => panic!(
-                "invalid: {}, should be `constructor | function | method`",
-                token.value
-            ),
+            _ => {
+                return Err(format!(
+                    "invalid: {}, should be `constructor | function | method`, line: {}",
+                    token.value, token.line
+                ))
+            }
         };
         self.completed_tokens.push(token);
 
         // void | type
-        let token = self.tokens.next();
+        let token = self.tokens.next()?;
         let type_ = match token.value.as_str() {
             "void" => SubroutineType::Void,
             "int" => SubroutineType::Type(VarType::Int),
@@ -185,21 +267,21 @@ impl JackParser {
         };
         self.completed_tokens.push(token);
 
-        let name = self.step_identifier(); // subroutineName
-        self.step("("); // (
-        let params = self.compile_param_list();
-        self.step(")"); // )
+        let name = self.step_identifier()?; // subroutineName
+        self.step("(")?; // (
+        let params = self.compile_param_list()?;
+        self.step(")")?; // )
 
         self.completed_tokens
             .push(Token::unterminal("subroutineBody", true));
 
-        self.step("{"); // {
+        self.step("{")?; // {
         let mut vars = vec![];
         while self.tokens.peek().value.as_str() == "var" {
-            vars.push(self.compile_var_dec());
+            vars.push(self.compile_var_dec()?);
         }
-        let statements = self.compile_statements();
-        self.step("}"); // }
+        let statements = self.compile_statements()?;
+        self.step("}")?; // }
 
         self.class.subroutines.push(SubroutineDec {
             kind,
@@ -210,32 +292,34 @@ impl JackParser {
                 local_vars: vars,
                 body:       statements,
             },
+            line,
         });
 
         self.completed_tokens
             .push(Token::unterminal("subroutineBody", false));
         self.completed_tokens
             .push(Token::unterminal("subroutineDec", false));
+        Ok(())
     }
 
-    fn compile_param_list(&mut self) -> Vec<Param> {
+    fn compile_param_list(&mut self) -> Result<Vec<Param>, String> {
         self.completed_tokens
             .push(Token::unterminal("parameterList", true));
 
         let mut params = vec![];
 
         if self.tokens.peek().value.as_str() != ")" {
-            let type_ = self.step_type(); // type
-            let name = self.step_identifier(); // varName
+            let type_ = self.step_type()?; // type
+            let name = self.step_identifier()?; // varName
             params.push(Param {
                 name,
                 var_type: type_,
             });
         }
         while self.tokens.peek().value.as_str() == "," {
-            self.step(","); // ,
-            let type_ = self.step_type(); // type
-            let name = self.step_identifier(); // varName
+            self.step(",")?; // ,
+            let type_ = self.step_type()?; // type
+            let name = self.step_identifier()?; // varName
             params.push(Param {
                 name,
                 var_type: type_,
@@ -245,31 +329,31 @@ impl JackParser {
         self.completed_tokens
             .push(Token::unterminal("parameterList", false));
 
-        params
+        Ok(params)
     }
 
-    fn compile_var_dec(&mut self) -> VarDec {
+    fn compile_var_dec(&mut self) -> Result<VarDec, String> {
         self.completed_tokens
             .push(Token::unterminal("varDec", true));
 
         let mut names = vec![];
 
-        self.step("var"); // var
-        let type_ = self.step_type(); // type
-        names.push(self.step_identifier()); // varName
+        self.step("var")?; // var
+        let type_ = self.step_type()?; // type
+        names.push(self.step_identifier()?); // varName
         while self.tokens.peek().value.as_str() == "," {
-            self.step(","); // ,
-            names.push(self.step_identifier()); // varName
+            self.step(",")?; // ,
+            names.push(self.step_identifier()?); // varName
         }
-        self.step(";"); // ;
+        self.step(";")?; // ;
 
         self.completed_tokens
             .push(Token::unterminal("varDec", false));
 
-        VarDec { type_, names }
+        Ok(VarDec { type_, names })
     }
 
-    fn compile_statements(&mut self) -> Vec<Statement> {
+    fn compile_statements(&mut self) -> Result<Vec<Statement>, String> {
         self.completed_tokens
             .push(Token::unterminal("statements", true));
 
@@ -280,13 +364,13 @@ impl JackParser {
             .unwrap()
             .contains(self.tokens.peek().value.as_str())
         {
-            let v = self.tokens.peek().value;
+            let v = self.tokens.peek().value.clone();
             match v.as_str() {
-                "if" => statements.push(self.compile_if()),
-                "let" => statements.push(self.compile_let()),
-                "while" => statements.push(self.compile_while()),
-                "do" => statements.push(self.compile_do()),
-                "return" => statements.push(self.compile_return()),
+                "if" => statements.push(self.compile_if()?),
+                "let" => statements.push(self.compile_let()?),
+                "while" => statements.push(self.compile_while()?),
+                "do" => statements.push(self.compile_do()?),
+                "return" => statements.push(self.compile_return()?),
                 _ => unreachable!(),
             }
         }
@@ -294,27 +378,28 @@ impl JackParser {
         self.completed_tokens
             .push(Token::unterminal("statements", false));
 
-        statements
+        Ok(statements)
     }
 
-    fn compile_if(&mut self) -> Statement {
+    fn compile_if(&mut self) -> Result<Statement, String> {
         self.completed_tokens
             .push(Token::unterminal("ifStatement", true));
 
-        self.step("if"); // if
-        self.step("("); // (
-        let cond = self.compile_expression();
-        self.step(")"); // )
+        let line = self.tokens.peek().line;
+        self.step("if")?; // if
+        self.step("(")?; // (
+        let cond = self.compile_expression()?;
+        self.step(")")?; // )
 
-        self.step("{"); // {
-        let if_body = self.compile_statements();
-        self.step("}"); // }
+        self.step("{")?; // {
+        let if_body = self.compile_statements()?;
+        self.step("}")?; // }
 
         let else_body = if self.tokens.peek().value.as_str() == "else" {
-            self.step("else"); // else
-            self.step("{"); // {
-            let else_body = self.compile_statements();
-            self.step("}"); // }
+            self.step("else")?; // else
+            self.step("{")?; // {
+            let else_body = self.compile_statements()?;
+            self.step("}")?; // }
             Some(else_body)
         } else {
             None
@@ -323,83 +408,88 @@ impl JackParser {
         self.completed_tokens
             .push(Token::unterminal("ifStatement", false));
 
-        Statement::If(IfStatement {
+        Ok(Statement::If(IfStatement {
             cond,
             if_body,
             else_body,
-        })
+            line,
+        }))
     }
 
-    fn compile_let(&mut self) -> Statement {
+    fn compile_let(&mut self) -> Result<Statement, String> {
         self.completed_tokens
             .push(Token::unterminal("letStatement", true));
 
-        self.step("let"); // let
-        let var_name = self.step_identifier(); // varName
+        let line = self.tokens.peek().line;
+        self.step("let")?; // let
+        let var_name = self.step_identifier()?; // varName
 
         let array_index = if self.tokens.peek().value.as_str() == "[" {
-            self.step("["); // [
-            let expression = self.compile_expression();
-            self.step("]"); // ]
+            self.step("[")?; // [
+            let expression = self.compile_expression()?;
+            self.step("]")?; // ]
             Some(expression)
         } else {
             None
         };
 
-        self.step("="); // =
-        let right_expr = self.compile_expression();
-        self.step(";"); // ;
+        self.step("=")?; // =
+        let right_expr = self.compile_expression()?;
+        self.step(";")?; // ;
 
         self.completed_tokens
             .push(Token::unterminal("letStatement", false));
 
-        Statement::Let(LetStatement {
+        Ok(Statement::Let(LetStatement {
             var_name,
             array_index,
             right_expr,
-        })
+            line,
+        }))
     }
 
-    fn compile_while(&mut self) -> Statement {
+    fn compile_while(&mut self) -> Result<Statement, String> {
         self.completed_tokens
             .push(Token::unterminal("whileStatement", true));
 
-        self.step("while"); // while
-        self.step("("); // (
-        let cond = self.compile_expression();
-        self.step(")"); // )
+        let line = self.tokens.peek().line;
+        self.step("while")?; // while
+        self.step("(")?; // (
+        let cond = self.compile_expression()?;
+        self.step(")")?; // )
 
-        self.step("{"); // {
-        let body = self.compile_statements();
-        self.step("}"); // }
+        self.step("{")?; // {
+        let body = self.compile_statements()?;
+        self.step("}")?; // }
 
         self.completed_tokens
             .push(Token::unterminal("whileStatement", false));
 
-        Statement::While(WhileStatement { cond, body })
+        Ok(Statement::While(WhileStatement { cond, body, line }))
     }
 
-    fn compile_do(&mut self) -> Statement {
+    fn compile_do(&mut self) -> Result<Statement, String> {
         self.completed_tokens
             .push(Token::unterminal("doStatement", true));
 
-        self.step("do"); // do
-        let name = self.step_identifier(); // subroutineCall
+        let line = self.tokens.peek().line;
+        self.step("do")?; // do
+        let name = self.step_identifier()?; // subroutineCall
         let subroutine_call = if self.tokens.peek().value.as_str() == "(" {
-            self.step("("); // (
-            let args = self.compile_expression_list();
-            self.step(")"); // )
+            self.step("(")?; // (
+            let args = self.compile_expression_list()?;
+            self.step(")")?; // )
 
             SubroutineCall::Internal(InternalCall {
                 name,
                 args: Args(args),
             })
         } else if self.tokens.peek().value.as_str() == "." {
-            self.step("."); // .
-            let subroutine_name = self.step_identifier(); // subroutineName
-            self.step("("); // (
-            let args = self.compile_expression_list();
-            self.step(")"); // )
+            self.step(".")?; // .
+            let subroutine_name = self.step_identifier()?; // subroutineName
+            self.step("(")?; // (
+            let args = self.compile_expression_list()?;
+            self.step(")")?; // )
 
             SubroutineCall::External(ExternalCall {
                 name,
@@ -407,54 +497,53 @@ impl JackParser {
                 args: Args(args),
             })
         } else {
-            panic!(
-                "invalid: {}, line: {}",
-                self.tokens.peek().value,
-                self.tokens.peek().line
-            );
+            return Err(invalid(self.tokens.peek()));
         };
-        self.step(";"); // ;
+        self.step(";")?; // ;
 
         self.completed_tokens
             .push(Token::unterminal("doStatement", false));
 
-        Statement::Do(DoStatement { subroutine_call })
+        Ok(Statement::Do(DoStatement {
+            subroutine_call,
+            line,
+        }))
     }
 
-    fn compile_return(&mut self) -> Statement {
+    fn compile_return(&mut self) -> Result<Statement, String> {
         self.completed_tokens
             .push(Token::unterminal("returnStatement", true));
 
-        self.step("return"); // return
+        let line = self.tokens.peek().line;
+        self.step("return")?; // return
 
-        let expr = if self.tokens.peek().value.as_str() != ";" {
-            Some(self.compile_expression())
+        let expr = if self.peek_symbol() != Some(';') {
+            Some(self.compile_expression()?)
         } else {
             None
         };
 
-        self.step(";"); // ;
+        self.step(";")?; // ;
 
         self.completed_tokens
             .push(Token::unterminal("returnStatement", false));
 
-        Statement::Return(ReturnStatement { expr })
+        Ok(Statement::Return(ReturnStatement { expr, line }))
     }
 
-    fn compile_expression(&mut self) -> Expression {
+    fn compile_expression(&mut self) -> Result<Expression, String> {
         self.completed_tokens
             .push(Token::unterminal("expression", true));
 
-        let term = self.compile_term();
+        let term = self.compile_term()?;
 
         let mut op_terms = vec![];
-        while OP
-            .get()
-            .unwrap()
-            .contains(&self.tokens.peek().value.chars().next().unwrap())
+        while self
+            .peek_symbol()
+            .is_some_and(|v| OP.get().unwrap().contains(&v))
         {
             // op
-            let token = self.tokens.next();
+            let token = self.tokens.next()?;
             let op = match token.value.as_str() {
                 "+" => Op::Add,
                 "-" => Op::Minus,
@@ -465,11 +554,11 @@ impl JackParser {
                 "<" => Op::Less,
                 ">" => Op::Greater,
                 "=" => Op::Euqal,
-                _ => panic!("invalid: {}, line: {}", token.value, token.line),
+                _ => return Err(invalid(&token)),
             };
             self.completed_tokens.push(token);
 
-            let term = self.compile_term();
+            let term = self.compile_term()?;
 
             op_terms.push(OpTerm { op, term });
         }
@@ -477,166 +566,185 @@ impl JackParser {
         self.completed_tokens
             .push(Token::unterminal("expression", false));
 
-        Expression {
+        Ok(Expression {
             term: Box::new(term),
             op_terms,
-        }
+        })
     }
 
-    fn compile_term(&mut self) -> Term {
+    fn compile_term(&mut self) -> Result<Term, String> {
         self.completed_tokens.push(Token::unterminal("term", true));
 
-        let term = if self.tokens.peek().value.as_str() == "(" {
-            self.step("("); // (
-            let expression = self.compile_expression();
-            self.step(")"); // )
+        let term = if self.peek_symbol() == Some('(') {
+            self.step("(")?; // (
+            let expression = self.compile_expression()?;
+            self.step(")")?; // )
             Term::Expression(expression)
-        } else if UNARY_OP
-            .get()
-            .unwrap()
-            .contains(&self.tokens.peek().value.chars().next().unwrap())
+        } else if self
+            .peek_symbol()
+            .is_some_and(|v| UNARY_OP.get().unwrap().contains(&v))
         {
             // - or ~
-            let token = self.tokens.next();
+            let token = self.tokens.next()?;
             let unary_op = match token.value.as_str() {
                 "-" => UnaryOp::Neg,
                 "~" => UnaryOp::Not,
-                _ => panic!("invalid: {}, line: {}", token.value, token.line),
+                _ => return Err(invalid(&token)),
             };
             self.completed_tokens.push(token);
 
-            let term = self.compile_term();
+            let term = self.compile_term()?;
 
             Term::UnaryExpression(UnaryExpression {
                 unary_op,
                 term: Box::new(term),
             })
         } else {
-            let token = self.tokens.next();
+            let token = self.tokens.next()?;
             self.completed_tokens.push(token.clone());
 
             let name = token.value.clone();
 
-            match self.tokens.peek().value.as_str() {
-                "[" => {
-                    self.step("["); // [
-                    let expr = self.compile_expression();
-                    self.step("]"); // ]
+            let next = if token.category == TokenType::Identifier {
+                self.peek_symbol()
+            } else {
+                None
+            };
+
+            match next {
+                Some('[') => {
+                    self.step("[")?; // [
+                    let expr = self.compile_expression()?;
+                    self.step("]")?; // ]
 
                     Term::Array(Array {
                         name,
                         index: Box::new(expr),
                     })
                 }
-                "(" => {
-                    self.step("("); // (
-                    let args = self.compile_expression_list();
-                    self.step(")"); // )
+                Some('(') => {
+                    self.step("(")?; // (
+                    let args = self.compile_expression_list()?;
+                    self.step(")")?; // )
 
                     Term::SubRoutineCall(SubroutineCall::Internal(InternalCall {
                         name,
                         args: Args(args),
                     }))
                 }
-                "." => {
-                    self.step("."); // .
-                    let subroutine_name = self.step_identifier(); // subroutineName
-                    self.step("("); // (
-                    let args = self.compile_expression_list();
-                    self.step(")"); // )
-
-                    Term::SubRoutineCall(SubroutineCall::External(ExternalCall {
-                        name,
-                        subroutine_name,
-                        args: Args(args),
-                    }))
+                Some('.') => {
+                    self.step(".")?; // .
+                    let member_name = self.step_identifier()?; // subroutineName | constName
+
+                    if self.tokens.peek().value.as_str() == "(" {
+                        self.step("(")?; // (
+                        let args = self.compile_expression_list()?;
+                        self.step(")")?; // )
+
+                        Term::SubRoutineCall(SubroutineCall::External(ExternalCall {
+                            name,
+                            subroutine_name: member_name,
+                            args: Args(args),
+                        }))
+                    } else {
+                        Term::ConstRef(ConstRef {
+                            class_name: name,
+                            name:       member_name,
+                        })
+                    }
                 }
                 _ => match token.category {
-                    TokenType::IntegerConstant => {
-                        Term::IntegerConst(token.value.parse::<u32>().unwrap())
-                    }
+                    TokenType::IntegerConstant => match token.value.parse::<u32>() {
+                        Ok(value) => Term::IntegerConst(value),
+                        Err(_) => return Err(invalid(&token)),
+                    },
                     TokenType::StringConstant => Term::StringConst(token.value),
                     TokenType::Keyword => match token.value.as_str() {
                         "false" => Term::KeywordConst(KeywordConstant::False),
                         "true" => Term::KeywordConst(KeywordConstant::True),
                         "null" => Term::KeywordConst(KeywordConstant::Null),
                         "this" => Term::KeywordConst(KeywordConstant::This),
-                        _ => panic!(
-                            "invalid: {}, line: {}",
-                            self.tokens.peek().value,
-                            self.tokens.peek().line
-                        ),
+                        _ => return Err(invalid(&token)),
                     },
                     TokenType::Identifier => Term::VarName(token.value),
-                    _ => panic!(
-                        "invalid: {}, line: {}",
-                        self.tokens.peek().value,
-                        self.tokens.peek().line
-                    ),
+                    _ => return Err(invalid(&token)),
                 },
             }
         };
 
         self.completed_tokens.push(Token::unterminal("term", false));
 
-        term
+        Ok(term)
     }
 
-    fn compile_expression_list(&mut self) -> Vec<Expression> {
+    fn compile_expression_list(&mut self) -> Result<Vec<Expression>, String> {
         self.completed_tokens
             .push(Token::unterminal("expressionList", true));
 
         let mut expressions = vec![];
 
-        if self.tokens.peek().value.as_str() != ")" {
-            expressions.push(self.compile_expression());
+        if self.peek_symbol() != Some(')') {
+            expressions.push(self.compile_expression()?);
             while self.tokens.peek().value.as_str() == "," {
-                self.step(","); // ,
-                expressions.push(self.compile_expression());
+                self.step(",")?; // ,
+                expressions.push(self.compile_expression()?);
             }
         }
 
         self.completed_tokens
             .push(Token::unterminal("expressionList", false));
 
-        expressions
+        Ok(expressions)
     }
 
-    pub fn save_file(&self, dst_path: &PathBuf) {
-        assert_eq!(dst_path.extension().unwrap(), "xml");
-
+    /// The parse tree as the XML of the course
+    pub fn xml(&self) -> Vec<u8> {
         let mut output: Vec<u8> = vec![];
 
         for token in self.completed_tokens.iter() {
             writeln!(&mut output, "{}", token.form).unwrap();
         }
 
-        save_file(&output, dst_path).unwrap();
+        output
+    }
+
+    pub fn save_file(&self, dst_path: &PathBuf) {
+        save_file(&self.xml(), dst_path).unwrap();
     }
 }
 
-pub struct TokenStream {
+fn invalid(token: &Token) -> String {
+    format!("invalid: {}, line: {}", token.value, token.line)
+}
+
+struct TokenStream {
     tokens: Vec<Token>,
     i:      usize,
+    // what `peek` sees after the last token, on the line of the last token
+    end:    Token,
 }
 
 impl TokenStream {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, i: 0 }
-    }
-
-    pub fn next(&mut self) -> Token {
-        let token = self.tokens[self.i].clone();
-        self.i += 1;
-        token
+        let line = tokens.last().map_or(1, |token| token.line);
+        Self {
+            tokens,
+            i: 0,
+            end: Token::new(TokenType::Symbol, String::new(), line),
+        }
     }
 
-    pub fn peek(&self) -> Token {
-        self.tokens[self.i].clone()
+    pub fn next(&mut self) -> Result<Token, String> {
+        match self.tokens.get(self.i) {
+            Some(token) => {
+                self.i += 1;
+                Ok(token.clone())
+            }
+            None => Err(format!("invalid: end of file, line: {}", self.end.line)),
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn is_end(&self) -> bool {
-        self.i >= self.tokens.len()
+    pub fn peek(&self) -> &Token {
+        self.tokens.get(self.i).unwrap_or(&self.end)
     }
 }
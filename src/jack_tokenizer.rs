@@ -1,6 +1,5 @@
 use std::collections::HashSet;
 use std::fmt;
-use std::fs::read_to_string;
 use std::io::Write;
 use std::path::PathBuf;
 
@@ -12,14 +11,16 @@ pub static KEYWORDS: OnceCell<HashSet<&str>> = OnceCell::new();
 pub static SYMBOLS: OnceCell<HashSet<char>> = OnceCell::new();
 
 pub struct JackTokenizer {
-    codes:  Vec<String>,
+    // (line number in the source file, code without comments)
+    codes:  Vec<(usize, String)>,
     tokens: Vec<Token>,
 }
 
 impl JackTokenizer {
-    pub fn new(path: PathBuf) -> Self {
-        KEYWORDS
-            .set(HashSet::from([
+    /// Tokenize Jack source code
+    pub fn from_source(source: &str) -> Self {
+        KEYWORDS.get_or_init(|| {
+            HashSet::from([
                 "class",
                 "constructor",
                 "function",
@@ -41,53 +42,57 @@ impl JackTokenizer {
                 "else",
                 "while",
                 "return",
-            ]))
-            .unwrap();
+                "const",
+                "enum",
+            ])
+        });
 
-        SYMBOLS
-            .set(HashSet::from([
+        SYMBOLS.get_or_init(|| {
+            HashSet::from([
                 '{', '}', '(', ')', '[', ']', '.', ',', ';', '+', '-', '*', '/', '&', '|', '<',
                 '>', '=', '~',
-            ]))
-            .unwrap();
-
-        assert_eq!(path.extension().unwrap(), "jack");
+            ])
+        });
 
         let mut multi_comments = false;
         let mut codes = vec![];
 
-        for line in read_to_string(path).unwrap().lines() {
+        for (i, line) in source.lines().enumerate() {
             let line = line.trim();
+            let line_no = i + 1;
 
             if multi_comments {
                 if !line.contains("*/") {
                     continue;
                 }
                 let start = line.find("*/").unwrap() + 2;
-                codes.push(substr(line, start, line.len() - start).trim().to_string());
+                codes.push((
+                    line_no,
+                    substr(line, start, line.len() - start).trim().to_string(),
+                ));
                 multi_comments = false;
             } else if line.is_empty() || line.starts_with("//") {
                 continue;
             } else if line.contains("//") {
                 let end = line.find("//").unwrap();
-                codes.push(substr(line, 0, end).trim().to_string());
+                codes.push((line_no, substr(line, 0, end).trim().to_string()));
             } else if line.contains("/*") {
                 let start = line.find("/*").unwrap();
-                let end = line.find("*/");
-                if end.is_none() {
-                    multi_comments = true;
-                    codes.push(substr(line, 0, start).trim().to_string());
-                } else {
-                    codes.push(format!(
-                        "{}{}",
-                        substr(line, 0, start).trim().to_string(),
-                        substr(line, end.unwrap() + 2, line.len() - end.unwrap() - 2)
-                            .trim()
-                            .to_string(),
+                if let Some(end) = line.find("*/") {
+                    codes.push((
+                        line_no,
+                        format!(
+                            "{}{}",
+                            substr(line, 0, start).trim(),
+                            substr(line, end + 2, line.len() - end - 2).trim(),
+                        ),
                     ));
+                } else {
+                    multi_comments = true;
+                    codes.push((line_no, substr(line, 0, start).trim().to_string()));
                 }
             } else {
-                codes.push(line.to_owned());
+                codes.push((line_no, line.to_owned()));
             }
         }
 
@@ -101,8 +106,9 @@ impl JackTokenizer {
         self.tokens
     }
 
-    pub fn run(&mut self) {
-        for (i, line) in self.codes.iter().enumerate() {
+    /// Split the codes into tokens. Errors read `invalid: ..., line: N`.
+    pub fn run(&mut self) -> Result<(), String> {
+        for (line_no, line) in self.codes.iter() {
             let line = line.trim().to_owned();
 
             if line.is_empty() {
@@ -114,16 +120,22 @@ impl JackTokenizer {
             while !chars.is_end() {
                 let mut c = chars.next();
 
-                if c == ' ' {
+                if c.is_whitespace() {
                     continue;
                 }
 
                 if SYMBOLS.get().unwrap().contains(&c) {
                     self.tokens
-                        .push(Token::new(TokenType::Symbol, c.to_string(), i));
+                        .push(Token::new(TokenType::Symbol, c.to_string(), *line_no));
                 } else if c == '\"' {
                     let mut word = String::default();
-                    while !chars.is_end() {
+                    loop {
+                        if chars.is_end() {
+                            return Err(format!(
+                                "invalid: unterminated string constant \"{}, line: {}",
+                                word, line_no
+                            ));
+                        }
                         c = chars.next();
                         if c == '\"' {
                             break;
@@ -131,7 +143,7 @@ impl JackTokenizer {
                         word.push(c);
                     }
                     self.tokens
-                        .push(Token::new(TokenType::StringConstant, word, i));
+                        .push(Token::new(TokenType::StringConstant, word, *line_no));
                 } else if c.is_numeric() {
                     let mut number = String::from(c);
                     while !chars.is_end() {
@@ -143,12 +155,12 @@ impl JackTokenizer {
                         chars.next();
                     }
                     self.tokens
-                        .push(Token::new(TokenType::IntegerConstant, number, i));
-                } else {
+                        .push(Token::new(TokenType::IntegerConstant, number, *line_no));
+                } else if c.is_alphabetic() || c == '_' {
                     let mut word = String::from(c);
                     while !chars.is_end() {
                         c = chars.peek();
-                        if c.is_alphanumeric() {
+                        if c.is_alphanumeric() || c == '_' {
                             word.push(c);
                             chars.next();
                         } else {
@@ -156,16 +168,22 @@ impl JackTokenizer {
                         }
                     }
                     if KEYWORDS.get().unwrap().contains(word.as_str()) {
-                        self.tokens.push(Token::new(TokenType::Keyword, word, i));
+                        self.tokens
+                            .push(Token::new(TokenType::Keyword, word, *line_no));
                     } else {
-                        self.tokens.push(Token::new(TokenType::Identifier, word, i));
+                        self.tokens
+                            .push(Token::new(TokenType::Identifier, word, *line_no));
                     }
+                } else {
+                    return Err(format!("invalid: {}, line: {}", c, line_no));
                 }
             }
         }
+        Ok(())
     }
 
-    pub fn save_file(&self, dst_path: &PathBuf) {
+    /// The tokens as the `<tokens>` XML of the course
+    pub fn xml(&self) -> Vec<u8> {
         let mut output: Vec<u8> = vec![];
         writeln!(&mut output, "<tokens>").unwrap();
 
@@ -174,7 +192,11 @@ impl JackTokenizer {
         }
 
         writeln!(&mut output, "</tokens>").unwrap();
-        save_file(&output, dst_path).unwrap();
+        output
+    }
+
+    pub fn save_file(&self, dst_path: &PathBuf) {
+        save_file(&self.xml(), dst_path).unwrap();
     }
 }
 
@@ -205,11 +227,10 @@ impl CharStream {
 
 #[derive(Clone)]
 pub struct Token {
-    pub category:   TokenType,
-    pub value:      String,
-    pub is_termial: bool,
-    pub line:       usize,
-    pub form:       String,
+    pub category: TokenType,
+    pub value:    String,
+    pub line:     usize,
+    pub form:     String,
 }
 
 impl Token {
@@ -224,7 +245,6 @@ impl Token {
         Self {
             category,
             value,
-            is_termial: true,
             form,
             line,
         }
@@ -240,7 +260,6 @@ impl Token {
         Self {
             category: TokenType::Identifier, // unused
             value: value.to_owned(),
-            is_termial: false,
             form,
             line: 0,
         }
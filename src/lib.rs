@@ -0,0 +1,122 @@
+mod asm_expr;
+mod asm_macro;
+mod assembler;
+pub mod ast;
+mod cli;
+mod disassembler;
+mod emulator;
+mod hack_format;
+mod hdl;
+mod jack_compiler;
+mod jack_parser;
+mod jack_tokenizer;
+mod native_os;
+mod optimizer;
+mod os;
+mod profiler;
+mod symbol_table;
+mod utils;
+mod vm_interpreter;
+mod vm_linker;
+mod vm_translator;
+mod vm_writer;
+
+use std::fmt;
+
+use assembler::Assembler;
+use ast::Class;
+use jack_compiler::compile_to_vm;
+use jack_tokenizer::JackTokenizer;
+use vm_translator::{OsOptions, VmTranslator};
+
+pub use cli::run as run_cli;
+pub use jack_tokenizer::{Token, TokenType};
+pub use vm_linker::{Diagnostic, Severity};
+pub use vm_translator::Bootstrap;
+
+// The functions below are the in-memory API of the toolchain, which can be
+// called any number of times in one process. The invalid input the command
+// line tools report and exit on is returned as an `Error` instead.
+
+/// Why a function of the API failed
+#[derive(Debug)]
+pub enum Error {
+    /// The problems found when linking a VM program, at least one of them
+    /// an error
+    Link(Vec<Diagnostic>),
+    /// Invalid input, with the message of the command line tools, like
+    /// `invalid: ..., line: 3`
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Link(diagnostics) => {
+                let messages: Vec<String> = diagnostics.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            Error::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Invalid(message)
+    }
+}
+
+/// Assemble Hack assembly into the lines of `0` and `1` of a *.hack file
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    let mut assembler = Assembler::from_source(source);
+    assembler.run()?;
+    Ok(assembler.output().to_vec())
+}
+
+/// Translate a VM program, given as (file name without extension, VM
+/// commands) pairs, into Hack assembly. The bundled Jack OS classes it calls
+/// are linked and its unused functions left out.
+pub fn translate(
+    vm_sources: &[(&str, &str)],
+    bootstrap: Option<Bootstrap>,
+) -> Result<Vec<u8>, Error> {
+    let vm_sources = vm_sources
+        .iter()
+        .map(|(name, source)| (name.to_string(), source.to_string()))
+        .collect();
+    let os = OsOptions {
+        link: true,
+        dir:  None,
+    };
+
+    let mut vm_translator = VmTranslator::new(vm_sources, false, os, true, bootstrap)?;
+    if vm_translator.diagnostics().iter().any(|v| v.is_error()) {
+        return Err(Error::Link(vm_translator.diagnostics().to_vec()));
+    }
+    vm_translator.run()?;
+    Ok(vm_translator.output().to_vec())
+}
+
+/// Split the source code of a Jack class into tokens
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let mut tokenizer = JackTokenizer::from_source(source);
+    tokenizer.run()?;
+    Ok(tokenizer.tokens())
+}
+
+/// Parse the source code of a Jack class into its AST
+pub fn parse(source: &str) -> Result<Class, Error> {
+    Ok(jack_compiler::parse(source)?)
+}
+
+/// Compile the source code of Jack classes into (class name, VM commands)
+/// pairs, in the same order. Constants are resolved across the classes.
+pub fn compile(sources: &[&str], optimize: bool) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    Ok(compile_to_vm(sources, optimize, None)?
+        .into_iter()
+        .map(|vm_writer| (vm_writer.class_name().to_owned(), vm_writer.output()))
+        .collect())
+}
@@ -0,0 +1,701 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use once_cell::sync::OnceCell;
+
+use crate::ast::{ClassScope, DoStatement, Statement, SubroutineCall};
+use crate::jack_compiler::parse;
+use crate::optimizer::expression_value;
+use crate::os;
+use crate::vm_interpreter::VmInterpreter;
+
+/// A function of the Jack OS implemented in Rust, given its arguments and
+/// returning its result, which is 0 for void functions
+pub type NativeFn = fn(&mut VmInterpreter, &[i16]) -> i16;
+
+// The natives share the memory layout of the Jack OS, so that native and
+// Jack functions can be mixed: statics are found by name in the bundled OS
+// classes and a String is its chars Array, length and capacity.
+const MEMORY_FREE_LIST: (&str, &str) = ("Memory", "freeList");
+const OUTPUT_ROW: (&str, &str) = ("Output", "row");
+const OUTPUT_COL: (&str, &str) = ("Output", "col");
+const SCREEN_COLOR: (&str, &str) = ("Screen", "color");
+const STRING_CHARS: i16 = 0;
+const STRING_LENGTH: i16 = 1;
+const STRING_CAPACITY: i16 = 2;
+
+const SCREEN: i16 = 16384;
+const KEYBOARD: i16 = 24576;
+const NEW_LINE: i16 = 128;
+const BACKSPACE: i16 = 129;
+
+/// The OS functions with a native implementation. The `init` functions have
+/// none, so that the Jack functions always find their state set up.
+pub const NATIVES: [(&str, NativeFn); 43] = [
+    ("Math.abs", math_abs),
+    ("Math.multiply", math_multiply),
+    ("Math.divide", math_divide),
+    ("Math.min", math_min),
+    ("Math.max", math_max),
+    ("Math.sqrt", math_sqrt),
+    ("Memory.peek", memory_peek),
+    ("Memory.poke", memory_poke),
+    ("Memory.alloc", memory_alloc),
+    ("Memory.deAlloc", memory_de_alloc),
+    ("Array.new", array_new),
+    ("Array.dispose", array_dispose),
+    ("String.new", string_new),
+    ("String.dispose", string_dispose),
+    ("String.length", string_length),
+    ("String.charAt", string_char_at),
+    ("String.setCharAt", string_set_char_at),
+    ("String.appendChar", string_append_char),
+    ("String.eraseLastChar", string_erase_last_char),
+    ("String.intValue", string_int_value),
+    ("String.setInt", string_set_int),
+    ("String.newLine", string_new_line),
+    ("String.backSpace", string_back_space),
+    ("String.doubleQuote", string_double_quote),
+    ("Output.moveCursor", output_move_cursor),
+    ("Output.printChar", output_print_char),
+    ("Output.printString", output_print_string),
+    ("Output.printInt", output_print_int),
+    ("Output.println", output_println),
+    ("Output.backSpace", output_back_space),
+    ("Screen.clearScreen", screen_clear_screen),
+    ("Screen.setColor", screen_set_color),
+    ("Screen.drawPixel", screen_draw_pixel),
+    ("Screen.drawLine", screen_draw_line),
+    ("Screen.drawRectangle", screen_draw_rectangle),
+    ("Screen.drawCircle", screen_draw_circle),
+    ("Keyboard.keyPressed", keyboard_key_pressed),
+    ("Keyboard.readChar", keyboard_read_char),
+    ("Keyboard.readLine", keyboard_read_line),
+    ("Keyboard.readInt", keyboard_read_int),
+    ("Sys.halt", sys_halt),
+    ("Sys.wait", sys_wait),
+    ("Sys.error", sys_error),
+];
+
+fn peek(vm: &VmInterpreter, address: i16) -> i16 {
+    vm.peek(address as u16 as usize) as i16
+}
+
+fn poke(vm: &mut VmInterpreter, address: i16, value: i16) {
+    vm.poke(address as u16 as usize, value as u16);
+}
+
+// (class name, static name) -> index of every static of the bundled OS
+// classes, numbered in declaration order like the VmWriter does
+static STATICS: OnceCell<HashMap<(&str, String), u16>> = OnceCell::new();
+
+fn static_index(class: &str, name: &str) -> u16 {
+    let statics = STATICS.get_or_init(|| {
+        let mut statics = HashMap::new();
+        for (class, source) in os::CLASSES.iter() {
+            let names = parse(source)
+                .unwrap()
+                .vars
+                .into_iter()
+                .filter(|v| matches!(v.kind, ClassScope::Static))
+                .flat_map(|v| v.names);
+            for (i, name) in names.enumerate() {
+                statics.insert((*class, name), i as u16);
+            }
+        }
+        statics
+    });
+    statics[&(class, name.to_owned())]
+}
+
+fn get_static(vm: &mut VmInterpreter, (class, name): (&str, &str)) -> i16 {
+    let address = vm.static_address(class, static_index(class, name));
+    peek(vm, address as i16)
+}
+
+fn set_static(vm: &mut VmInterpreter, (class, name): (&str, &str), value: i16) {
+    let address = vm.static_address(class, static_index(class, name));
+    poke(vm, address as i16, value);
+}
+
+// Sys.error halts, so the caller only has to return some value
+fn error(vm: &mut VmInterpreter, code: i16) -> i16 {
+    vm.invoke("Sys.error", &[code]);
+    0
+}
+
+fn math_abs(_vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    args[0].wrapping_abs()
+}
+
+fn math_multiply(_vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    args[0].wrapping_mul(args[1])
+}
+
+fn math_divide(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    if args[1] == 0 {
+        return error(vm, 3);
+    }
+    args[0].wrapping_div(args[1])
+}
+
+fn math_min(_vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    args[0].min(args[1])
+}
+
+fn math_max(_vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    args[0].max(args[1])
+}
+
+fn math_sqrt(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    if args[0] < 0 {
+        return error(vm, 4);
+    }
+    (args[0] as f64).sqrt() as i16
+}
+
+fn memory_peek(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    peek(vm, args[0])
+}
+
+fn memory_poke(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    poke(vm, args[0], args[1]);
+    0
+}
+
+// The first fit allocator of Memory.jack: a free block starts with its size,
+// including this header, followed by the address of the next free block
+fn memory_alloc(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let need = args[0].max(1) + 1;
+    let mut prev = 0;
+    let mut block = get_static(vm, MEMORY_FREE_LIST);
+
+    while block != 0 {
+        let size = peek(vm, block);
+        // split a block large enough to leave a usable free block behind
+        if size > need + 1 {
+            poke(vm, block, size - need);
+            let block = block + size - need;
+            poke(vm, block, need);
+            return block + 1;
+        }
+        // hand out the whole block
+        if size >= need {
+            let next = peek(vm, block + 1);
+            if prev == 0 {
+                set_static(vm, MEMORY_FREE_LIST, next);
+            } else {
+                poke(vm, prev + 1, next);
+            }
+            return block + 1;
+        }
+        prev = block;
+        block = peek(vm, block + 1);
+    }
+
+    error(vm, 6)
+}
+
+fn memory_de_alloc(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let block = args[0] - 1;
+    let free_list = get_static(vm, MEMORY_FREE_LIST);
+    poke(vm, block + 1, free_list);
+    set_static(vm, MEMORY_FREE_LIST, block);
+    0
+}
+
+fn array_new(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    if args[0] < 1 {
+        return error(vm, 2);
+    }
+    vm.invoke("Memory.alloc", &[args[0]])
+}
+
+fn array_dispose(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    vm.invoke("Memory.deAlloc", &[args[0]]);
+    0
+}
+
+fn string_new(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let capacity = args[0];
+    if capacity < 0 {
+        return error(vm, 14);
+    }
+
+    let this = vm.invoke("Memory.alloc", &[3]);
+    if capacity > 0 {
+        let chars = vm.invoke("Array.new", &[capacity]);
+        poke(vm, this + STRING_CHARS, chars);
+    }
+    poke(vm, this + STRING_LENGTH, 0);
+    poke(vm, this + STRING_CAPACITY, capacity);
+    this
+}
+
+fn string_dispose(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let this = args[0];
+    if peek(vm, this + STRING_CAPACITY) > 0 {
+        let chars = peek(vm, this + STRING_CHARS);
+        vm.invoke("Array.dispose", &[chars]);
+    }
+    vm.invoke("Memory.deAlloc", &[this]);
+    0
+}
+
+fn string_length(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    peek(vm, args[0] + STRING_LENGTH)
+}
+
+fn string_char_at(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (this, j) = (args[0], args[1]);
+    if j < 0 || j >= peek(vm, this + STRING_LENGTH) {
+        return error(vm, 15);
+    }
+    peek(vm, peek(vm, this + STRING_CHARS) + j)
+}
+
+fn string_set_char_at(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (this, j, c) = (args[0], args[1], args[2]);
+    if j < 0 || j >= peek(vm, this + STRING_LENGTH) {
+        return error(vm, 16);
+    }
+    let chars = peek(vm, this + STRING_CHARS);
+    poke(vm, chars + j, c);
+    0
+}
+
+fn string_append_char(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (this, c) = (args[0], args[1]);
+    let length = peek(vm, this + STRING_LENGTH);
+    if length >= peek(vm, this + STRING_CAPACITY) {
+        return error(vm, 17);
+    }
+    let chars = peek(vm, this + STRING_CHARS);
+    poke(vm, chars + length, c);
+    poke(vm, this + STRING_LENGTH, length + 1);
+    this
+}
+
+fn string_erase_last_char(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let this = args[0];
+    let length = peek(vm, this + STRING_LENGTH);
+    if length == 0 {
+        return error(vm, 18);
+    }
+    poke(vm, this + STRING_LENGTH, length - 1);
+    0
+}
+
+fn string_int_value(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let this = args[0];
+    let length = peek(vm, this + STRING_LENGTH);
+    let chars = peek(vm, this + STRING_CHARS);
+
+    let negative = length > 0 && peek(vm, chars) == '-' as i16;
+    let mut i = negative as i16;
+    let mut value: i16 = 0;
+    while i < length {
+        let c = peek(vm, chars + i);
+        if !('0' as i16..='9' as i16).contains(&c) {
+            break;
+        }
+        value = value.wrapping_mul(10).wrapping_add(c - '0' as i16);
+        i += 1;
+    }
+
+    if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    }
+}
+
+fn string_set_int(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let this = args[0];
+    poke(vm, this + STRING_LENGTH, 0);
+    for c in args[1].to_string().chars() {
+        vm.invoke("String.appendChar", &[this, c as i16]);
+    }
+    0
+}
+
+fn string_new_line(_vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    NEW_LINE
+}
+
+fn string_back_space(_vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    BACKSPACE
+}
+
+fn string_double_quote(_vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    '"' as i16
+}
+
+// Draws character c at the cursor without moving it
+fn draw_char(vm: &mut VmInterpreter, c: i16) {
+    let font = font();
+    let glyph = if (32..=126).contains(&c) {
+        &font[c as usize - 32]
+    } else {
+        &font[font.len() - 1]
+    };
+
+    let row = get_static(vm, OUTPUT_ROW);
+    let col = get_static(vm, OUTPUT_COL);
+    let mut address = SCREEN + row * 11 * 32 + col / 2;
+    // odd columns are the high byte of a word
+    let (mask, shift) = if col & 1 == 0 { (!0xff, 0) } else { (0xff, 8) };
+
+    for &bits in glyph {
+        let value = (peek(vm, address) & mask) | ((bits as i16) << shift);
+        poke(vm, address, value);
+        address += 32;
+    }
+}
+
+fn output_move_cursor(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (i, j) = (args[0], args[1]);
+    if !(0..23).contains(&i) || !(0..64).contains(&j) {
+        return error(vm, 20);
+    }
+    set_static(vm, OUTPUT_ROW, i);
+    set_static(vm, OUTPUT_COL, j);
+    draw_char(vm, ' ' as i16);
+    0
+}
+
+fn output_print_char(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    match args[0] {
+        NEW_LINE => output_println(vm, &[]),
+        BACKSPACE => output_back_space(vm, &[]),
+        c => {
+            draw_char(vm, c);
+            let col = get_static(vm, OUTPUT_COL) + 1;
+            set_static(vm, OUTPUT_COL, col);
+            if col == 64 {
+                output_println(vm, &[]);
+            }
+            0
+        }
+    }
+}
+
+fn output_print_string(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let s = args[0];
+    let length = vm.invoke("String.length", &[s]);
+    for i in 0..length {
+        let c = vm.invoke("String.charAt", &[s, i]);
+        vm.invoke("Output.printChar", &[c]);
+    }
+    0
+}
+
+fn output_print_int(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    for c in args[0].to_string().chars() {
+        vm.invoke("Output.printChar", &[c as i16]);
+    }
+    0
+}
+
+fn output_println(vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    let row = get_static(vm, OUTPUT_ROW) + 1;
+    set_static(vm, OUTPUT_ROW, if row == 23 { 0 } else { row });
+    set_static(vm, OUTPUT_COL, 0);
+    0
+}
+
+fn output_back_space(vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    let row = get_static(vm, OUTPUT_ROW);
+    let col = get_static(vm, OUTPUT_COL);
+    if col > 0 {
+        set_static(vm, OUTPUT_COL, col - 1);
+    } else if row > 0 {
+        set_static(vm, OUTPUT_ROW, row - 1);
+        set_static(vm, OUTPUT_COL, 63);
+    }
+    draw_char(vm, ' ' as i16);
+    0
+}
+
+fn screen_clear_screen(vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    for address in SCREEN..KEYBOARD {
+        poke(vm, address, 0);
+    }
+    0
+}
+
+fn screen_set_color(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    set_static(vm, SCREEN_COLOR, args[0]);
+    0
+}
+
+fn on_screen(x: i16, y: i16) -> bool {
+    (0..512).contains(&x) && (0..256).contains(&y)
+}
+
+fn draw_pixel(vm: &mut VmInterpreter, x: i16, y: i16) {
+    let address = SCREEN + y * 32 + x / 16;
+    let mask = 1 << (x & 15);
+    let value = if get_static(vm, SCREEN_COLOR) != 0 {
+        peek(vm, address) | mask
+    } else {
+        peek(vm, address) & !mask
+    };
+    poke(vm, address, value);
+}
+
+fn draw_horizontal_line(vm: &mut VmInterpreter, x1: i16, x2: i16, y: i16) {
+    for x in x1..=x2 {
+        draw_pixel(vm, x, y);
+    }
+}
+
+fn screen_draw_pixel(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (x, y) = (args[0], args[1]);
+    if !on_screen(x, y) {
+        return error(vm, 7);
+    }
+    draw_pixel(vm, x, y);
+    0
+}
+
+// The pixels of the Jack version, which steps along x while
+// a * dy - b * dx is negative and along y otherwise
+fn screen_draw_line(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (x1, y1, x2, y2) = (args[0], args[1], args[2], args[3]);
+    if !on_screen(x1, y1) || !on_screen(x2, y2) {
+        return error(vm, 8);
+    }
+    if y1 == y2 {
+        draw_horizontal_line(vm, x1.min(x2), x1.max(x2), y1);
+        return 0;
+    }
+
+    let (dx, dy) = ((x2 - x1).abs(), (y2 - y1).abs());
+    let (sx, sy) = (if x2 < x1 { -1 } else { 1 }, if y2 < y1 { -1 } else { 1 });
+    let (mut a, mut b, mut diff) = (0, 0, 0);
+    while a <= dx && b <= dy {
+        draw_pixel(vm, x1 + a * sx, y1 + b * sy);
+        if diff < 0 {
+            a += 1;
+            diff += dy;
+        } else {
+            b += 1;
+            diff -= dx;
+        }
+    }
+    0
+}
+
+fn screen_draw_rectangle(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (x1, y1, x2, y2) = (args[0], args[1], args[2], args[3]);
+    if x1 > x2 || y1 > y2 || !on_screen(x1, y1) || !on_screen(x2, y2) {
+        return error(vm, 9);
+    }
+    for y in y1..=y2 {
+        draw_horizontal_line(vm, x1, x2, y);
+    }
+    0
+}
+
+fn screen_draw_circle(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let (x, y, r) = (args[0], args[1], args[2]);
+    if !on_screen(x, y) {
+        return error(vm, 12);
+    }
+    if !(0..=181).contains(&r) {
+        return error(vm, 13);
+    }
+    for dy in -r..=r {
+        // rows outside the screen are clipped
+        if !(0..256).contains(&(y + dy)) {
+            continue;
+        }
+        let dx = ((r * r - dy * dy) as f64).sqrt() as i16;
+        draw_horizontal_line(vm, (x - dx).max(0), (x + dx).min(511), y + dy);
+    }
+    0
+}
+
+fn keyboard_key_pressed(vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    peek(vm, KEYBOARD)
+}
+
+// Keys are read from the standard input, a new line being the new line key.
+// The program halts at the end of the input, as no key would ever come.
+fn keyboard_read_char(vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    let mut buf = [0u8; 1];
+    let c = match std::io::stdin().read(&mut buf) {
+        Ok(1) if buf[0] == b'\n' => NEW_LINE,
+        Ok(1) => buf[0] as i16,
+        _ => {
+            vm.halt();
+            return 0;
+        }
+    };
+    vm.invoke("Output.printChar", &[c]);
+    c
+}
+
+fn keyboard_read_line(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    vm.invoke("Output.printString", &[args[0]]);
+    let line = vm.invoke("String.new", &[80]);
+
+    while !vm.is_halted() {
+        let c = vm.invoke("Keyboard.readChar", &[]);
+        if c == NEW_LINE {
+            break;
+        }
+        let length = vm.invoke("String.length", &[line]);
+        if c == BACKSPACE {
+            if length > 0 {
+                vm.invoke("String.eraseLastChar", &[line]);
+            }
+        } else if length < 80 {
+            vm.invoke("String.appendChar", &[line, c]);
+        }
+    }
+    line
+}
+
+fn keyboard_read_int(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    let line = vm.invoke("Keyboard.readLine", &[args[0]]);
+    let value = vm.invoke("String.intValue", &[line]);
+    vm.invoke("String.dispose", &[line]);
+    value
+}
+
+fn sys_halt(vm: &mut VmInterpreter, _args: &[i16]) -> i16 {
+    vm.halt();
+    0
+}
+
+// Waiting has no use without a real screen and keyboard
+fn sys_wait(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    if args[0] < 0 {
+        return error(vm, 1);
+    }
+    0
+}
+
+fn sys_error(vm: &mut VmInterpreter, args: &[i16]) -> i16 {
+    eprintln!("Sys.error: {}", args[0]);
+    for c in "ERR".chars() {
+        vm.invoke("Output.printChar", &[c as i16]);
+    }
+    vm.invoke("Output.printInt", &[args[0]]);
+    vm.halt();
+    0
+}
+
+// The font of Output.jack: 11 rows of 6 pixels for the characters 32 to 126
+// followed by the black square, the leftmost pixel of a row being its least
+// significant bit. It is decoded from the words `Output.initMap` loads, so
+// that both draw the same characters.
+static FONT: OnceCell<Vec<[u8; 11]>> = OnceCell::new();
+
+fn font() -> &'static [[u8; 11]] {
+    FONT.get_or_init(|| {
+        let class = parse(os::source("Output").unwrap()).unwrap();
+        let init_map = class
+            .subroutines
+            .iter()
+            .find(|v| v.name == "initMap")
+            .unwrap();
+        let words: Vec<u16> = init_map
+            .body
+            .body
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Do(DoStatement {
+                    subroutine_call: SubroutineCall::External(call),
+                    ..
+                }) if call.subroutine_name == "load" => Some(call.args.0.iter()),
+                _ => None,
+            })
+            .flatten()
+            .map(|arg| expression_value(arg, &|_, _| None).unwrap() as u16)
+            .collect();
+
+        // 6-bit rows packed from the least significant bit of the first word
+        let bit = |i: usize| (words[i / 16] >> (i % 16)) as u8 & 1;
+        (0..96)
+            .map(|c| {
+                let mut glyph = [0; 11];
+                for (row, bits) in glyph.iter_mut().enumerate() {
+                    let start = (c * 11 + row) * 6;
+                    *bits = (0..6).map(|j| bit(start + j) << j).sum();
+                }
+                glyph
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jack_compiler::compile_source;
+    use crate::vm_translator::link_os;
+
+    const MAIN: &str = "
+class Main {
+    function void main() {
+        var String s;
+        var Array a, b;
+        let a = Array.new(5);
+        let s = String.new(8);
+        let s = s.appendChar(72);
+        let s = s.appendChar(105);
+        do Output.printString(s);
+        do Output.println();
+        do Output.moveCursor(3, 10);
+        do Output.printInt(-1234);
+        do s.setInt(567);
+        do Memory.poke(8000, s.intValue());
+        do Memory.poke(8001, s.length());
+        do a.dispose();
+        let b = Array.new(2);
+        do Memory.poke(8002, b);
+        do Screen.drawRectangle(100, 100, 140, 120);
+        do Screen.setColor(false);
+        do Screen.drawLine(100, 100, 140, 120);
+        do Screen.setColor(true);
+        do Screen.drawCircle(300, 150, 30);
+        do Memory.poke(8003, Math.sqrt(20000));
+        do Memory.poke(8004, Math.divide(-7, 2));
+        return;
+    }
+}
+";
+
+    fn run(natives: &[(&str, NativeFn)]) -> VmInterpreter {
+        let (_, vm) = compile_source(MAIN).unwrap();
+        let mut vm_sources = vec![("Main".to_owned(), String::from_utf8(vm).unwrap())];
+        link_os(&mut vm_sources, Some("Sys.init"), None).unwrap();
+
+        let mut interpreter = VmInterpreter::new(&vm_sources, natives);
+        interpreter.run(50_000_000);
+        assert!(interpreter.is_halted());
+        interpreter
+    }
+
+    #[test]
+    fn match_the_jack_os() {
+        let mut native = run(&NATIVES);
+        let mut jack = run(&[]);
+        // a scratch variable of the Jack multiplication and division
+        for vm in [&mut native, &mut jack] {
+            let address = vm.static_address("Math", static_index("Math", "product"));
+            vm.ram[address as usize] = 0;
+        }
+
+        assert!(native.cycles < jack.cycles);
+        assert_eq!(native.ram[8000..8005], jack.ram[8000..8005]);
+        assert_eq!(native.ram[8000..8002], [567, 3]);
+        // the statics and the screen, but not the freed blocks of the heap as
+        // the Jack OS allocates temporary strings to print numbers
+        assert_eq!(native.ram[16..256], jack.ram[16..256]);
+        assert_eq!(native.ram[16384..24576], jack.ram[16384..24576]);
+    }
+}
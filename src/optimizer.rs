@@ -0,0 +1,675 @@
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::symbol_table::ConstTable;
+
+/// Resolves a name used in a constant expression: `NAME` of the current class
+/// when the class is `None`, otherwise `Class.NAME`.
+pub type ConstLookup<'a> = dyn Fn(Option<&str>, &str) -> Option<i16> + 'a;
+
+/// Rewrites the AST of a class before it is handed to the `VmWriter`:
+/// constant subexpressions are folded, algebraic identities are removed,
+/// multiplications of a variable by two become additions and branches on
+/// constant conditions are resolved.
+pub struct Optimizer {
+    class:     Class,
+    constants: ConstTable,
+    // statics and fields of the class, parameters and locals of the current
+    // subroutine, which hide constants of the same name like in the VmWriter
+    shadowed:  HashSet<String>,
+}
+
+impl Optimizer {
+    pub fn new(class: Class, constants: ConstTable) -> Self {
+        Self {
+            class,
+            constants,
+            shadowed: HashSet::new(),
+        }
+    }
+
+    pub fn ast(self) -> Class {
+        self.class
+    }
+
+    pub fn run(&mut self) {
+        let mut subroutines = std::mem::take(&mut self.class.subroutines);
+
+        let class_vars: Vec<String> = self
+            .class
+            .vars
+            .iter()
+            .flat_map(|v| v.names.iter().cloned())
+            .collect();
+
+        for subroutine in subroutines.iter_mut() {
+            self.shadowed = class_vars
+                .iter()
+                .cloned()
+                .chain(subroutine.params.iter().map(|v| v.name.clone()))
+                .chain(
+                    subroutine
+                        .body
+                        .local_vars
+                        .iter()
+                        .flat_map(|v| v.names.iter().cloned()),
+                )
+                .collect();
+
+            let body = std::mem::take(&mut subroutine.body.body);
+            subroutine.body.body = self.optimize_statements(body);
+        }
+
+        self.class.subroutines = subroutines;
+    }
+
+    fn expression_value(&self, expr: &Expression) -> Option<i16> {
+        expression_value(expr, &|class_name, name| self.lookup(class_name, name))
+    }
+
+    fn term_value(&self, term: &Term) -> Option<i16> {
+        term_value(term, &|class_name, name| self.lookup(class_name, name))
+    }
+
+    fn lookup(&self, class_name: Option<&str>, name: &str) -> Option<i16> {
+        match class_name {
+            Some(class_name) => self.constants.get(class_name, name),
+            None if self.shadowed.contains(name) => None,
+            None => self.constants.get(&self.class.name, name),
+        }
+    }
+
+    fn optimize_statements(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        let mut optimized = vec![];
+
+        for statement in statements.into_iter() {
+            match statement {
+                Statement::Let(v) => optimized.push(Statement::Let(LetStatement {
+                    var_name:    v.var_name,
+                    array_index: v.array_index.map(|v| self.optimize_expression(v)),
+                    right_expr:  self.optimize_expression(v.right_expr),
+                    line:        v.line,
+                })),
+                Statement::If(v) => {
+                    let cond = self.optimize_expression(v.cond);
+                    match self.expression_value(&cond) {
+                        // if (true) { ... } => ...
+                        Some(value) if value != 0 => {
+                            optimized.extend(self.optimize_statements(v.if_body));
+                        }
+                        // if (false) { ... } else { ... } => ...
+                        Some(_) => {
+                            if let Some(else_body) = v.else_body {
+                                optimized.extend(self.optimize_statements(else_body));
+                            }
+                        }
+                        None => optimized.push(Statement::If(IfStatement {
+                            cond,
+                            if_body: self.optimize_statements(v.if_body),
+                            else_body: v.else_body.map(|v| self.optimize_statements(v)),
+                            line: v.line,
+                        })),
+                    }
+                }
+                Statement::While(v) => {
+                    let cond = self.optimize_expression(v.cond);
+                    // while (false) { ... } is never entered
+                    if self.expression_value(&cond) != Some(0) {
+                        optimized.push(Statement::While(WhileStatement {
+                            cond,
+                            body: self.optimize_statements(v.body),
+                            line: v.line,
+                        }));
+                    }
+                }
+                Statement::Do(v) => optimized.push(Statement::Do(DoStatement {
+                    subroutine_call: self.optimize_call(v.subroutine_call),
+                    line:            v.line,
+                })),
+                Statement::Return(v) => optimized.push(Statement::Return(ReturnStatement {
+                    expr: v.expr.map(|v| self.optimize_expression(v)),
+                    line: v.line,
+                })),
+            }
+        }
+
+        optimized
+    }
+
+    // Jack has no operator precedence, so an expression is evaluated strictly
+    // from left to right. The folded prefix is kept in `acc` and each `op term`
+    // is either merged into it or appended as is.
+    fn optimize_expression(&self, expr: Expression) -> Expression {
+        let mut acc = Expression {
+            term:     Box::new(self.optimize_term(*expr.term)),
+            op_terms: vec![],
+        };
+
+        for op_term in expr.op_terms.into_iter() {
+            let op = op_term.op;
+            let term = self.optimize_term(op_term.term);
+            let lhs = self.expression_value(&acc);
+            let rhs = self.term_value(&term);
+
+            acc = match (lhs, rhs) {
+                (Some(x), Some(y)) => match fold(&op, x, y) {
+                    Some(value) => single(const_term(value)),
+                    None => append(acc, op, term),
+                },
+                (_, Some(y)) => match (&op, y) {
+                    // x + 0, x - 0, x | 0
+                    (Op::Add | Op::Minus | Op::Or, 0) => acc,
+                    // x * 1, x / 1, x & -1
+                    (Op::Multiply | Op::Divid, 1) | (Op::And, -1) => acc,
+                    // x * 0, x & 0
+                    (Op::Multiply | Op::And, 0) if is_pure_expression(&acc) => {
+                        single(const_term(0))
+                    }
+                    // x * 2 => x + x
+                    (Op::Multiply, 2) if acc.op_terms.is_empty() && is_variable(&acc.term) => {
+                        twice(into_term(acc))
+                    }
+                    _ => append(acc, op, term),
+                },
+                (Some(x), _) => match (&op, x) {
+                    // 0 + x, 0 | x
+                    (Op::Add | Op::Or, 0) => single(term),
+                    // 1 * x, -1 & x
+                    (Op::Multiply, 1) | (Op::And, -1) => single(term),
+                    // 0 * x, 0 & x
+                    (Op::Multiply | Op::And, 0) if is_pure_term(&term) => single(const_term(0)),
+                    // 2 * x => x + x
+                    (Op::Multiply, 2) if is_variable(&term) => twice(term),
+                    _ => append(acc, op, term),
+                },
+                (None, None) => append(acc, op, term),
+            };
+        }
+
+        acc
+    }
+
+    fn optimize_term(&self, term: Term) -> Term {
+        match term {
+            // (x) => x
+            Term::Expression(v) => into_term(self.optimize_expression(v)),
+            Term::UnaryExpression(v) => {
+                let term = self.optimize_term(*v.term);
+                if let Some(value) = self.term_value(&term) {
+                    let value = match v.unary_op {
+                        UnaryOp::Neg => value.wrapping_neg(),
+                        UnaryOp::Not => !value,
+                    };
+                    return const_term(value);
+                }
+                match term {
+                    // --x => x, ~~x => x
+                    Term::UnaryExpression(inner) if inner.unary_op == v.unary_op => *inner.term,
+                    term => Term::UnaryExpression(UnaryExpression {
+                        unary_op: v.unary_op,
+                        term:     Box::new(term),
+                    }),
+                }
+            }
+            Term::Array(v) => Term::Array(Array {
+                name:  v.name,
+                index: Box::new(self.optimize_expression(*v.index)),
+            }),
+            Term::SubRoutineCall(v) => Term::SubRoutineCall(self.optimize_call(v)),
+            Term::VarName(_) | Term::ConstRef(_) => match self.term_value(&term) {
+                Some(value) => const_term(value),
+                None => term,
+            },
+            term => term,
+        }
+    }
+
+    fn optimize_call(&self, call: SubroutineCall) -> SubroutineCall {
+        match call {
+            SubroutineCall::Internal(v) => SubroutineCall::Internal(InternalCall {
+                name: v.name,
+                args: self.optimize_args(v.args),
+            }),
+            SubroutineCall::External(v) => SubroutineCall::External(ExternalCall {
+                name:            v.name,
+                subroutine_name: v.subroutine_name,
+                args:            self.optimize_args(v.args),
+            }),
+        }
+    }
+
+    fn optimize_args(&self, args: Args) -> Args {
+        Args(
+            args.0
+                .into_iter()
+                .map(|v| self.optimize_expression(v))
+                .collect(),
+        )
+    }
+}
+
+/// Evaluate a binary operator on 16-bit two's complement values the same way
+/// the Hack platform does. Division by zero is left to the runtime.
+fn fold(op: &Op, x: i16, y: i16) -> Option<i16> {
+    let value = match op {
+        Op::Add => x.wrapping_add(y),
+        Op::Minus => x.wrapping_sub(y),
+        Op::Multiply => x.wrapping_mul(y),
+        Op::Divid => {
+            if y == 0 {
+                return None;
+            }
+            x.wrapping_div(y)
+        }
+        Op::And => x & y,
+        Op::Or => x | y,
+        Op::Greater => bool_value(x > y),
+        Op::Less => bool_value(x < y),
+        Op::Euqal => bool_value(x == y),
+    };
+    Some(value)
+}
+
+fn bool_value(b: bool) -> i16 {
+    if b {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Returns the value of an expression if it is known at compile time.
+pub fn expression_value(expr: &Expression, lookup: &ConstLookup) -> Option<i16> {
+    let mut value = term_value(&expr.term, lookup)?;
+    for op_term in expr.op_terms.iter() {
+        value = fold(&op_term.op, value, term_value(&op_term.term, lookup)?)?;
+    }
+    Some(value)
+}
+
+/// Returns the value of a term if it is known at compile time.
+pub fn term_value(term: &Term, lookup: &ConstLookup) -> Option<i16> {
+    match term {
+        Term::IntegerConst(v) => i16::try_from(*v).ok(),
+        Term::KeywordConst(KeywordConstant::True) => Some(-1),
+        Term::KeywordConst(KeywordConstant::False | KeywordConstant::Null) => Some(0),
+        Term::VarName(v) => lookup(None, v),
+        Term::ConstRef(v) => lookup(Some(&v.class_name), &v.name),
+        Term::Expression(v) => expression_value(v, lookup),
+        Term::UnaryExpression(v) => {
+            let value = term_value(&v.term, lookup)?;
+            Some(match v.unary_op {
+                UnaryOp::Neg => value.wrapping_neg(),
+                UnaryOp::Not => !value,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Build the cheapest term that evaluates to `value`. Integer constants in
+/// Jack are limited to 0..32767, so negative values are expressed with `-`,
+/// and -32768 as `~32767`.
+pub fn const_term(value: i16) -> Term {
+    if value >= 0 {
+        Term::IntegerConst(value as u32)
+    } else if value == i16::MIN {
+        Term::UnaryExpression(UnaryExpression {
+            unary_op: UnaryOp::Not,
+            term:     Box::new(Term::IntegerConst(i16::MAX as u32)),
+        })
+    } else {
+        Term::UnaryExpression(UnaryExpression {
+            unary_op: UnaryOp::Neg,
+            term:     Box::new(Term::IntegerConst(-value as u32)),
+        })
+    }
+}
+
+// Whether a term is a variable, whose value one push command gives. Then
+// `x + x` takes no more commands than `x * 2` and calls no Math.multiply.
+// Other terms would be evaluated twice, and larger powers of two need more
+// additions than the call costs in the ROM, so they are left to Math.multiply
+// or to the shifts of the extended instruction set.
+fn is_variable(term: &Term) -> bool {
+    matches!(term, Term::VarName(_))
+}
+
+// x + x for a variable x
+fn twice(term: Term) -> Expression {
+    let Term::VarName(name) = &term else {
+        unreachable!("not a variable");
+    };
+    let copy = Term::VarName(name.clone());
+    append(single(term), Op::Add, copy)
+}
+
+// A term is pure when evaluating it has no side effects, so it can be dropped
+// without changing the behaviour of the program.
+fn is_pure_term(term: &Term) -> bool {
+    match term {
+        Term::IntegerConst(_) | Term::KeywordConst(_) | Term::VarName(_) | Term::ConstRef(_) => {
+            true
+        }
+        Term::Array(v) => is_pure_expression(&v.index),
+        Term::Expression(v) => is_pure_expression(v),
+        Term::UnaryExpression(v) => is_pure_term(&v.term),
+        Term::StringConst(_) | Term::SubRoutineCall(_) => false,
+    }
+}
+
+// The first integer constant of an expression too large for a 16-bit value
+fn large_integer(expr: &Expression) -> Option<u32> {
+    std::iter::once(expr.term.as_ref())
+        .chain(expr.op_terms.iter().map(|v| &v.term))
+        .find_map(large_integer_term)
+}
+
+fn large_integer_term(term: &Term) -> Option<u32> {
+    match term {
+        Term::IntegerConst(v) if *v > i16::MAX as u32 => Some(*v),
+        Term::Expression(v) => large_integer(v),
+        Term::UnaryExpression(v) => large_integer_term(&v.term),
+        _ => None,
+    }
+}
+
+fn is_pure_expression(expr: &Expression) -> bool {
+    is_pure_term(&expr.term) && expr.op_terms.iter().all(|v| is_pure_term(&v.term))
+}
+
+fn single(term: Term) -> Expression {
+    Expression {
+        term:     Box::new(term),
+        op_terms: vec![],
+    }
+}
+
+fn append(mut acc: Expression, op: Op, term: Term) -> Expression {
+    acc.op_terms.push(OpTerm { op, term });
+    acc
+}
+
+fn into_term(expr: Expression) -> Term {
+    if expr.op_terms.is_empty() {
+        *expr.term
+    } else {
+        Term::Expression(expr)
+    }
+}
+
+/// Evaluate the constants declared by `classes`. A constant may refer to
+/// constants declared later or in other classes, so the declarations are
+/// evaluated repeatedly until no more of them can be resolved.
+pub fn resolve_constants(classes: &[&Class]) -> Result<ConstTable, String> {
+    let mut constants = ConstTable::new();
+    let mut pending: Vec<(&str, &ConstDec)> = classes
+        .iter()
+        .flat_map(|class| class.consts.iter().map(|v| (class.name.as_str(), v)))
+        .collect();
+
+    let mut declared = HashSet::new();
+    for (class_name, dec) in pending.iter() {
+        if !declared.insert((*class_name, dec.name.as_str())) {
+            return Err(format!(
+                "invalid: duplicate constant: {}.{}, line: {}",
+                class_name, dec.name, dec.line
+            ));
+        }
+        // an integer constant of Jack is at most 32767, -32768 is ~32767
+        if let Some(value) = large_integer(&dec.value) {
+            return Err(format!(
+                "invalid: constant out of range 0..{}: {}.{} = {}, line: {}",
+                i16::MAX,
+                class_name,
+                dec.name,
+                value,
+                dec.line
+            ));
+        }
+    }
+
+    while !pending.is_empty() {
+        let mut unresolved = vec![];
+
+        for (class_name, dec) in pending.iter() {
+            let lookup =
+                |class: Option<&str>, name: &str| constants.get(class.unwrap_or(class_name), name);
+            match expression_value(&dec.value, &lookup) {
+                Some(value) => constants.define(class_name, dec.name.clone(), value),
+                None => unresolved.push((*class_name, *dec)),
+            }
+        }
+
+        if unresolved.len() == pending.len() {
+            let names: Vec<String> = unresolved
+                .iter()
+                .map(|(class_name, dec)| format!("{}.{}", class_name, dec.name))
+                .collect();
+            return Err(format!(
+                "invalid: constants are undefined or cyclic: {}",
+                names.join(", ")
+            ));
+        }
+        pending = unresolved;
+    }
+
+    Ok(constants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jack_compiler::parse;
+    use crate::vm_writer::VmWriter;
+
+    // The optimized VM commands of the body of `Main.f`, without its
+    // `function` command
+    fn optimize(consts: &str, body: &str) -> Vec<String> {
+        let source = format!(
+            "class Main {{ {} function int f(int a) {{ var int x; {} }} }}",
+            consts, body
+        );
+        let class = parse(&source).unwrap();
+        let constants = resolve_constants(&[&class]).unwrap();
+        let mut optimizer = Optimizer::new(class, constants.clone());
+        optimizer.run();
+
+        let mut vm_writer = VmWriter::new(optimizer.ast(), constants, None);
+        vm_writer.run().unwrap();
+        String::from_utf8(vm_writer.output())
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|v| v.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn fold_constants() {
+        assert_eq!(optimize("", "return (2 + 3) * 4 - 1;"), [
+            "push constant 19",
+            "return"
+        ]);
+        // left to right, without precedence
+        assert_eq!(optimize("", "return 2 + 3 * 4;"), [
+            "push constant 20",
+            "return"
+        ]);
+        assert_eq!(optimize("", "return 1 < 2;"), [
+            "push constant 1",
+            "neg",
+            "return"
+        ]);
+        assert_eq!(optimize("", "return 7 / 0;"), [
+            "push constant 7",
+            "push constant 0",
+            "call Math.divide 2",
+            "return"
+        ]);
+    }
+
+    #[test]
+    fn fold_with_overflow_wrap() {
+        assert_eq!(optimize("", "return 32767 + 1;"), [
+            "push constant 32767",
+            "not",
+            "return"
+        ]);
+        assert_eq!(optimize("", "return 200 * 200;"), [
+            "push constant 25536",
+            "neg",
+            "return"
+        ]);
+        assert_eq!(optimize("", "return -(~32767);"), [
+            "push constant 32767",
+            "not",
+            "return"
+        ]);
+    }
+
+    #[test]
+    fn remove_identities() {
+        let push_a = ["push argument 0", "return"];
+        assert_eq!(optimize("", "return a + 0;"), push_a);
+        assert_eq!(optimize("", "return 0 + a;"), push_a);
+        assert_eq!(optimize("", "return a * 1;"), push_a);
+        assert_eq!(optimize("", "return a / 1;"), push_a);
+        assert_eq!(optimize("", "return a | 0;"), push_a);
+        assert_eq!(optimize("", "return -1 & a;"), push_a);
+        assert_eq!(optimize("", "return --a;"), push_a);
+        assert_eq!(optimize("", "return a * 0;"), ["push constant 0", "return"]);
+        // the call has side effects
+        assert_eq!(optimize("", "return Main.f(1) * 0;"), [
+            "push constant 1",
+            "call Main.f 1",
+            "push constant 0",
+            "call Math.multiply 2",
+            "return"
+        ]);
+    }
+
+    #[test]
+    fn double_only_variables() {
+        let twice = ["push argument 0", "push argument 0", "add", "return"];
+        assert_eq!(optimize("", "return a * 2;"), twice);
+        assert_eq!(optimize("", "return 2 * a;"), twice);
+        // more additions than the call would cost
+        assert_eq!(optimize("", "return a * 4;"), [
+            "push argument 0",
+            "push constant 4",
+            "call Math.multiply 2",
+            "return"
+        ]);
+        // a + 1 would be evaluated twice
+        assert_eq!(optimize("", "return (a + 1) * 2;"), [
+            "push argument 0",
+            "push constant 1",
+            "add",
+            "push constant 2",
+            "call Math.multiply 2",
+            "return"
+        ]);
+    }
+
+    #[test]
+    fn shadowed_constant() {
+        let consts = "const int a = 5; const int N = 3;";
+        assert_eq!(optimize(consts, "return a + N;"), [
+            "push argument 0",
+            "push constant 3",
+            "add",
+            "return"
+        ]);
+        assert_eq!(optimize(consts, "return Main.a + N;"), [
+            "push constant 8",
+            "return"
+        ]);
+    }
+
+    #[test]
+    fn fold_conditions() {
+        assert_eq!(
+            optimize(
+                "",
+                "if (1 < 2) { let x = 1; } else { let x = 2; } return x;"
+            ),
+            ["push constant 1", "pop local 0", "push local 0", "return"]
+        );
+        assert_eq!(
+            optimize(
+                "",
+                "if (false) { let x = 1; } else { let x = 2; } return x;"
+            ),
+            ["push constant 2", "pop local 0", "push local 0", "return"]
+        );
+        assert_eq!(
+            optimize(
+                "const int DEBUG = 0;",
+                "while (DEBUG) { let x = 1; } return x;"
+            ),
+            ["push local 0", "return"]
+        );
+        // left to the VmWriter, which drops the test of the loop
+        assert_eq!(optimize("", "while (~false) { let x = 1; } return x;"), [
+            "label loop_start_0",
+            "push constant 1",
+            "pop local 0",
+            "goto loop_start_0",
+            "label loop_end_1",
+            "push local 0",
+            "return"
+        ]);
+    }
+
+    fn resolve(source: &str) -> Result<ConstTable, String> {
+        resolve_constants(&[&parse(source).unwrap()])
+    }
+
+    #[test]
+    fn resolve_across_declarations() {
+        let constants = resolve(
+            "class Main {
+                const int B = A * 2;
+                enum Color { RED, GREEN = 5, BLUE }
+                const int A = BLUE + Main.RED;
+            }",
+        )
+        .unwrap();
+        assert_eq!(constants.get("Main", "A"), Some(6));
+        assert_eq!(constants.get("Main", "B"), Some(12));
+        assert_eq!(constants.get("Main", "GREEN"), Some(5));
+
+        assert_eq!(
+            resolve("class Main { const int A = B; const int B = A; }").err(),
+            Some("invalid: constants are undefined or cyclic: Main.A, Main.B".to_owned())
+        );
+    }
+
+    #[test]
+    fn duplicate_constant() {
+        assert_eq!(
+            resolve("class Main {\n const int A = 1;\n const int A = 2;\n}").err(),
+            Some("invalid: duplicate constant: Main.A, line: 3".to_owned())
+        );
+        assert_eq!(
+            resolve("class Main {\n const int RED = 1;\n enum Color { GREEN, RED }\n}").err(),
+            Some("invalid: duplicate constant: Main.RED, line: 3".to_owned())
+        );
+    }
+
+    #[test]
+    fn constant_out_of_range() {
+        assert_eq!(
+            resolve("class Main {\n const int X = 40000;\n}").err(),
+            Some("invalid: constant out of range 0..32767: Main.X = 40000, line: 2".to_owned())
+        );
+        assert_eq!(
+            resolve("class Main {\n enum E { A = -(1 + 32768) }\n}").err(),
+            Some("invalid: constant out of range 0..32767: Main.A = 32768, line: 2".to_owned())
+        );
+        // -32768 fits, as the result of an expression
+        let constants = resolve("class Main { const int MIN = -32767 - 1; }").unwrap();
+        assert_eq!(constants.get("Main", "MIN"), Some(i16::MIN));
+    }
+}
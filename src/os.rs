@@ -0,0 +1,99 @@
+// The Jack OS, written in Jack and embedded in the binary so that programs
+// can be linked without the VM files of the course.
+
+/// (class name, Jack source) of every OS class
+pub const CLASSES: [(&str, &str); 8] = [
+    ("Array", include_str!("os/Array.jack")),
+    ("Keyboard", include_str!("os/Keyboard.jack")),
+    ("Math", include_str!("os/Math.jack")),
+    ("Memory", include_str!("os/Memory.jack")),
+    ("Output", include_str!("os/Output.jack")),
+    ("Screen", include_str!("os/Screen.jack")),
+    ("String", include_str!("os/String.jack")),
+    ("Sys", include_str!("os/Sys.jack")),
+];
+
+/// The Jack source of an OS class
+pub fn source(class_name: &str) -> Option<&'static str> {
+    CLASSES
+        .iter()
+        .find(|(name, _)| *name == class_name)
+        .map(|(_, source)| *source)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::emulator::Emulator;
+    use crate::jack_compiler::compile_source;
+    use crate::vm_translator::{Bootstrap, OsOptions, VmTranslator};
+
+    const MAIN: &str = "
+class Main {
+    function void main() {
+        var Array a, b;
+        let a = Array.new(3);
+        let a[0] = -7 * 6;
+        let a[1] = 100 / -7;
+        let a[2] = Math.sqrt(1000);
+        do Memory.poke(8000, a[0]);
+        do Memory.poke(8001, a[1]);
+        do Memory.poke(8002, a[2]);
+        do Memory.poke(8003, a);
+        do a.dispose();
+        let b = Array.new(3);
+        do Memory.poke(8004, b);
+        do Output.printInt(-42);
+        return;
+    }
+}
+";
+
+    // Compile Main with the Jack OS down to machine code and run it until
+    // Sys.halt
+    fn run(main: &str) -> Emulator {
+        let (_, vm) = compile_source(main).unwrap();
+        let os = OsOptions {
+            link: true,
+            dir:  None,
+        };
+        let bootstrap = Bootstrap {
+            sp:    256,
+            entry: "Sys.init".to_owned(),
+        };
+        let vm_sources = vec![("Main".to_owned(), String::from_utf8(vm).unwrap())];
+        let mut vm_translator =
+            VmTranslator::new(vm_sources, false, os, true, Some(bootstrap)).unwrap();
+        assert!(!vm_translator.diagnostics().iter().any(|v| v.is_error()));
+        vm_translator.run().unwrap();
+
+        let asm = String::from_utf8(vm_translator.output().to_vec()).unwrap();
+        let mut assembler = Assembler::from_source(&asm);
+        assembler.run().unwrap();
+
+        let mut emulator = Emulator::new(assembler.binary());
+        emulator.run(50_000_000);
+        assert!(emulator.is_halted());
+        emulator
+    }
+
+    #[test]
+    fn run_on_the_emulator() {
+        let emulator = run(MAIN);
+        let ram = |address: usize| emulator.ram[address] as i16;
+
+        // Math
+        assert_eq!((ram(8000), ram(8001), ram(8002)), (-42, -14, 31));
+        // Memory reuses the block of the disposed Array
+        assert!((2048..16384).contains(&ram(8003)));
+        assert_eq!(ram(8004), ram(8003));
+        // Output draws `-42` from the top left corner, `-` in the low byte
+        // of the first word and `4` in its high byte
+        let screen = |row: usize, word: usize| emulator.ram[16384 + row * 32 + word];
+        assert_eq!(screen(0, 0), 16 << 8);
+        assert_eq!(screen(5, 0), 63 | (63 << 8));
+        assert_eq!(screen(0, 1), 30);
+        assert_eq!(screen(5, 1), 6);
+        assert_eq!(screen(0, 2), 0);
+    }
+}
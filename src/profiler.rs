@@ -0,0 +1,300 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+// Cycles executed before the first function is entered, such as the
+// bootstrap code, are attributed to this pseudo function.
+const ROOT: &str = "<program>";
+
+/// Attributes the cycles of a running Hack program to the VM functions it
+/// was translated from.
+///
+/// A function is entered when the program counter reaches one of the
+/// `(Class.func)` labels written by the VM translator for `function`, and
+/// left when it reaches one of the `(End$Class.func$N)` return labels written
+/// for `call`.
+pub struct Profiler {
+    names:            Vec<String>,
+    entries:          HashMap<u16, usize>,
+    return_addresses: HashSet<u16>,
+    stack:            Vec<Frame>,
+    // number of frames of each function on the stack, so the inclusive time
+    // of recursive calls is only counted once
+    active:           Vec<u32>,
+    exclusive:        Vec<u64>,
+    inclusive:        Vec<u64>,
+    calls:            Vec<u64>,
+    // (caller, callee) -> (calls, inclusive cycles)
+    edges:            HashMap<(usize, usize), (u64, u64)>,
+    // call stack -> exclusive cycles of its top
+    stacks:           HashMap<Vec<usize>, u64>,
+    // cycle when the call stack last changed
+    last_cycle:       u64,
+}
+
+struct Frame {
+    function: usize,
+    entered:  u64,
+}
+
+impl Profiler {
+    /// `labels` are the label symbols of the assembled program
+    pub fn new(labels: &HashMap<String, u32>) -> Self {
+        let mut names = vec![ROOT.to_owned()];
+        let mut entries = HashMap::new();
+        let mut return_addresses = HashSet::new();
+
+        let mut labels: Vec<(&String, &u32)> = labels.iter().collect();
+        labels.sort();
+
+        for (label, address) in labels.into_iter() {
+            if label.starts_with("End$") || label == "bootstrap" {
+                return_addresses.insert(*address as u16);
+            } else if label.contains('.') && !label.contains('$') {
+                entries.insert(*address as u16, names.len());
+                names.push(label.to_owned());
+            }
+        }
+
+        let count = names.len();
+        Self {
+            names,
+            entries,
+            return_addresses,
+            stack: vec![Frame {
+                function: 0,
+                entered:  0,
+            }],
+            active: vec![0; count],
+            exclusive: vec![0; count],
+            inclusive: vec![0; count],
+            calls: vec![0; count],
+            edges: HashMap::new(),
+            stacks: HashMap::new(),
+            last_cycle: 0,
+        }
+    }
+
+    /// Called with the address of every instruction before it is executed,
+    /// `cycle` being the number of instructions executed so far
+    pub fn record(&mut self, pc: u16, cycle: u64) {
+        if self.return_addresses.contains(&pc) && self.stack.len() > 1 {
+            self.flush(cycle);
+            self.leave(cycle);
+        }
+        if let Some(&function) = self.entries.get(&pc) {
+            self.flush(cycle);
+            self.enter(function, cycle);
+        }
+    }
+
+    /// Close the frames still on the call stack when the program stops
+    pub fn finish(&mut self, cycle: u64) {
+        self.flush(cycle);
+        while self.stack.len() > 1 {
+            self.leave(cycle);
+        }
+        self.inclusive[0] = cycle;
+    }
+
+    fn enter(&mut self, function: usize, cycle: u64) {
+        let caller = self.stack.last().unwrap().function;
+
+        self.calls[function] += 1;
+        self.edges.entry((caller, function)).or_default().0 += 1;
+        self.active[function] += 1;
+        self.stack.push(Frame {
+            function,
+            entered: cycle,
+        });
+    }
+
+    fn leave(&mut self, cycle: u64) {
+        let frame = self.stack.pop().unwrap();
+        let caller = self.stack.last().unwrap().function;
+        let elapsed = cycle - frame.entered;
+
+        self.active[frame.function] -= 1;
+        if self.active[frame.function] == 0 {
+            self.inclusive[frame.function] += elapsed;
+        }
+        self.edges.entry((caller, frame.function)).or_default().1 += elapsed;
+    }
+
+    // Attribute the cycles since the call stack last changed to its top
+    fn flush(&mut self, cycle: u64) {
+        let elapsed = cycle - self.last_cycle;
+        self.last_cycle = cycle;
+        if elapsed == 0 {
+            return;
+        }
+
+        let top = self.stack.last().unwrap().function;
+        self.exclusive[top] += elapsed;
+
+        let stack: Vec<usize> = self.stack.iter().map(|v| v.function).collect();
+        *self.stacks.entry(stack).or_default() += elapsed;
+    }
+
+    /// Flat profile sorted by exclusive cycles, followed by the call graph
+    pub fn report(&self) -> Vec<u8> {
+        let mut output = vec![];
+        let total = self.inclusive[0].max(1);
+
+        let mut functions: Vec<usize> = (0..self.names.len())
+            .filter(|&i| i == 0 || self.calls[i] > 0)
+            .collect();
+        functions.sort_by(|&x, &y| {
+            self.exclusive[y]
+                .cmp(&self.exclusive[x])
+                .then(self.names[x].cmp(&self.names[y]))
+        });
+
+        writeln!(&mut output, "Flat profile ({} cycles):", self.inclusive[0]).unwrap();
+        writeln!(
+            &mut output,
+            "{:>7} {:>12} {:>12} {:>8}  function",
+            "%time", "exclusive", "inclusive", "calls"
+        )
+        .unwrap();
+        for &i in functions.iter() {
+            writeln!(
+                &mut output,
+                "{:>7.2} {:>12} {:>12} {:>8}  {}",
+                self.exclusive[i] as f64 * 100.0 / total as f64,
+                self.exclusive[i],
+                self.inclusive[i],
+                self.calls[i],
+                self.names[i]
+            )
+            .unwrap();
+        }
+
+        let mut edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|(&(caller, callee), &(calls, cycles))| (caller, callee, calls, cycles))
+            .collect();
+        edges.sort_by(|x, y| y.3.cmp(&x.3).then((x.0, x.1).cmp(&(y.0, y.1))));
+
+        functions.sort_by(|&x, &y| {
+            self.inclusive[y]
+                .cmp(&self.inclusive[x])
+                .then(self.names[x].cmp(&self.names[y]))
+        });
+
+        writeln!(&mut output, "\nCall graph (calls, inclusive cycles):").unwrap();
+        for &i in functions.iter() {
+            writeln!(
+                &mut output,
+                "{}  [exclusive {}, inclusive {}]",
+                self.names[i], self.exclusive[i], self.inclusive[i]
+            )
+            .unwrap();
+            for (caller, _, calls, cycles) in edges.iter().filter(|v| v.1 == i) {
+                writeln!(
+                    &mut output,
+                    "    <- {:<32} {:>8} {:>12}",
+                    self.names[*caller], calls, cycles
+                )
+                .unwrap();
+            }
+            for (_, callee, calls, cycles) in edges.iter().filter(|v| v.0 == i) {
+                writeln!(
+                    &mut output,
+                    "    -> {:<32} {:>8} {:>12}",
+                    self.names[*callee], calls, cycles
+                )
+                .unwrap();
+            }
+        }
+
+        output
+    }
+
+    /// One `outer;inner;innermost cycles` line per call stack, the input format
+    /// of flame graph tools
+    pub fn collapsed_stacks(&self) -> Vec<u8> {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<&str> = stack.iter().map(|&i| self.names[i].as_str()).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+
+        let mut output = vec![];
+        for line in lines.iter() {
+            writeln!(&mut output, "{}", line).unwrap();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiler(labels: &[(&str, u32)]) -> Profiler {
+        let labels = labels
+            .iter()
+            .map(|(label, address)| (label.to_string(), *address))
+            .collect();
+        Profiler::new(&labels)
+    }
+
+    fn lines(output: Vec<u8>) -> Vec<String> {
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|v| v.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn attribute_cycles_to_functions() {
+        let mut profiler = profiler(&[
+            ("Main.main", 10),
+            ("Math.f", 20),
+            ("End$Math.f$0", 15),
+            ("Main.main$loop", 12),
+        ]);
+        // bootstrap, Main.main calling Math.f, back in Main.main
+        let trace = [0, 1, 10, 11, 12, 20, 21, 22, 23, 15, 16];
+        for (cycle, pc) in trace.iter().enumerate() {
+            profiler.record(*pc, cycle as u64);
+        }
+        profiler.finish(trace.len() as u64);
+
+        assert_eq!(profiler.exclusive, [2, 5, 4]);
+        assert_eq!(profiler.inclusive, [11, 9, 4]);
+        assert_eq!(profiler.calls, [0, 1, 1]);
+        assert_eq!(lines(profiler.collapsed_stacks()), [
+            "<program> 2",
+            "<program>;Main.main 5",
+            "<program>;Main.main;Math.f 4"
+        ]);
+
+        let report = lines(profiler.report());
+        assert_eq!(report[0], "Flat profile (11 cycles):");
+        assert!(report[2].ends_with("  Main.main"));
+        assert!(report
+            .contains(&"    -> Math.f                                  1            4".to_owned()));
+    }
+
+    #[test]
+    fn count_recursion_once() {
+        let mut profiler = profiler(&[("Main.f", 20), ("End$Main.f$0", 30)]);
+        let trace = [20, 21, 20, 21, 22, 30, 31, 30];
+        for (cycle, pc) in trace.iter().enumerate() {
+            profiler.record(*pc, cycle as u64);
+        }
+        profiler.finish(trace.len() as u64);
+
+        assert_eq!(profiler.calls, [0, 2]);
+        assert_eq!(profiler.exclusive, [1, 7]);
+        // the outer call only, not the inner one within it
+        assert_eq!(profiler.inclusive, [8, 7]);
+    }
+}
@@ -9,6 +9,12 @@ pub struct SymbolTable {
     pub kind_index: HashMap<VarKind, u32>,
 }
 
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
@@ -48,6 +54,29 @@ impl SymbolTable {
     }
 }
 
+/// Compile-time constants of every class being compiled, by class name
+#[derive(Clone, Default)]
+pub struct ConstTable {
+    pub table: HashMap<String, HashMap<String, i16>>,
+}
+
+impl ConstTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(&mut self, class_name: &str, name: String, value: i16) {
+        self.table
+            .entry(class_name.to_owned())
+            .or_default()
+            .insert(name, value);
+    }
+
+    pub fn get(&self, class_name: &str, name: &str) -> Option<i16> {
+        self.table.get(class_name)?.get(name).copied()
+    }
+}
+
 #[derive(Clone)]
 pub struct Var {
     pub name:  String,
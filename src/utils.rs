@@ -1,12 +1,24 @@
+use std::ffi::OsStr;
 use std::fs;
-use std::io::{Result, Write};
-use std::path::PathBuf;
+use std::io::{self, Read, Result, Write};
+use std::path::{Path, PathBuf};
 
 pub fn substr(s: &str, start: usize, len: usize) -> String {
     s.chars().skip(start).take(len).collect()
 }
 
+/// Write `content` to `dest_path`, creating its directory if needed, or to the
+/// standard output when the path is `-`
 pub fn save_file(content: &[u8], dest_path: &PathBuf) -> Result<()> {
+    if is_stdio(dest_path) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(content)?;
+        return stdout.flush();
+    }
+
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
     let mut file_out = fs::OpenOptions::new()
         .create(true)
         .write(true)
@@ -16,3 +28,217 @@ pub fn save_file(content: &[u8], dest_path: &PathBuf) -> Result<()> {
     file_out.flush()?;
     Ok(())
 }
+
+/// Whether a path given on the command line stands for the standard input or
+/// output
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// A source file given on the command line, or the standard input for `-`
+pub enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    pub fn read(&self) -> String {
+        match self {
+            Input::Stdin => {
+                let mut source = String::new();
+                io::stdin().read_to_string(&mut source).unwrap();
+                source
+            }
+            Input::File(path) => fs::read_to_string(path).unwrap(),
+        }
+    }
+
+    pub fn read_bytes(&self) -> Vec<u8> {
+        match self {
+            Input::Stdin => {
+                let mut content = vec![];
+                io::stdin().read_to_end(&mut content).unwrap();
+                content
+            }
+            Input::File(path) => fs::read(path).unwrap(),
+        }
+    }
+
+    /// File name without extension, `stdin` for the standard input
+    pub fn name(&self) -> String {
+        match self {
+            Input::Stdin => "stdin".to_owned(),
+            Input::File(path) => path.file_stem().unwrap().to_str().unwrap().to_owned(),
+        }
+    }
+
+    /// Directory of the default `output` directory, None for the standard
+    /// input, whose output goes to the standard output
+    pub fn dir(&self) -> Option<PathBuf> {
+        match self {
+            Input::Stdin => None,
+            Input::File(path) => Some(path.parent().unwrap().to_path_buf()),
+        }
+    }
+}
+
+/// Expand a path given on the command line into the files with the given
+/// extension: a directory stands for its files sorted by name, and a path
+/// with `*`, `?` or `[...]` wildcards for the matching files
+pub fn expand_path(path: &OsStr, extension: &str) -> Vec<Input> {
+    let path = PathBuf::from(path);
+    if is_stdio(&path) {
+        return vec![Input::Stdin];
+    }
+
+    let has_extension =
+        |path: &PathBuf| path.is_file() && path.extension().is_some_and(|v| v == extension);
+
+    let mut files = vec![];
+    if path.is_dir() {
+        for file in fs::read_dir(&path).unwrap() {
+            let path = file.unwrap().path();
+            if has_extension(&path) {
+                files.push(path);
+            }
+        }
+        files.sort();
+    } else if path.exists() {
+        files.push(path);
+    } else {
+        let pattern = path.to_str().unwrap();
+        files.extend(
+            glob::glob(pattern)
+                .unwrap_or_else(|_| panic!("invalid: {}", pattern))
+                .map(|v| v.unwrap())
+                .filter(has_extension),
+        );
+        if files.is_empty() {
+            panic!("invalid: {}, no such file", pattern);
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|path| Input::File(path.canonicalize().unwrap()))
+        .collect()
+}
+
+/// Where the `--out` and `--out-dir` options put the output files
+pub struct OutputOptions {
+    /// The single output file, `-` being the standard output
+    pub out:     Option<PathBuf>,
+    pub out_dir: Option<PathBuf>,
+}
+
+impl OutputOptions {
+    /// The path of the output file `name` of an input in `dir`, which defaults
+    /// to `dir/output/name`, or to the standard output when there is no `dir`
+    pub fn path(&self, name: &str, dir: Option<&Path>) -> PathBuf {
+        if let Some(out) = &self.out {
+            return out.clone();
+        }
+        if let Some(out_dir) = &self.out_dir {
+            return out_dir.join(name);
+        }
+        match dir {
+            Some(dir) => dir.join("output").join(name),
+            None => PathBuf::from("-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory of its own for each test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "nand2tetris-rs-utils-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn names(inputs: &[Input]) -> Vec<String> {
+        inputs.iter().map(|v| v.name()).collect()
+    }
+
+    #[test]
+    fn expand_a_directory_in_name_order() {
+        let dir = temp_dir("directory");
+        for name in ["Sys.vm", "Main.vm", "Array.vm", "Main.jack", "Zero.vm.map"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        fs::create_dir(dir.join("Sub.vm")).unwrap();
+
+        let inputs = expand_path(dir.as_os_str(), "vm");
+        assert_eq!(names(&inputs), ["Array", "Main", "Sys"]);
+        assert!(matches!(&inputs[0], Input::File(path) if *path == dir.join("Array.vm")));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expand_globs_and_files() {
+        let dir = temp_dir("glob");
+        for name in ["Add.asm", "Max.asm", "Max.hack", "Pong.asm"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let pattern = dir.join("[AM]*.asm");
+        assert_eq!(names(&expand_path(pattern.as_os_str(), "asm")), [
+            "Add", "Max"
+        ]);
+        let pattern = dir.join("*");
+        assert_eq!(names(&expand_path(pattern.as_os_str(), "hack")), ["Max"]);
+        // a file is taken whatever its extension
+        let file = dir.join("Max.hack");
+        assert_eq!(names(&expand_path(file.as_os_str(), "asm")), ["Max"]);
+        assert!(matches!(expand_path(OsStr::new("-"), "asm")[..], [
+            Input::Stdin
+        ]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "no such file")]
+    fn reject_a_pattern_without_files() {
+        expand_path(OsStr::new("no/such/dir/*.asm"), "asm");
+    }
+
+    #[test]
+    fn place_output_files() {
+        let dir = Path::new("/prog");
+        let default = OutputOptions {
+            out:     None,
+            out_dir: None,
+        };
+        assert_eq!(
+            default.path("Main.vm", Some(dir)),
+            Path::new("/prog/output/Main.vm")
+        );
+        assert_eq!(default.path("stdin.vm", None), Path::new("-"));
+
+        let out_dir = OutputOptions {
+            out:     None,
+            out_dir: Some(PathBuf::from("build")),
+        };
+        assert_eq!(
+            out_dir.path("Main.vm", Some(dir)),
+            Path::new("build/Main.vm")
+        );
+        assert_eq!(out_dir.path("stdin.vm", None), Path::new("build/stdin.vm"));
+
+        let out = OutputOptions {
+            out:     Some(PathBuf::from("-")),
+            out_dir: None,
+        };
+        assert!(is_stdio(&out.path("Main.vm", Some(dir))));
+    }
+}
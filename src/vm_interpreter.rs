@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+
+use crate::emulator::RAM_SIZE;
+use crate::native_os::NativeFn;
+use crate::vm_writer::Segment;
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: usize = 5;
+const STATIC: u16 = 16;
+
+// Return address of the frames set up by `invoke` and the bootstrap, past the
+// end of any program
+const NO_RETURN: usize = usize::MAX;
+
+#[derive(Clone, Copy)]
+enum Command {
+    // the index of a static variable is replaced by its address
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(Arithmetic),
+    Label,
+    Goto(usize),
+    IfGoto(usize),
+    Function(u16),
+    Call(usize, u16),
+    Return,
+}
+
+#[derive(Clone, Copy)]
+enum Arithmetic {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+struct Function {
+    name:    String,
+    // index of its `function` command
+    address: Option<usize>,
+    native:  Option<NativeFn>,
+}
+
+/// Executes VM commands directly against a simulated RAM laid out like the
+/// Hack platform, where functions with a native implementation run in Rust
+/// instead of their VM code.
+pub struct VmInterpreter {
+    commands:         Vec<Command>,
+    functions:        Vec<Function>,
+    function_ids:     HashMap<String, usize>,
+    // (file name, index) -> address of a static variable
+    statics:          HashMap<(String, u16), u16>,
+    pub ram:          Vec<u16>,
+    pub pc:           usize,
+    // number of VM commands executed, native functions counting as one
+    pub cycles:       u64,
+    // return address of each frame on the stack, kept out of the RAM as a
+    // program may have more than 65536 commands
+    return_addresses: Vec<usize>,
+    halted:           bool,
+}
+
+impl VmInterpreter {
+    /// `vm_sources` are (file name without extension, VM commands) pairs, and
+    /// `natives` the functions run in Rust. The program starts by calling
+    /// `Sys.init` when it is defined, or else at its first command.
+    pub fn new(vm_sources: &[(String, String)], natives: &[(&str, NativeFn)]) -> Self {
+        let mut interpreter = Self {
+            commands:         vec![],
+            functions:        vec![],
+            function_ids:     HashMap::new(),
+            statics:          HashMap::new(),
+            ram:              vec![0; RAM_SIZE],
+            pc:               0,
+            cycles:           0,
+            return_addresses: vec![],
+            halted:           false,
+        };
+
+        for (name, native) in natives.iter() {
+            let id = interpreter.function_id(name);
+            interpreter.functions[id].native = Some(*native);
+        }
+        for (vm_filename, source) in vm_sources.iter() {
+            interpreter.load(vm_filename, source);
+        }
+
+        interpreter.ram[SP] = 256;
+        if let Some(&id) = interpreter.function_ids.get("Sys.init") {
+            if interpreter.functions[id].address.is_some() {
+                interpreter.pc = interpreter.call(id, 0, NO_RETURN);
+            }
+        }
+        interpreter
+    }
+
+    fn function_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.function_ids.get(name) {
+            return id;
+        }
+        self.functions.push(Function {
+            name:    name.to_owned(),
+            address: None,
+            native:  None,
+        });
+        self.function_ids
+            .insert(name.to_owned(), self.functions.len() - 1);
+        self.functions.len() - 1
+    }
+
+    /// Address of the static variable `index` of a *.vm file, allocated on
+    /// first use like the assembler allocates variables
+    pub fn static_address(&mut self, vm_filename: &str, index: u16) -> u16 {
+        let next = STATIC + self.statics.len() as u16;
+        *self
+            .statics
+            .entry((vm_filename.to_owned(), index))
+            .or_insert(next)
+    }
+
+    fn load(&mut self, vm_filename: &str, source: &str) {
+        let lines: Vec<(usize, Vec<&str>)> = source
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split("//").next().unwrap()))
+            .map(|(line_no, line)| (line_no, line.split_whitespace().collect::<Vec<_>>()))
+            .filter(|(_, parts)| !parts.is_empty())
+            .collect();
+
+        // labels are local to the function they are declared in
+        let start = self.commands.len();
+        let mut labels = HashMap::new();
+        let mut function = "";
+        for (i, (_, parts)) in lines.iter().enumerate() {
+            match parts[0] {
+                "function" => function = parts[1],
+                "label" => {
+                    labels.insert((function, parts[1]), start + i);
+                }
+                _ => {}
+            }
+        }
+
+        let mut function = "";
+        for (line_no, parts) in lines.iter() {
+            let line_no = *line_no;
+            let index = |i: usize| {
+                parts
+                    .get(i)
+                    .and_then(|v| v.parse::<u16>().ok())
+                    .unwrap_or_else(|| invalid(parts, line_no))
+            };
+            let name = |i: usize| *parts.get(i).unwrap_or_else(|| invalid(parts, line_no));
+
+            let command = match parts[0] {
+                "push" | "pop" => {
+                    let segment = parse_segment(name(1)).unwrap_or_else(|| invalid(parts, line_no));
+                    let mut index = index(2);
+                    if let Segment::Static = segment {
+                        index = self.static_address(vm_filename, index);
+                    }
+                    if parts[0] == "push" {
+                        Command::Push(segment, index)
+                    } else {
+                        Command::Pop(segment, index)
+                    }
+                }
+                "label" => Command::Label,
+                "goto" | "if-goto" => {
+                    let target = *labels.get(&(function, name(1))).unwrap_or_else(|| {
+                        panic!("undefined label: {}, line: {}", name(1), line_no)
+                    });
+                    if parts[0] == "goto" {
+                        Command::Goto(target)
+                    } else {
+                        Command::IfGoto(target)
+                    }
+                }
+                "function" => {
+                    function = name(1);
+                    let id = self.function_id(function);
+                    self.functions[id].address = Some(self.commands.len());
+                    Command::Function(index(2))
+                }
+                "call" => Command::Call(self.function_id(name(1)), index(2)),
+                "return" => Command::Return,
+                "add" => Command::Arithmetic(Arithmetic::Add),
+                "sub" => Command::Arithmetic(Arithmetic::Sub),
+                "neg" => Command::Arithmetic(Arithmetic::Neg),
+                "eq" => Command::Arithmetic(Arithmetic::Eq),
+                "gt" => Command::Arithmetic(Arithmetic::Gt),
+                "lt" => Command::Arithmetic(Arithmetic::Lt),
+                "and" => Command::Arithmetic(Arithmetic::And),
+                "or" => Command::Arithmetic(Arithmetic::Or),
+                "not" => Command::Arithmetic(Arithmetic::Not),
+                _ => invalid(parts, line_no),
+            };
+            self.commands.push(command);
+        }
+    }
+
+    /// The program has halted when it runs past its last command, returns
+    /// from `Sys.init`, calls the native `Sys.halt` or spins in a
+    /// `label L; goto L` loop.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    /// Run until the program halts or `max_cycles` commands have been
+    /// executed in total.
+    pub fn run(&mut self, max_cycles: u64) {
+        while !self.halted && self.cycles < max_cycles {
+            self.step();
+        }
+    }
+
+    pub fn step(&mut self) {
+        if self.halted || self.pc >= self.commands.len() {
+            self.halted = true;
+            return;
+        }
+
+        self.cycles += 1;
+        let mut next = self.pc + 1;
+
+        match self.commands[self.pc] {
+            Command::Push(segment, index) => {
+                let value = self.read(segment, index);
+                self.push(value);
+            }
+            Command::Pop(segment, index) => {
+                let value = self.pop();
+                self.write(segment, index, value);
+            }
+            Command::Arithmetic(op) => self.arithmetic(op),
+            Command::Label => {}
+            Command::Goto(target) => {
+                if target + 1 == self.pc {
+                    self.halted = true;
+                }
+                next = target;
+            }
+            Command::IfGoto(target) => {
+                if self.pop() != 0 {
+                    next = target;
+                }
+            }
+            Command::Function(locals) => {
+                for _ in 0..locals {
+                    self.push(0);
+                }
+            }
+            Command::Call(id, args) => next = self.call(id, args, next),
+            Command::Return => next = self.ret(),
+        }
+
+        self.pc = next;
+    }
+
+    /// Call a function from native code and return its result, running its
+    /// VM code to completion unless it is native too
+    pub fn invoke(&mut self, name: &str, args: &[i16]) -> i16 {
+        let id = *self
+            .function_ids
+            .get(name)
+            .unwrap_or_else(|| panic!("undefined function: {}", name));
+        for arg in args.iter() {
+            self.push(*arg as u16);
+        }
+
+        let pc = self.pc;
+        let frames = self.return_addresses.len();
+        self.pc = self.call(id, args.len() as u16, NO_RETURN);
+        while self.return_addresses.len() > frames && !self.halted {
+            self.step();
+        }
+        self.pc = pc;
+
+        self.pop() as i16
+    }
+
+    // Returns the index of the next command to execute
+    fn call(&mut self, id: usize, args: u16, return_address: usize) -> usize {
+        if let Some(native) = self.functions[id].native {
+            let sp = self.ram[SP] - args;
+            let values: Vec<i16> = self.ram[sp as usize..(sp + args) as usize]
+                .iter()
+                .map(|v| *v as i16)
+                .collect();
+            self.ram[SP] = sp;
+
+            let result = native(self, &values);
+            self.push(result as u16);
+            return return_address;
+        }
+
+        let address = self.functions[id]
+            .address
+            .unwrap_or_else(|| panic!("undefined function: {}", self.functions[id].name));
+
+        // the frame keeps its RAM layout with a placeholder return address
+        self.push(0);
+        for pointer in [LCL, ARG, THIS, THAT] {
+            self.push(self.ram[pointer]);
+        }
+        self.ram[ARG] = self.ram[SP] - args - 5;
+        self.ram[LCL] = self.ram[SP];
+        self.return_addresses.push(return_address);
+
+        address
+    }
+
+    fn ret(&mut self) -> usize {
+        let frame = self.ram[LCL] as usize;
+        let return_address = self.return_addresses.pop().unwrap_or(NO_RETURN);
+
+        let value = self.pop();
+        let arg = self.ram[ARG];
+        self.ram[arg as usize] = value;
+        self.ram[SP] = arg + 1;
+
+        for (i, pointer) in [THAT, THIS, ARG, LCL].into_iter().enumerate() {
+            self.ram[pointer] = self.ram[frame - 1 - i];
+        }
+
+        return_address
+    }
+
+    fn arithmetic(&mut self, op: Arithmetic) {
+        let y = self.pop() as i16;
+        let value = match op {
+            Arithmetic::Neg => y.wrapping_neg(),
+            Arithmetic::Not => !y,
+            _ => {
+                let x = self.pop() as i16;
+                match op {
+                    Arithmetic::Add => x.wrapping_add(y),
+                    Arithmetic::Sub => x.wrapping_sub(y),
+                    Arithmetic::And => x & y,
+                    Arithmetic::Or => x | y,
+                    Arithmetic::Eq => -((x == y) as i16),
+                    Arithmetic::Gt => -((x > y) as i16),
+                    Arithmetic::Lt => -((x < y) as i16),
+                    Arithmetic::Neg | Arithmetic::Not => unreachable!(),
+                }
+            }
+        };
+        self.push(value as u16);
+    }
+
+    fn address(&self, segment: Segment, index: u16) -> usize {
+        let address = match segment {
+            Segment::Local => self.ram[LCL].wrapping_add(index),
+            Segment::Argument => self.ram[ARG].wrapping_add(index),
+            Segment::This => self.ram[THIS].wrapping_add(index),
+            Segment::That => self.ram[THAT].wrapping_add(index),
+            Segment::Pointer => THIS as u16 + index,
+            Segment::Temp => TEMP as u16 + index,
+            Segment::Static => index,
+            Segment::Constant => unreachable!(),
+        };
+        address as usize
+    }
+
+    fn read(&self, segment: Segment, index: u16) -> u16 {
+        match segment {
+            Segment::Constant => index,
+            _ => self.peek(self.address(segment, index)),
+        }
+    }
+
+    fn write(&mut self, segment: Segment, index: u16, value: u16) {
+        match segment {
+            Segment::Constant => panic!("invalid: pop constant {}", index),
+            _ => self.poke(self.address(segment, index), value),
+        }
+    }
+
+    pub fn peek(&self, address: usize) -> u16 {
+        if address >= RAM_SIZE {
+            panic!(
+                "invalid address: {}, function: {}",
+                address,
+                self.function_at(self.pc)
+            );
+        }
+        self.ram[address]
+    }
+
+    pub fn poke(&mut self, address: usize, value: u16) {
+        if address >= RAM_SIZE {
+            panic!(
+                "invalid address: {}, function: {}",
+                address,
+                self.function_at(self.pc)
+            );
+        }
+        self.ram[address] = value;
+    }
+
+    pub fn push(&mut self, value: u16) {
+        let sp = self.ram[SP];
+        self.poke(sp as usize, value);
+        self.ram[SP] = sp + 1;
+    }
+
+    pub fn pop(&mut self) -> u16 {
+        self.ram[SP] -= 1;
+        self.peek(self.ram[SP] as usize)
+    }
+
+    // Name of the function a command belongs to, for error messages
+    fn function_at(&self, pc: usize) -> &str {
+        self.functions
+            .iter()
+            .filter(|v| v.address.is_some_and(|address| address <= pc))
+            .max_by_key(|v| v.address)
+            .map_or("-", |v| v.name.as_str())
+    }
+}
+
+fn invalid<T>(parts: &[&str], line_no: usize) -> T {
+    panic!("invalid: {}, line: {}", parts.join(" "), line_no);
+}
+
+fn parse_segment(segment: &str) -> Option<Segment> {
+    match segment {
+        "argument" => Some(Segment::Argument),
+        "local" => Some(Segment::Local),
+        "static" => Some(Segment::Static),
+        "constant" => Some(Segment::Constant),
+        "this" => Some(Segment::This),
+        "that" => Some(Segment::That),
+        "pointer" => Some(Segment::Pointer),
+        "temp" => Some(Segment::Temp),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn return_past_16_bit_addresses() {
+        let mut source = "function Sys.init 0\n".to_owned();
+        for _ in 0..40_000 {
+            source.push_str("push constant 1\npop temp 0\n");
+        }
+        source.push_str("call Foo.seven 0\npop temp 1\nlabel END\ngoto END\n");
+        source.push_str("function Foo.seven 0\npush constant 7\nreturn\n");
+
+        let mut interpreter = VmInterpreter::new(&[("Sys".to_owned(), source)], &[]);
+        interpreter.run(1_000_000);
+        assert!(interpreter.is_halted());
+        assert_eq!(interpreter.ram[TEMP + 1], 7);
+        assert_eq!(interpreter.pc, 80_003);
+    }
+}
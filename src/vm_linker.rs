@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// What the dead function elimination found
+pub struct Reachability {
+    /// Functions no root reaches, in the order of the files
+    pub removed: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found when linking *.vm files, located at a line of a file
+/// unless it concerns the whole program
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message:  String,
+    // (file name without extension, line number)
+    pub location: Option<(String, usize)>,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        matches!(self.severity, Severity::Error)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match &self.location {
+            Some((vm_filename, line_no)) => write!(
+                f,
+                "{}: {}, file: {}.vm, line: {}",
+                severity, self.message, vm_filename, line_no
+            ),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+// The commands of a file from a `function` command up to the next one, or
+// the commands preceding its first function when `name` is None
+struct Chunk {
+    source: usize,
+    name:   Option<String>,
+    // indexes of its first and past its last line in the source
+    start:  usize,
+    end:    usize,
+    calls:  Vec<String>,
+}
+
+/// Names of the functions defined by VM commands
+pub fn defined_functions(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .filter_map(|line| match command(line)[..] {
+            ["function", name, ..] => Some(name.to_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Blank out the functions of `vm_sources` that cannot be reached over `call`
+/// commands from `roots`, nor from the commands preceding the first function
+/// of a file, which run when a file is not made of functions only. Lines are
+/// blanked rather than deleted so that line numbers still match the files.
+pub fn eliminate_dead_functions(
+    vm_sources: &mut [(String, String)],
+    roots: &[String],
+) -> Reachability {
+    let chunks: Vec<Chunk> = vm_sources
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_, source))| split_functions(i, source))
+        .collect();
+
+    // a function defined twice keeps both definitions alive
+    let mut definitions: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        if let Some(name) = &chunk.name {
+            definitions.entry(name.as_str()).or_default().push(i);
+        }
+    }
+
+    let mut reached = vec![false; chunks.len()];
+    let mut pending: Vec<usize> = chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.name.is_none())
+        .map(|(i, _)| i)
+        .collect();
+    for root in roots.iter() {
+        pending.extend(definitions.get(root.as_str()).into_iter().flatten());
+    }
+
+    while let Some(i) = pending.pop() {
+        if reached[i] {
+            continue;
+        }
+        reached[i] = true;
+
+        for function in chunks[i].calls.iter() {
+            pending.extend(definitions.get(function.as_str()).into_iter().flatten());
+        }
+    }
+
+    let mut removed = vec![];
+    for (i, (_, source)) in vm_sources.iter_mut().enumerate() {
+        let mut lines: Vec<&str> = source.lines().collect();
+        for (chunk, _) in chunks
+            .iter()
+            .zip(reached.iter())
+            .filter(|(chunk, reached)| chunk.source == i && !**reached)
+        {
+            lines[chunk.start..chunk.end].fill("");
+            removed.push(chunk.name.clone().unwrap());
+        }
+        *source = lines.join("\n");
+    }
+
+    Reachability { removed }
+}
+
+/// Check the program made of `vm_sources` as a whole: functions defined
+/// twice, calls to functions no file defines, jumps to labels the function
+/// does not declare, and a missing `entry` function for the bootstrap to call.
+/// Undefined calls are warnings, as a missing OS class may be provided later,
+/// the others errors.
+pub fn check(vm_sources: &[(String, String)], entry: Option<&str>) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    // name -> (file, line) of its first definition
+    let mut definitions: HashMap<&str, (&str, usize)> = HashMap::new();
+    for (vm_filename, source) in vm_sources.iter() {
+        for (i, line) in source.lines().enumerate() {
+            if let ["function", name, ..] = command(line)[..] {
+                if let Some((file, line_no)) = definitions.get(name) {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        message:  format!(
+                            "duplicate function: {}, first defined in {}.vm, line {}",
+                            name, file, line_no
+                        ),
+                        location: Some((vm_filename.to_owned(), i + 1)),
+                    });
+                } else {
+                    definitions.insert(name, (vm_filename, i + 1));
+                }
+            }
+        }
+    }
+
+    for (vm_filename, source) in vm_sources.iter() {
+        // labels are local to the function they are declared in
+        let mut labels: HashSet<(&str, &str)> = HashSet::new();
+        let mut function = "";
+        for line in source.lines() {
+            match command(line)[..] {
+                ["function", name, ..] => function = name,
+                ["label", label, ..] => {
+                    labels.insert((function, label));
+                }
+                _ => {}
+            }
+        }
+
+        let mut function = "";
+        for (i, line) in source.lines().enumerate() {
+            let location = Some((vm_filename.to_owned(), i + 1));
+            match command(line)[..] {
+                ["function", name, ..] => function = name,
+                ["call", name, ..] if !definitions.contains_key(name) => {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Warning,
+                        message: format!("undefined function: {}", name),
+                        location,
+                    })
+                }
+                ["goto" | "if-goto", label, ..] if !labels.contains(&(function, label)) => {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        message: format!(
+                            "undeclared label: {}, function: {}",
+                            label,
+                            if function.is_empty() { "-" } else { function }
+                        ),
+                        location,
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(entry) = entry {
+        if !definitions.contains_key(entry) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                message:  format!("undefined entry function: {}", entry),
+                location: None,
+            });
+        }
+    }
+
+    diagnostics
+}
+
+fn split_functions(source_index: usize, source: &str) -> Vec<Chunk> {
+    let mut chunks = vec![Chunk {
+        source: source_index,
+        name:   None,
+        start:  0,
+        end:    0,
+        calls:  vec![],
+    }];
+
+    for (i, line) in source.lines().enumerate() {
+        match command(line)[..] {
+            ["function", name, ..] => chunks.push(Chunk {
+                source: source_index,
+                name:   Some(name.to_owned()),
+                start:  i,
+                end:    i,
+                calls:  vec![],
+            }),
+            ["call", name, ..] => chunks.last_mut().unwrap().calls.push(name.to_owned()),
+            _ => {}
+        }
+        chunks.last_mut().unwrap().end = i + 1;
+    }
+
+    chunks
+}
+
+// Words of a VM command without its comment
+fn command(line: &str) -> Vec<&str> {
+    line.split("//")
+        .next()
+        .unwrap()
+        .split_whitespace()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(files: &[(&str, &str)]) -> Vec<(String, String)> {
+        files
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+
+    const MAIN: &str = "\
+function Main.main 0
+call Main.used 0
+return
+function Main.used 0
+call Main.used 0
+return
+function Main.unused 0
+call Main.used 0
+return";
+
+    #[test]
+    fn remove_unreachable_functions() {
+        let mut vm_sources = sources(&[("Main", MAIN)]);
+        let reachability = eliminate_dead_functions(&mut vm_sources, &["Main.main".to_owned()]);
+
+        assert_eq!(reachability.removed, ["Main.unused"]);
+        // lines are blanked so that the others keep their numbers
+        let lines: Vec<&str> = vm_sources[0].1.split('\n').collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[3], "function Main.used 0");
+        assert_eq!(lines[6..], ["", "", ""]);
+    }
+
+    #[test]
+    fn keep_the_callees_of_commands_before_functions() {
+        let mut vm_sources = sources(&[
+            ("Main", MAIN),
+            (
+                "Test",
+                "push constant 1\ncall Main.unused 0\nlabel END\ngoto END",
+            ),
+        ]);
+        let reachability = eliminate_dead_functions(&mut vm_sources, &[]);
+
+        assert_eq!(reachability.removed, ["Main.main"]);
+        assert!(vm_sources[0].1.contains("function Main.unused 0"));
+        assert!(vm_sources[0].1.contains("function Main.used 0"));
+    }
+
+    #[test]
+    fn keep_every_definition_of_a_duplicate() {
+        let mut vm_sources = sources(&[
+            ("Main", MAIN),
+            (
+                "Other",
+                "function Main.used 0\ncall Other.f 0\nreturn\nfunction Other.f 0\nreturn",
+            ),
+        ]);
+        let reachability = eliminate_dead_functions(&mut vm_sources, &["Main.main".to_owned()]);
+
+        assert_eq!(reachability.removed, ["Main.unused"]);
+        assert_eq!(vm_sources[1].1.lines().filter(|v| v.is_empty()).count(), 0);
+    }
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn report_duplicate_functions_and_undefined_calls() {
+        let vm_sources = sources(&[
+            ("Main", MAIN),
+            (
+                "Other",
+                "push constant 1\nfunction Main.used 0\ncall Foo.bar 0\nreturn",
+            ),
+        ]);
+
+        assert_eq!(messages(&check(&vm_sources, None)), [
+            "error: duplicate function: Main.used, first defined in Main.vm, line 4, file: \
+             Other.vm, line: 2",
+            "warning: undefined function: Foo.bar, file: Other.vm, line: 3",
+        ]);
+    }
+
+    #[test]
+    fn report_labels_of_other_functions() {
+        let source = "\
+label START
+goto START
+function Main.a 0
+label LOOP
+goto LOOP
+function Main.b 0
+if-goto LOOP
+goto START
+return";
+        let vm_sources = sources(&[("Main", source)]);
+
+        assert_eq!(messages(&check(&vm_sources, None)), [
+            "error: undeclared label: LOOP, function: Main.b, file: Main.vm, line: 7",
+            "error: undeclared label: START, function: Main.b, file: Main.vm, line: 8",
+        ]);
+    }
+
+    #[test]
+    fn report_a_missing_entry() {
+        let vm_sources = sources(&[("Main", MAIN)]);
+
+        assert!(check(&vm_sources, Some("Main.main")).is_empty());
+        let diagnostics = check(&vm_sources, Some("Sys.init"));
+        assert_eq!(messages(&diagnostics), [
+            "error: undefined entry function: Sys.init"
+        ]);
+        assert!(diagnostics[0].is_error());
+    }
+}
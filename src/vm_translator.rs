@@ -1,82 +1,160 @@
-use std::collections::HashMap;
-use std::fs::{read_dir, read_to_string};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::read_to_string;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use once_cell::sync::OnceCell;
 
-use crate::utils::{save_file, substr};
+use crate::jack_compiler::compile_source;
+use crate::os;
+use crate::utils::{expand_path, save_file, substr};
+use crate::vm_linker::{
+    check, defined_functions, eliminate_dead_functions, Diagnostic, Reachability,
+};
 
 pub static ARITH_TABLE: OnceCell<HashMap<&str, &str>> = OnceCell::new();
 pub static SEGMENT_TABLE: OnceCell<HashMap<&str, &str>> = OnceCell::new();
 
+/// Where the classes of the Jack OS called by a program come from
+pub struct OsOptions {
+    /// Link the OS classes that are called but not defined by the program
+    pub link: bool,
+    /// Directory of *.vm or *.jack files taking precedence over the bundled
+    /// OS classes
+    pub dir:  Option<PathBuf>,
+}
+
+/// The code run before the program, which sets up the stack and calls the
+/// entry function
+pub struct Bootstrap {
+    /// Initial value of SP
+    pub sp:    u16,
+    pub entry: String,
+}
+
 pub struct VmTranslator {
-    vm_files:     Vec<PathBuf>,
+    // (file name without extension, VM commands)
+    vm_sources:   Vec<(String, String)>,
     output:       Vec<u8>,
+    source_map:   Vec<u8>,
     symbol_index: u32,
     return_index: u32,
-    multi_files:  bool,
+    rom_address:  u32,
+    bootstrap:    Option<Bootstrap>,
+    annotate:     bool,
+    reachability: Option<Reachability>,
+    diagnostics:  Vec<Diagnostic>,
+    extended:     bool,
+    // file name without extension -> *.vm.map written by the Jack compiler
+    jack_maps:    HashMap<String, String>,
 }
 
 impl VmTranslator {
-    pub fn new(path: PathBuf) -> Self {
-        ARITH_TABLE
-            .set(HashMap::from([
+    /// `vm_sources` are (file name without extension, VM commands) pairs,
+    /// the file name prefixing the static variables. With `annotate`, the
+    /// assembly of every VM command is preceded by a `// File.vm:12  push
+    /// local 2` comment. With `eliminate`, the functions that are never
+    /// called from the entry function, or from the functions of the program
+    /// when there is no bootstrap, are left out. Fails when an OS class
+    /// does not compile.
+    pub fn new(
+        mut vm_sources: Vec<(String, String)>,
+        annotate: bool,
+        os: OsOptions,
+        eliminate: bool,
+        bootstrap: Option<Bootstrap>,
+    ) -> Result<Self, String> {
+        ARITH_TABLE.get_or_init(|| {
+            HashMap::from([
                 ("not", "!"),
                 ("neg", "-"),
-                ("add", "+"),
-                ("sub", "-"),
-                ("and", "&"),
-                ("or", "|"),
+                ("add", "D+M"),
+                ("sub", "M-D"),
+                ("and", "D&M"),
+                ("or", "D|M"),
+                // jump over `*SP = true` when the comparison is false
                 ("eq", "JNE"),
-                ("lt", "JLE"),
-                ("gt", "JGE"),
-            ]))
-            .unwrap();
+                ("lt", "JGE"),
+                ("gt", "JLE"),
+            ])
+        });
 
-        SEGMENT_TABLE
-            .set(HashMap::from([
+        SEGMENT_TABLE.get_or_init(|| {
+            HashMap::from([
                 ("local", "LCL"),
                 ("argument", "ARG"),
                 ("this", "THIS"),
                 ("that", "THAT"),
                 ("temp", "5"),
                 ("pointer", "3"),
-            ]))
-            .unwrap();
+            ])
+        });
 
-        let mut multi_files = false;
-        let mut vm_files = vec![];
-
-        if path.is_dir() {
-            multi_files = true;
-            for file in read_dir(path).unwrap() {
-                let file = file.unwrap();
-                let path = file.path();
-
-                if path.is_file() && path.extension().unwrap() == "vm" {
-                    vm_files.push(path);
-                }
-            }
+        let entry = bootstrap.as_ref().map(|v| v.entry.as_str());
+        // without a bootstrap, any function of the program may be the entry
+        let roots: Vec<String> = if let Some(entry) = entry {
+            vec![entry.to_owned()]
         } else {
-            assert_eq!(path.extension().unwrap(), "vm");
-            vm_files.push(path);
+            vm_sources
+                .iter()
+                .flat_map(|(_, source)| defined_functions(source))
+                .collect()
+        };
+        if os.link {
+            link_os(&mut vm_sources, entry, os.dir.as_deref())?;
         }
+        let diagnostics = check(&vm_sources, entry);
+        let reachability = eliminate.then(|| eliminate_dead_functions(&mut vm_sources, &roots));
 
-        Self {
-            vm_files,
+        Ok(Self {
+            vm_sources,
             output: vec![],
+            source_map: vec![],
             symbol_index: 0,
             return_index: 0,
-            multi_files,
-        }
+            rom_address: 0,
+            bootstrap,
+            annotate,
+            reachability,
+            diagnostics,
+            extended: false,
+            jack_maps: HashMap::new(),
+        })
     }
 
-    pub fn run(&mut self) {
-        if self.multi_files {
+    /// Multiply and divide by constant powers of two with the shift
+    /// instructions of the extended instruction set rather than calling
+    /// `Math.multiply` and `Math.divide`
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    /// Trace the commands of `vm_filename` back to the Jack source with the
+    /// `vmLine class subroutine jackLine` entries of its *.vm.map file, as if
+    /// it had been compiled with `// Main.jack:42` comments
+    pub fn set_jack_map(&mut self, vm_filename: &str, map: &str) {
+        self.jack_maps
+            .insert(vm_filename.to_owned(), map.to_owned());
+    }
+
+    /// The problems found when linking the files, which should be reported
+    /// before calling `run` as errors make the assembly unusable
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The result of the dead function elimination, if it was done
+    pub fn reachability(&self) -> Option<&Reachability> {
+        self.reachability.as_ref()
+    }
+
+    /// Translate the VM commands. Errors read `invalid: ..., file: Main.vm,
+    /// line: N`.
+    pub fn run(&mut self) -> Result<(), String> {
+        if let Some(bootstrap) = &self.bootstrap {
             // add bootstrap codes
-            // SP = 256
-            writeln!(&mut self.output, "@256").unwrap();
+            // SP = bootstrap.sp
+            writeln!(&mut self.output, "@{}", bootstrap.sp).unwrap();
             writeln!(&mut self.output, "D=A").unwrap();
             writeln!(&mut self.output, "@SP").unwrap();
             writeln!(&mut self.output, "M=D").unwrap();
@@ -114,84 +192,265 @@ impl VmTranslator {
             writeln!(&mut self.output, "@LCL").unwrap();
             writeln!(&mut self.output, "M=D").unwrap();
 
-            writeln!(&mut self.output, "@Sys.init").unwrap();
+            writeln!(&mut self.output, "@{}", bootstrap.entry).unwrap();
             writeln!(&mut self.output, "0;JMP").unwrap();
             writeln!(&mut self.output, "(bootstrap)").unwrap();
+
+            self.rom_address = count_instructions(&self.output);
+            writeln!(&mut self.source_map, "0 {} - 0 bootstrap", self.rom_address).unwrap();
         }
 
-        for file in self.vm_files.iter() {
-            let mut vm =
-                SingleVmTranslator::new(file.to_owned(), self.symbol_index, self.return_index);
-            vm.parse();
+        let mut uses_frames = false;
+        for (name, source) in self.vm_sources.iter() {
+            let mut vm = SingleVmTranslator::new(
+                name.to_owned(),
+                source,
+                self.symbol_index,
+                self.return_index,
+                self.rom_address,
+                self.annotate,
+                self.extended,
+            );
+            if let Some(map) = self.jack_maps.get(name) {
+                vm.set_jack_map(map);
+            }
+            vm.parse()?;
 
             self.output.extend(vm.output);
+            self.source_map.extend(vm.source_map);
             self.symbol_index = vm.symbol_index;
             self.return_index = vm.return_index;
+            self.rom_address = vm.rom_address;
+            uses_frames |= vm.uses_frames;
+        }
+
+        if uses_frames {
+            let start = self.output.len();
+            write_call_routine(&mut self.output);
+            let end = self.output.len();
+            write_return_routine(&mut self.output);
+
+            let call_size = count_instructions(&self.output[start..end]);
+            let return_size = count_instructions(&self.output[end..]);
+            writeln!(
+                &mut self.source_map,
+                "{} {} - 0 {}",
+                self.rom_address,
+                self.rom_address + call_size,
+                CALL_ROUTINE
+            )
+            .unwrap();
+            writeln!(
+                &mut self.source_map,
+                "{} {} - 0 {}",
+                self.rom_address + call_size,
+                self.rom_address + call_size + return_size,
+                RETURN_ROUTINE
+            )
+            .unwrap();
+            self.rom_address += call_size + return_size;
         }
+        Ok(())
+    }
+
+    /// The generated assembly
+    pub fn output(&self) -> &[u8] {
+        &self.output
     }
 
     pub fn save_file(&self, dst_path: &PathBuf) {
         save_file(&self.output, dst_path).unwrap();
     }
+
+    /// Save the ROM address ranges the VM commands are assembled into, one
+    /// `start end File.vm line function` entry per command, where `end` is
+    /// exclusive, followed by `Main.jack:42` when the Jack source line is
+    /// known. The bootstrap code is reported as `- 0 bootstrap`.
+    pub fn save_map(&self, dst_path: &PathBuf) {
+        save_file(&self.source_map, dst_path).unwrap();
+    }
 }
 
 pub struct SingleVmTranslator {
     vm_filename:  String,
-    codes:        Vec<String>,
+    // (line number in the source file, code without comments)
+    codes:        Vec<(usize, String)>,
     output:       Vec<u8>,
+    source_map:   Vec<u8>,
     symbol_index: u32,
     return_index: u32,
+    // ROM address of the next generated instruction
+    rom_address:  u32,
     current_func: String,
+    // `Main.jack:42` of the last `// Main.jack:42` comment
+    jack_line:    Option<String>,
+    annotate:     bool,
+    // whether `call` or `return` jumps to the shared routines
+    uses_frames:  bool,
+    extended:     bool,
 }
 
 impl SingleVmTranslator {
-    pub fn new(path: PathBuf, symbol_index: u32, return_index: u32) -> Self {
-        assert_eq!(path.extension().unwrap(), "vm");
-
-        let vm_filename = path.file_stem().unwrap().to_str().unwrap().to_owned();
+    /// `vm_filename` is the name of the *.vm file without extension, which
+    /// prefixes the static variables
+    pub fn new(
+        vm_filename: String,
+        source: &str,
+        symbol_index: u32,
+        return_index: u32,
+        rom_address: u32,
+        annotate: bool,
+        extended: bool,
+    ) -> Self {
         let mut codes = vec![];
 
-        for line in read_to_string(path).unwrap().lines() {
+        for (i, line) in source.lines().enumerate() {
             let line = line.trim();
+            let line_no = i + 1;
+            // keep `// Main.jack:42` comments written by the Jack compiler so
+            // the assembly can be traced back to the Jack source
+            if line.starts_with("//")
+                && line.ends_with(|c: char| c.is_ascii_digit())
+                && line.contains(".jack:")
+            {
+                codes.push((line_no, line.to_string()));
+                continue;
+            }
             if line.is_empty() || line.starts_with('/') {
                 continue;
             }
             if line.find('/').is_some() {
-                codes.push(substr(line, 0, line.find('/').unwrap()).trim().to_string());
+                codes.push((
+                    line_no,
+                    substr(line, 0, line.find('/').unwrap()).trim().to_string(),
+                ));
                 continue;
             }
-            codes.push(line.to_string());
+            codes.push((line_no, line.to_string()));
         }
 
         Self {
             vm_filename,
             codes,
             output: vec![],
+            source_map: vec![],
             symbol_index,
             return_index,
+            rom_address,
             current_func: "".to_owned(),
+            jack_line: None,
+            annotate,
+            uses_frames: false,
+            extended,
         }
     }
 
+    /// Precede the commands with `// Main.jack:42` comments where their Jack
+    /// source line changes, from the `vmLine class subroutine jackLine`
+    /// entries of a *.vm.map file
+    pub fn set_jack_map(&mut self, map: &str) {
+        let jack_lines: HashMap<usize, String> = map
+            .lines()
+            .filter_map(
+                |entry| match entry.split_whitespace().collect::<Vec<_>>()[..] {
+                    [vm_line, class, _, jack_line] => Some((
+                        vm_line.parse().ok()?,
+                        format!("{}.jack:{}", class, jack_line),
+                    )),
+                    _ => None,
+                },
+            )
+            .collect();
+
+        let mut jack_line = None;
+        let mut codes = vec![];
+        for (line_no, code) in std::mem::take(&mut self.codes) {
+            if let Some(location) = jack_lines.get(&line_no) {
+                if jack_line != Some(location) {
+                    codes.push((line_no, format!("// {}", location)));
+                    jack_line = Some(location);
+                }
+            }
+            codes.push((line_no, code));
+        }
+        self.codes = codes;
+    }
+
     // For each line in self.codes, generate its corresponding assembly codes
-    pub fn parse(&mut self) {
+    pub fn parse(&mut self) -> Result<(), String> {
         let codes = self.codes.clone();
+        let mut codes = codes.iter().peekable();
+
+        while let Some((line_no, code)) = codes.next() {
+            if code.starts_with("//") {
+                writeln!(&mut self.output, "{}", code).unwrap();
+                self.jack_line = Some(code.trim_start_matches("// ").to_owned());
+                continue;
+            }
+
+            if self.annotate {
+                writeln!(
+                    &mut self.output,
+                    "// {}.vm:{}  {}",
+                    self.vm_filename, line_no, code
+                )
+                .unwrap();
+            }
+            let start = self.output.len();
 
-        for code in codes.iter() {
             let parts: Vec<&str> = code.split(' ').collect();
+            if !is_valid_command(&parts) {
+                return Err(format!(
+                    "invalid: {}, file: {}.vm, line: {}",
+                    code, self.vm_filename, line_no
+                ));
+            }
+
+            let shifted = self.extended
+                && matches!(codes.peek(), Some((_, next)) if self.parse_shift(&parts, next));
+            if shifted {
+                // the call is part of the shift
+                codes.next();
+            } else {
+                match parts[0] {
+                    "push" => self.parse_c_push(parts),
+                    "pop" => self.parse_c_pop(parts),
+                    "label" => self.parse_c_label(parts),
+                    "goto" => self.parse_c_goto(parts),
+                    "if-goto" => self.parse_c_if_goto(parts),
+                    "function" => self.parse_c_function(parts),
+                    "call" => self.parse_c_call(parts),
+                    "return" => self.parse_c_return(),
+                    _ => self.parse_c_arithmetic(parts[0]),
+                }
+            }
 
-            match parts[0] {
-                "push" => self.parse_c_push(parts),
-                "pop" => self.parse_c_pop(parts),
-                "label" => self.parse_c_label(parts),
-                "goto" => self.parse_c_goto(parts),
-                "if-goto" => self.parse_c_if_goto(parts),
-                "function" => self.parse_c_function(parts),
-                "call" => self.parse_c_call(parts),
-                "return" => self.parse_c_return(),
-                _ => self.parse_c_arithmetic(parts[0]),
+            // labels generate no instruction
+            let size = count_instructions(&self.output[start..]);
+            if size > 0 {
+                write!(
+                    &mut self.source_map,
+                    "{} {} {}.vm {} {}",
+                    self.rom_address,
+                    self.rom_address + size,
+                    self.vm_filename,
+                    line_no,
+                    if self.current_func.is_empty() {
+                        "-"
+                    } else {
+                        &self.current_func
+                    }
+                )
+                .unwrap();
+                match &self.jack_line {
+                    Some(jack_line) => writeln!(&mut self.source_map, " {}", jack_line),
+                    None => writeln!(&mut self.source_map),
+                }
+                .unwrap();
+                self.rom_address += size;
             }
         }
+        Ok(())
     }
 
     fn parse_c_arithmetic(&mut self, command: &str) {
@@ -213,7 +472,7 @@ impl SingleVmTranslator {
                 writeln!(&mut self.output, "A=A-1").unwrap();
                 writeln!(
                     &mut self.output,
-                    "M=M{}D",
+                    "M={}",
                     ARITH_TABLE.get().unwrap().get(command).unwrap()
                 )
                 .unwrap();
@@ -242,6 +501,48 @@ impl SingleVmTranslator {
         }
     }
 
+    // `push constant 2^k` followed by `call Math.multiply 2` or `call
+    // Math.divide 2` is a shift by k, rounding the quotient of a negative
+    // number towards zero like `Math.divide`. Returns false for other commands.
+    fn parse_shift(&mut self, push: &[&str], call: &str) -> bool {
+        let shift = match push {
+            ["push", "constant", constant] => match constant.parse::<u16>() {
+                Ok(constant) if constant.is_power_of_two() => constant.trailing_zeros(),
+                _ => return false,
+            },
+            _ => return false,
+        };
+
+        match call.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["call", "Math.multiply", "2"] => {
+                writeln!(&mut self.output, "@SP").unwrap();
+                writeln!(&mut self.output, "A=M-1").unwrap();
+                for _ in 0..shift {
+                    writeln!(&mut self.output, "M=M<<").unwrap();
+                }
+            }
+            ["call", "Math.divide", "2"] => {
+                writeln!(&mut self.output, "@SP").unwrap();
+                writeln!(&mut self.output, "A=M-1").unwrap();
+                writeln!(&mut self.output, "D=M").unwrap();
+                writeln!(&mut self.output, "@div_{}", self.symbol_index).unwrap();
+                writeln!(&mut self.output, "D;JGE").unwrap();
+                writeln!(&mut self.output, "@{}", (1u32 << shift) - 1).unwrap();
+                writeln!(&mut self.output, "D=D+A").unwrap();
+                writeln!(&mut self.output, "(div_{})", self.symbol_index).unwrap();
+                for _ in 0..shift {
+                    writeln!(&mut self.output, "D=D>>").unwrap();
+                }
+                writeln!(&mut self.output, "@SP").unwrap();
+                writeln!(&mut self.output, "A=M-1").unwrap();
+                writeln!(&mut self.output, "M=D").unwrap();
+                self.symbol_index += 1;
+            }
+            _ => return false,
+        }
+        true
+    }
+
     fn parse_c_push(&mut self, command: Vec<&str>) {
         match command[1] {
             "constant" => {
@@ -257,7 +558,7 @@ impl SingleVmTranslator {
                     SEGMENT_TABLE.get().unwrap().get(command[1]).unwrap()
                 )
                 .unwrap();
-                writeln!(&mut self.output, "A=M+D").unwrap();
+                writeln!(&mut self.output, "A=D+M").unwrap();
                 writeln!(&mut self.output, "D=M").unwrap();
             }
             "temp" | "pointer" => {
@@ -269,7 +570,7 @@ impl SingleVmTranslator {
                     SEGMENT_TABLE.get().unwrap().get(command[1]).unwrap()
                 )
                 .unwrap();
-                writeln!(&mut self.output, "A=A+D").unwrap();
+                writeln!(&mut self.output, "A=D+A").unwrap();
                 writeln!(&mut self.output, "D=M").unwrap();
             }
             "static" => {
@@ -292,7 +593,7 @@ impl SingleVmTranslator {
                     SEGMENT_TABLE.get().unwrap().get(command[1]).unwrap()
                 )
                 .unwrap();
-                writeln!(&mut self.output, "D=M+D").unwrap();
+                writeln!(&mut self.output, "D=D+M").unwrap();
                 writeln!(&mut self.output, "@R15").unwrap();
                 writeln!(&mut self.output, "M=D").unwrap();
             }
@@ -305,14 +606,14 @@ impl SingleVmTranslator {
                     SEGMENT_TABLE.get().unwrap().get(command[1]).unwrap()
                 )
                 .unwrap();
-                writeln!(&mut self.output, "D=A+D").unwrap();
+                writeln!(&mut self.output, "D=D+A").unwrap();
                 writeln!(&mut self.output, "@R15").unwrap();
                 writeln!(&mut self.output, "M=D").unwrap();
             }
             "static" => {
                 writeln!(&mut self.output, "@{}.{}", self.vm_filename, command[2]).unwrap();
                 writeln!(&mut self.output, "D=A").unwrap();
-                writeln!(&mut self.output, "R15").unwrap();
+                writeln!(&mut self.output, "@R15").unwrap();
                 writeln!(&mut self.output, "M=D").unwrap();
             }
             _ => unreachable!("invalid command: pop"),
@@ -358,22 +659,11 @@ impl SingleVmTranslator {
         }
     }
 
+    // The frame is saved by the shared call routine, which is given the
+    // return address in D, `n + 5` in R13 and the address of f in R14
     fn parse_c_call(&mut self, command: Vec<&str>) {
         let label = format!("End${}${}", command[1], self.return_index);
 
-        // push return address
-        writeln!(&mut self.output, "@{}", label).unwrap();
-        writeln!(&mut self.output, "D=A").unwrap();
-        push_d(&mut self.output);
-
-        // push LCL, ARG, THIS, THAT
-        for segment in &["LCL", "ARG", "THIS", "THAT"] {
-            writeln!(&mut self.output, "@{}", segment).unwrap();
-            writeln!(&mut self.output, "D=M").unwrap();
-            push_d(&mut self.output);
-        }
-
-        // ARG = SP - n - 5, n is the count of paramers
         writeln!(
             &mut self.output,
             "@{}",
@@ -381,82 +671,229 @@ impl SingleVmTranslator {
         )
         .unwrap();
         writeln!(&mut self.output, "D=A").unwrap();
-        writeln!(&mut self.output, "@SP").unwrap();
-        writeln!(&mut self.output, "D=M-D").unwrap();
-        writeln!(&mut self.output, "@ARG").unwrap();
+        writeln!(&mut self.output, "@R13").unwrap();
         writeln!(&mut self.output, "M=D").unwrap();
 
-        // LCL = SP
-        writeln!(&mut self.output, "@SP").unwrap();
-        writeln!(&mut self.output, "D=M").unwrap();
-        writeln!(&mut self.output, "@LCL").unwrap();
+        writeln!(&mut self.output, "@{}", command[1]).unwrap();
+        writeln!(&mut self.output, "D=A").unwrap();
+        writeln!(&mut self.output, "@R14").unwrap();
         writeln!(&mut self.output, "M=D").unwrap();
 
-        // goto f
-        writeln!(&mut self.output, "@{}", command[1]).unwrap();
+        writeln!(&mut self.output, "@{}", label).unwrap();
+        writeln!(&mut self.output, "D=A").unwrap();
+        writeln!(&mut self.output, "@{}", CALL_ROUTINE).unwrap();
         writeln!(&mut self.output, "0;JMP").unwrap();
 
         // return label
         writeln!(&mut self.output, "({})", label).unwrap();
 
         self.return_index += 1;
+        self.uses_frames = true;
     }
 
     fn parse_c_return(&mut self) {
-        // FRAME = LCL, put LCL into R15
-        writeln!(&mut self.output, "@LCL").unwrap();
-        writeln!(&mut self.output, "D=M").unwrap();
-        writeln!(&mut self.output, "@R15").unwrap();
-        writeln!(&mut self.output, "M=D").unwrap();
+        writeln!(&mut self.output, "@{}", RETURN_ROUTINE).unwrap();
+        writeln!(&mut self.output, "0;JMP").unwrap();
 
-        // RET = *(FRAME - 5), put retAddr into R14
-        writeln!(&mut self.output, "@5").unwrap();
-        writeln!(&mut self.output, "D=A").unwrap();
-        writeln!(&mut self.output, "@R15").unwrap();
-        writeln!(&mut self.output, "A=M-D").unwrap();
-        writeln!(&mut self.output, "D=M").unwrap();
-        writeln!(&mut self.output, "@R14").unwrap();
-        writeln!(&mut self.output, "M=D").unwrap();
+        self.uses_frames = true;
+    }
+}
 
-        // *ARG = pop()
-        writeln!(&mut self.output, "@SP").unwrap();
-        writeln!(&mut self.output, "AM=M-1").unwrap();
-        writeln!(&mut self.output, "D=M").unwrap();
-        writeln!(&mut self.output, "@ARG").unwrap();
-        writeln!(&mut self.output, "A=M").unwrap();
-        writeln!(&mut self.output, "M=D").unwrap();
+// Whether the parts of a command are a known command with its arguments
+fn is_valid_command(parts: &[&str]) -> bool {
+    let is_index = |index: &str| index.parse::<u16>().is_ok();
+    match parts[..] {
+        ["push", "constant", index] => is_index(index),
+        ["push" | "pop", segment, index] => {
+            (segment == "static" || SEGMENT_TABLE.get().unwrap().contains_key(segment))
+                && is_index(index)
+        }
+        ["label" | "goto" | "if-goto", _] => true,
+        ["function" | "call", _, count] => is_index(count),
+        ["return"] => true,
+        [command] => ARITH_TABLE.get().unwrap().contains_key(command),
+        _ => false,
+    }
+}
 
-        // SP = ARG + 1
-        writeln!(&mut self.output, "@ARG").unwrap();
-        writeln!(&mut self.output, "D=M+1").unwrap();
-        writeln!(&mut self.output, "@SP").unwrap();
-        writeln!(&mut self.output, "M=D").unwrap();
+// Labels of the routines shared by every `call` and `return`, which keep the
+// program small enough for the 32K ROM when the Jack OS is linked
+const CALL_ROUTINE: &str = "$call";
+const RETURN_ROUTINE: &str = "$return";
 
-        // THAT = *(FRAME - 1)
-        writeln!(&mut self.output, "@R15").unwrap();
-        writeln!(&mut self.output, "A=M-1").unwrap();
-        writeln!(&mut self.output, "D=M").unwrap();
-        writeln!(&mut self.output, "@THAT").unwrap();
-        writeln!(&mut self.output, "M=D").unwrap();
+// Push the return address in D, LCL, ARG, THIS and THAT, then set ARG to
+// SP - R13, LCL to SP and jump to R14
+fn write_call_routine(output: &mut Vec<u8>) {
+    writeln!(output, "({})", CALL_ROUTINE).unwrap();
 
-        // THIS = *(FRAME - 2)
-        // ARG = *(FRAME - 3)
-        // LCL = *(FRAME - 4)
-        for (i, seg) in vec!["THIS", "ARG", "LCL"].into_iter().enumerate() {
-            writeln!(&mut self.output, "@{}", i + 2).unwrap();
-            writeln!(&mut self.output, "D=A").unwrap();
-            writeln!(&mut self.output, "@R15").unwrap();
-            writeln!(&mut self.output, "A=M-D").unwrap();
-            writeln!(&mut self.output, "D=M").unwrap();
-            writeln!(&mut self.output, "@{}", seg).unwrap();
-            writeln!(&mut self.output, "M=D").unwrap();
+    // push return address
+    push_d(output);
+
+    // push LCL, ARG, THIS, THAT
+    for segment in &["LCL", "ARG", "THIS", "THAT"] {
+        writeln!(output, "@{}", segment).unwrap();
+        writeln!(output, "D=M").unwrap();
+        push_d(output);
+    }
+
+    // ARG = SP - n - 5, n is the count of paramers
+    writeln!(output, "@R13").unwrap();
+    writeln!(output, "D=M").unwrap();
+    writeln!(output, "@SP").unwrap();
+    writeln!(output, "D=M-D").unwrap();
+    writeln!(output, "@ARG").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // LCL = SP
+    writeln!(output, "@SP").unwrap();
+    writeln!(output, "D=M").unwrap();
+    writeln!(output, "@LCL").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // goto f
+    writeln!(output, "@R14").unwrap();
+    writeln!(output, "A=M").unwrap();
+    writeln!(output, "0;JMP").unwrap();
+}
+
+fn write_return_routine(output: &mut Vec<u8>) {
+    writeln!(output, "({})", RETURN_ROUTINE).unwrap();
+
+    // FRAME = LCL, put LCL into R15
+    writeln!(output, "@LCL").unwrap();
+    writeln!(output, "D=M").unwrap();
+    writeln!(output, "@R15").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // RET = *(FRAME - 5), put retAddr into R14
+    writeln!(output, "@5").unwrap();
+    writeln!(output, "D=A").unwrap();
+    writeln!(output, "@R15").unwrap();
+    writeln!(output, "A=M-D").unwrap();
+    writeln!(output, "D=M").unwrap();
+    writeln!(output, "@R14").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // *ARG = pop()
+    writeln!(output, "@SP").unwrap();
+    writeln!(output, "AM=M-1").unwrap();
+    writeln!(output, "D=M").unwrap();
+    writeln!(output, "@ARG").unwrap();
+    writeln!(output, "A=M").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // SP = ARG + 1
+    writeln!(output, "@ARG").unwrap();
+    writeln!(output, "D=M+1").unwrap();
+    writeln!(output, "@SP").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // THAT = *(FRAME - 1)
+    writeln!(output, "@R15").unwrap();
+    writeln!(output, "A=M-1").unwrap();
+    writeln!(output, "D=M").unwrap();
+    writeln!(output, "@THAT").unwrap();
+    writeln!(output, "M=D").unwrap();
+
+    // THIS = *(FRAME - 2)
+    // ARG = *(FRAME - 3)
+    // LCL = *(FRAME - 4)
+    for (i, seg) in vec!["THIS", "ARG", "LCL"].into_iter().enumerate() {
+        writeln!(output, "@{}", i + 2).unwrap();
+        writeln!(output, "D=A").unwrap();
+        writeln!(output, "@R15").unwrap();
+        writeln!(output, "A=M-D").unwrap();
+        writeln!(output, "D=M").unwrap();
+        writeln!(output, "@{}", seg).unwrap();
+        writeln!(output, "M=D").unwrap();
+    }
+
+    // goto RET
+    writeln!(output, "@R14").unwrap();
+    writeln!(output, "A=M").unwrap();
+    writeln!(output, "0;JMP").unwrap();
+}
+
+/// Read a *.vm file, or every *.vm file of a directory sorted by name, as
+/// (file name without extension, VM commands) pairs
+pub fn read_vm_sources(path: &Path) -> Vec<(String, String)> {
+    expand_path(path.as_os_str(), "vm")
+        .into_iter()
+        .map(|input| (input.name(), input.read()))
+        .collect()
+}
+
+/// Append the OS classes that are called but not defined by the program,
+/// then the ones called by those in turn. The class of `entry`, the function
+/// called by the bootstrap, is also needed. A class defined by the program is
+/// never replaced.
+pub fn link_os(
+    vm_sources: &mut Vec<(String, String)>,
+    entry: Option<&str>,
+    dir: Option<&Path>,
+) -> Result<(), String> {
+    let mut linked: HashSet<String> = HashSet::new();
+    let mut needed: Vec<String> = vec![];
+
+    for (_, source) in vm_sources.iter() {
+        linked.extend(classes_of(source, "function"));
+        needed.extend(classes_of(source, "call"));
+    }
+    if let Some(entry) = entry {
+        needed.push(entry.split('.').next().unwrap().to_owned());
+    }
+
+    while let Some(class) = needed.pop() {
+        if !linked.insert(class.clone()) {
+            continue;
         }
 
-        // goto RET
-        writeln!(&mut self.output, "@R14").unwrap();
-        writeln!(&mut self.output, "A=M").unwrap();
-        writeln!(&mut self.output, "0;JMP").unwrap();
+        let source = match os_class(&class, dir)? {
+            Some(source) => source,
+            None => continue,
+        };
+        needed.extend(classes_of(&source, "call"));
+        vm_sources.push((class, source));
     }
+    Ok(())
+}
+
+// The VM commands of an OS class, read from `Class.vm` or compiled from
+// `Class.jack` in `dir` when present, or else compiled from the bundled Jack OS
+fn os_class(class: &str, dir: Option<&Path>) -> Result<Option<String>, String> {
+    if let Some(dir) = dir {
+        let path = dir.join(format!("{}.vm", class));
+        if path.is_file() {
+            return Ok(Some(read_to_string(path).unwrap()));
+        }
+        let path = dir.join(format!("{}.jack", class));
+        if path.is_file() {
+            let (_, output) = compile_source(&read_to_string(path).unwrap())?;
+            return Ok(Some(String::from_utf8(output).unwrap()));
+        }
+    }
+
+    let source = match os::source(class) {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+    let (_, output) = compile_source(source)?;
+    Ok(Some(String::from_utf8(output).unwrap()))
+}
+
+// Classes of the functions named by every `command Class.func n` line
+fn classes_of(source: &str, command: &str) -> BTreeSet<String> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            if parts.next() != Some(command) {
+                return None;
+            }
+            let name = parts.next()?;
+            Some(name.split('.').next().unwrap().to_owned())
+        })
+        .collect()
 }
 
 fn push_d(output: &mut Vec<u8>) {
@@ -466,3 +903,100 @@ fn push_d(output: &mut Vec<u8>) {
     writeln!(output, "@SP").unwrap();
     writeln!(output, "M=M+1").unwrap();
 }
+
+// Count the instructions in a piece of assembly, which excludes labels and
+// comments
+fn count_instructions(asm: &[u8]) -> u32 {
+    String::from_utf8_lossy(asm)
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('(') && !line.starts_with("//"))
+        .count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::emulator::Emulator;
+
+    fn translate(vm: &str, extended: bool) -> String {
+        let os = OsOptions {
+            link: false,
+            dir:  None,
+        };
+        let vm_sources = vec![("Test".to_owned(), vm.to_owned())];
+        let mut vm_translator = VmTranslator::new(vm_sources, false, os, false, None).unwrap();
+        vm_translator.set_extended(extended);
+        vm_translator.run().unwrap();
+        String::from_utf8(vm_translator.output().to_vec()).unwrap()
+    }
+
+    // RAM[5..] after running the commands, which end in a loop
+    fn temps(vm: &str) -> Vec<i16> {
+        let mut assembler = Assembler::from_source(&translate(vm, true));
+        assembler.set_extended(true);
+        assembler.run().unwrap();
+
+        let mut emulator = Emulator::new(assembler.binary());
+        emulator.set_extended(true);
+        emulator.ram[0] = 256;
+        emulator.run(10_000);
+        assert!(emulator.is_halted());
+        emulator.ram[5..13].iter().map(|v| *v as i16).collect()
+    }
+
+    // `x op y` into temp i, with negative constants
+    fn operations(operations: &[(i16, &str, u16)]) -> String {
+        let mut vm = String::new();
+        for (i, (x, function, y)) in operations.iter().enumerate() {
+            vm.push_str(&format!("push constant {}\n", x.unsigned_abs()));
+            if *x < 0 {
+                vm.push_str("neg\n");
+            }
+            vm.push_str(&format!(
+                "push constant {}\ncall Math.{} 2\npop temp {}\n",
+                y, function, i
+            ));
+        }
+        vm.push_str("label END\ngoto END\n");
+        vm
+    }
+
+    #[test]
+    fn shift_by_powers_of_two() {
+        let vm = operations(&[
+            (3, "multiply", 8),
+            (-3, "multiply", 2),
+            (100, "multiply", 1),
+            (-7, "multiply", 4096),
+        ]);
+        assert!(!translate(&vm, true).contains("Math.multiply"));
+        assert_eq!(temps(&vm)[..4], [24, -6, 100, -28672]);
+    }
+
+    #[test]
+    fn divide_towards_zero() {
+        let vm = operations(&[
+            (-7, "divide", 4),
+            (7, "divide", 4),
+            (-8, "divide", 4),
+            (-1, "divide", 2),
+            (-32767, "divide", 16384),
+            (12345, "divide", 1),
+        ]);
+        assert!(!translate(&vm, true).contains("Math.divide"));
+        assert_eq!(temps(&vm)[..6], [-1, 1, -2, 0, -1, 12345]);
+    }
+
+    #[test]
+    fn call_for_other_operands() {
+        let vm = operations(&[(7, "divide", 6), (7, "multiply", 0)]);
+        assert_eq!(translate(&vm, true).matches("@Math.divide").count(), 1);
+        assert_eq!(translate(&vm, true).matches("@Math.multiply").count(), 1);
+        // a shift needs the constant right before the call
+        let vm = "push constant 4\npush constant 1\nadd\ncall Math.multiply 2\n";
+        assert!(translate(vm, true).contains("@Math.multiply"));
+        assert!(translate(&operations(&[(3, "multiply", 8)]), false).contains("@Math.multiply"));
+    }
+}
@@ -1,9 +1,11 @@
+use std::collections::HashSet;
 use std::fmt;
 use std::io::Write;
 use std::path::PathBuf;
 
 use crate::ast::*;
-use crate::symbol_table::{SymbolTable, Var, VarKind};
+use crate::optimizer::{const_term, expression_value};
+use crate::symbol_table::{ConstTable, SymbolTable, Var, VarKind};
 use crate::utils::save_file;
 
 pub struct VmWriter {
@@ -13,20 +15,37 @@ pub struct VmWriter {
 }
 
 impl VmWriter {
-    pub fn new(ast: Class) -> Self {
+    pub fn new(ast: Class, constants: ConstTable, debug_info: Option<DebugInfo>) -> Self {
         Self {
-            context: VmContext::new(ast.name.clone()),
+            context: VmContext::new(ast.name.clone(), constants),
+            vm_writer: VmCommandWriter::new(ast.name.clone(), debug_info),
             ast,
-            vm_writer: VmCommandWriter::new(),
         }
     }
 
-    pub fn run(&mut self) {
-        self.ast.write_vm(&mut self.context, &mut self.vm_writer);
+    /// Generate the VM commands of the class. Errors read
+    /// `invalid: ..., file: Main.jack, line: N`.
+    pub fn run(&mut self) -> Result<(), String> {
+        self.ast.write_vm(&mut self.context, &mut self.vm_writer)
+    }
+
+    pub fn class_name(&self) -> &str {
+        &self.ast.name
+    }
+
+    /// The generated VM commands
+    pub fn output(self) -> Vec<u8> {
+        self.vm_writer.output()
     }
 
     pub fn save_file(self, dst_path: PathBuf) {
-        assert_eq!(dst_path.extension().unwrap(), "vm");
+        if self.vm_writer.debug_info == Some(DebugInfo::Map) {
+            save_file(
+                &self.vm_writer.source_map,
+                &dst_path.with_extension("vm.map"),
+            )
+            .unwrap();
+        }
         save_file(&self.vm_writer.output(), &dst_path).unwrap();
     }
 }
@@ -38,10 +57,13 @@ pub struct VmContext {
     pub method_name:  Option<String>,
     pub method_kind:  Option<SubroutineKind>,
     pub lable_count:  u32,
+    pub constants:    ConstTable,
+    // methods of the class, which take the current object as first argument
+    pub methods:      HashSet<String>,
 }
 
 impl VmContext {
-    pub fn new(class_name: String) -> Self {
+    pub fn new(class_name: String, constants: ConstTable) -> Self {
         Self {
             class_name,
             class_scope: SymbolTable::new(),
@@ -49,6 +71,8 @@ impl VmContext {
             method_name: None,
             method_kind: None,
             lable_count: 0,
+            constants,
+            methods: HashSet::new(),
         }
     }
 
@@ -87,10 +111,6 @@ impl VmContext {
             .to_owned()
     }
 
-    pub fn find(&self, name: &str) -> bool {
-        self.method_scope.get(name).is_some() || self.class_scope.get(name).is_some()
-    }
-
     pub fn get(&self, name: &str) -> Option<&Var> {
         let var = self.method_scope.get(name);
         if var.is_some() {
@@ -100,29 +120,43 @@ impl VmContext {
         }
     }
 
-    pub fn object_fields_count(&self) -> u32 {
-        self.class_scope.var_count(VarKind::Field)
+    /// The variable `name`, which must be defined
+    pub fn var(&self, name: &str, vm_output: &VmCommandWriter) -> Result<Var, String> {
+        self.get(name)
+            .cloned()
+            .ok_or_else(|| vm_output.invalid(&format!("undefined: {}", name)))
     }
 
-    pub fn local_var_count(&self) -> u32 {
-        self.method_scope.var_count(VarKind::Local)
+    /// Look up `NAME` of the current class when `class_name` is `None`, or
+    /// `Class.NAME` otherwise
+    pub fn get_const(&self, class_name: Option<&str>, name: &str) -> Option<i16> {
+        self.constants
+            .get(class_name.unwrap_or(&self.class_name), name)
     }
 
-    pub fn param_count(&self) -> u32 {
-        self.method_scope.var_count(VarKind::Argument)
+    pub fn object_fields_count(&self) -> u32 {
+        self.class_scope.var_count(VarKind::Field)
     }
 
-    pub fn local_variables(&self) -> Vec<&Var> {
-        self.method_scope.table.values().collect()
+    pub fn local_var_count(&self) -> u32 {
+        self.method_scope.var_count(VarKind::Local)
     }
 }
 
 pub trait VmWrite {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String>;
 }
 
 impl VmWrite for Class {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         for var in self.vars.iter() {
             var.names.iter().for_each(|id| {
                 context.define_class_var(id.to_owned(), var.type_.clone(), var.kind.clone().into());
@@ -130,14 +164,27 @@ impl VmWrite for Class {
         }
 
         for subroutine in self.subroutines.iter() {
-            subroutine.write_vm(context, vm_output);
+            if subroutine.kind == SubroutineKind::Method {
+                context.methods.insert(subroutine.name.clone());
+            }
+        }
+
+        for subroutine in self.subroutines.iter() {
+            subroutine.write_vm(context, vm_output)?;
         }
+        Ok(())
     }
 }
 
 impl VmWrite for SubroutineDec {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         context.start_subroutine(self.name.clone(), self.kind.clone());
+        vm_output.set_subroutine(&self.name);
+        vm_output.set_line(self.line);
 
         // Pass a reference to the manipulated object as a hidden argument of the called
         // method Compile b.mult(5) as if it were written as mult(b, 5)
@@ -157,23 +204,23 @@ impl VmWrite for SubroutineDec {
             );
         }
 
-        self.body.write_vm(context, vm_output);
+        self.body.write_vm(context, vm_output)
     }
 }
 
 impl VmWrite for SubroutineBody {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         for var in self.local_vars.iter() {
             var.names.iter().cloned().for_each(|id| {
                 context.define_method_var(id, var.type_.clone(), VarKind::Local);
             });
         }
 
-        vm_output.write_function(&context.current_function(), context.param_count());
-
-        for _ in 0..context.local_var_count() {
-            vm_output.write_push(Segment::Constant, 0);
-        }
+        vm_output.write_function(&context.current_function(), context.local_var_count());
 
         let kind = context.function_kind();
         // this = manipulated object
@@ -188,26 +235,21 @@ impl VmWrite for SubroutineBody {
             vm_output.write_pop(Segment::Pointer, 0);
         }
 
-        for var in context.local_variables() {
-            if var.kind == VarKind::Local && var.type_ == VarType::Class("String".to_string()) {
-                vm_output.write_push(Segment::Constant, var.name.len() as u32);
-                vm_output.write_call("String.new", 1);
-                for c in var.name.chars() {
-                    vm_output.write_push(Segment::Constant, c as u32);
-                    vm_output.write_call("String.appendChar", 2);
-                }
-                vm_output.write_pop(Segment::Local, var.index);
-            }
-        }
-
         for statement in self.body.iter() {
-            statement.write_vm(context, vm_output);
+            statement.write_vm(context, vm_output)?;
         }
+        Ok(())
     }
 }
 
 impl VmWrite for Statement {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        vm_output.set_line(self.line());
+
         match self {
             Statement::Let(v) => v.write_vm(context, vm_output),
             Statement::If(v) => v.write_vm(context, vm_output),
@@ -219,17 +261,21 @@ impl VmWrite for Statement {
 }
 
 impl VmWrite for LetStatement {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        self.right_expr.write_vm(context, vm_output);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        self.right_expr.write_vm(context, vm_output)?;
 
         if let Some(ref index) = self.array_index {
-            index.write_vm(context, vm_output);
+            index.write_vm(context, vm_output)?;
 
             // Get var's base address
-            let var = context.get(&self.var_name).unwrap();
+            let var = context.var(&self.var_name, vm_output)?;
             vm_output.write_push(var.kind.to_owned().into(), var.index);
 
-            Op::Add.write_vm(context, vm_output);
+            Op::Add.write_vm(context, vm_output)?;
 
             // Set that's base to (var + index)
             vm_output.write_pop(Segment::Pointer, 1);
@@ -238,83 +284,113 @@ impl VmWrite for LetStatement {
             vm_output.write_pop(Segment::That, 0);
         } else {
             // var = right expr
-            let var = context.get(&self.var_name).unwrap();
+            let var = context.var(&self.var_name, vm_output)?;
             vm_output.write_pop(var.kind.to_owned().into(), var.index);
         }
+        Ok(())
     }
 }
 
 impl VmWrite for IfStatement {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         let then_label = format!("if_{}", context.inc_label());
         let end_label = format!("fi_{}", context.inc_label());
 
-        self.cond.write_vm(context, vm_output);
+        self.cond.write_vm(context, vm_output)?;
         vm_output.write_if_goto(&then_label);
 
         if let Some(ref else_body) = self.else_body {
             for statement in else_body.iter() {
-                statement.write_vm(context, vm_output);
+                statement.write_vm(context, vm_output)?;
             }
         }
+        vm_output.set_line(self.line);
         vm_output.write_goto(&end_label);
 
         vm_output.write_label(&then_label);
         for statement in self.if_body.iter() {
-            statement.write_vm(context, vm_output);
+            statement.write_vm(context, vm_output)?;
         }
 
+        vm_output.set_line(self.line);
         vm_output.write_label(&end_label);
+        Ok(())
     }
 }
 
 impl VmWrite for DoStatement {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        self.subroutine_call.write_vm(context, vm_output);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        self.subroutine_call.write_vm(context, vm_output)?;
         vm_output.write_pop(Segment::Temp, 0);
+        Ok(())
     }
 }
 
 impl VmWrite for ReturnStatement {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        for var in context.local_variables() {
-            if var.kind == VarKind::Local && var.type_ == VarType::Class("String".to_string()) {
-                vm_output.write_push(Segment::Local, var.index);
-                vm_output.write_call("String.dispose", 1);
-            }
-        }
-
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         if let Some(ref expr) = self.expr {
-            expr.write_vm(context, vm_output);
+            expr.write_vm(context, vm_output)?;
         } else {
             vm_output.write_push(Segment::Constant, 0);
         }
 
         vm_output.write_return();
+        Ok(())
     }
 }
 
 impl VmWrite for WhileStatement {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         let loop_start_label = format!("loop_start_{}", context.inc_label());
         let loop_end_label = format!("loop_end_{}", context.inc_label());
 
         vm_output.write_label(&loop_start_label);
 
-        self.cond.write_vm(context, vm_output);
-        vm_output.write_if_goto(&loop_end_label);
+        // `while (true)` needs no test, which also turns `while (true) {}` into
+        // a jump to itself that halts the emulator
+        let lookup = |class_name: Option<&str>, name: &str| match class_name {
+            None if context.get(name).is_some() => None,
+            _ => context.get_const(class_name, name),
+        };
+        if expression_value(&self.cond, &lookup) != Some(-1) {
+            self.cond.write_vm(context, vm_output)?;
+            UnaryOp::Not.write_vm(context, vm_output)?;
+            vm_output.write_if_goto(&loop_end_label);
+        }
 
         for statement in self.body.iter() {
-            statement.write_vm(context, vm_output);
+            statement.write_vm(context, vm_output)?;
         }
+        vm_output.set_line(self.line);
         vm_output.write_goto(&loop_start_label);
 
         vm_output.write_label(&loop_end_label);
+        Ok(())
     }
 }
 
 impl VmWrite for SubroutineCall {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         match self {
             Self::Internal(v) => v.write_vm(context, vm_output),
             Self::External(v) => v.write_vm(context, vm_output),
@@ -323,65 +399,99 @@ impl VmWrite for SubroutineCall {
 }
 
 impl VmWrite for InternalCall {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        // The first argument
-        vm_output.write_push(Segment::Pointer, 0);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        let mut args_count = self.args.0.len() as u32;
+
+        // The first argument of a method is the current object
+        if context.methods.contains(&self.name) {
+            vm_output.write_push(Segment::Pointer, 0);
+            args_count += 1;
+        }
 
         // Others arguments
-        self.args.write_vm(context, vm_output);
+        self.args.write_vm(context, vm_output)?;
 
         let func_name = format!("{}.{}", context.class_name, self.name);
-        vm_output.write_call(&func_name, self.args.0.len() as u32);
+        vm_output.write_call(&func_name, args_count);
+        Ok(())
     }
 }
 
 impl VmWrite for ExternalCall {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         match context.get(&self.name).cloned() {
             Some(var) => {
                 match var.type_ {
                     VarType::Class(ref class) => {
                         // Arguments
                         vm_output.write_push(var.kind.clone().into(), var.index);
-                        self.args.write_vm(context, vm_output);
+                        self.args.write_vm(context, vm_output)?;
 
-                        let func_name = format!("{}.{}", class, self.name);
-                        vm_output.write_call(&func_name, self.args.0.len() as u32);
+                        let func_name = format!("{}.{}", class, self.subroutine_name);
+                        vm_output.write_call(&func_name, self.args.0.len() as u32 + 1);
+                    }
+                    _ => {
+                        return Err(vm_output.invalid(&format!(
+                            "{} of type {:?} has no methods",
+                            var.name, var.type_
+                        )))
                     }
-                    _ => panic!("invald var: {}, type: {:?}", var.name, var.type_),
                 }
             }
             None => {
-                self.args.write_vm(context, vm_output);
+                self.args.write_vm(context, vm_output)?;
 
                 let func_name = format!("{}.{}", self.name, self.subroutine_name);
                 vm_output.write_call(&func_name, self.args.0.len() as u32);
             }
         }
+        Ok(())
     }
 }
 
 impl VmWrite for Args {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         for arg in self.0.iter() {
-            arg.write_vm(context, vm_output);
+            arg.write_vm(context, vm_output)?;
         }
+        Ok(())
     }
 }
 
 impl VmWrite for Expression {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        self.term.write_vm(context, vm_output);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        self.term.write_vm(context, vm_output)?;
 
         for op_term in self.op_terms.iter() {
-            op_term.term.write_vm(context, vm_output);
-            op_term.op.write_vm(context, vm_output);
+            op_term.term.write_vm(context, vm_output)?;
+            op_term.op.write_vm(context, vm_output)?;
         }
+        Ok(())
     }
 }
 
 impl VmWrite for Term {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         match self {
             Term::IntegerConst(v) => vm_output.write_push(Segment::Constant, *v),
             Term::KeywordConst(v) => match v {
@@ -390,60 +500,85 @@ impl VmWrite for Term {
                 }
                 KeywordConstant::True => {
                     vm_output.write_push(Segment::Constant, 0);
-                    UnaryOp::Not.write_vm(context, vm_output);
+                    UnaryOp::Not.write_vm(context, vm_output)?;
                 }
                 KeywordConstant::This => vm_output.write_push(Segment::Pointer, 0),
             },
             Term::StringConst(v) => {
-                if !context.find(v) {
-                    context.define_method_var(
-                        v.to_string(),
-                        VarType::Class("String".to_string()),
-                        VarKind::Local,
-                    );
+                vm_output.write_push(Segment::Constant, v.chars().count() as u32);
+                vm_output.write_call("String.new", 1);
+                for c in v.chars() {
+                    vm_output.write_push(Segment::Constant, c as u32);
+                    vm_output.write_call("String.appendChar", 2);
                 }
-                let var = context.get(v).unwrap();
-                vm_output.write_push(Segment::Local, var.index.to_owned());
             }
-            Term::VarName(v) => {
-                let var = context.get(v).unwrap();
-                vm_output.write_push(var.kind.clone().into(), var.index);
+            Term::VarName(v) => match context.get(v) {
+                Some(var) => vm_output.write_push(var.kind.clone().into(), var.index),
+                None => {
+                    let value = context
+                        .get_const(None, v)
+                        .ok_or_else(|| vm_output.invalid(&format!("undefined: {}", v)))?;
+                    const_term(value).write_vm(context, vm_output)?;
+                }
+            },
+            Term::ConstRef(v) => {
+                let value = context
+                    .get_const(Some(&v.class_name), &v.name)
+                    .ok_or_else(|| {
+                        vm_output
+                            .invalid(&format!("undefined constant: {}.{}", v.class_name, v.name))
+                    })?;
+                const_term(value).write_vm(context, vm_output)?;
             }
-            Term::Expression(v) => v.write_vm(context, vm_output),
-            Term::Array(v) => v.write_vm(context, vm_output),
-            Term::UnaryExpression(v) => v.write_vm(context, vm_output),
-            Term::SubRoutineCall(v) => v.write_vm(context, vm_output),
+            Term::Expression(v) => v.write_vm(context, vm_output)?,
+            Term::Array(v) => v.write_vm(context, vm_output)?,
+            Term::UnaryExpression(v) => v.write_vm(context, vm_output)?,
+            Term::SubRoutineCall(v) => v.write_vm(context, vm_output)?,
         }
+        Ok(())
     }
 }
 
 impl VmWrite for UnaryExpression {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        self.term.write_vm(context, vm_output);
-        self.unary_op.write_vm(context, vm_output);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        self.term.write_vm(context, vm_output)?;
+        self.unary_op.write_vm(context, vm_output)
     }
 }
 
 impl VmWrite for Array {
-    fn write_vm(&self, context: &mut VmContext, vm_output: &mut VmCommandWriter) {
-        self.index.write_vm(context, vm_output);
+    fn write_vm(
+        &self,
+        context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
+        self.index.write_vm(context, vm_output)?;
 
         // Get var's base address
-        let var = context.get(&self.name).unwrap();
+        let var = context.var(&self.name, vm_output)?;
         vm_output.write_push(var.kind.to_owned().into(), var.index);
 
-        Op::Add.write_vm(context, vm_output);
+        Op::Add.write_vm(context, vm_output)?;
 
         // Set that's base to (var + index)
         vm_output.write_pop(Segment::Pointer, 1);
 
         // *(var + index) = right expr
         vm_output.write_push(Segment::That, 0);
+        Ok(())
     }
 }
 
 impl VmWrite for Op {
-    fn write_vm(&self, _context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        _context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         match self {
             Op::Add => vm_output.write_arithmetic("add"),
             Op::Minus => vm_output.write_arithmetic("sub"),
@@ -455,61 +590,142 @@ impl VmWrite for Op {
             Op::Less => vm_output.write_arithmetic("lt"),
             Op::Euqal => vm_output.write_arithmetic("eq"),
         }
+        Ok(())
     }
 }
 
 impl VmWrite for UnaryOp {
-    fn write_vm(&self, _context: &mut VmContext, vm_output: &mut VmCommandWriter) {
+    fn write_vm(
+        &self,
+        _context: &mut VmContext,
+        vm_output: &mut VmCommandWriter,
+    ) -> Result<(), String> {
         match self {
             UnaryOp::Neg => vm_output.write_arithmetic("neg"),
             UnaryOp::Not => vm_output.write_arithmetic("not"),
         }
+        Ok(())
     }
 }
 
+/// How the origin of each VM command in the Jack source is recorded
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DebugInfo {
+    /// A `// Main.jack:42` comment before the commands of each source line
+    Comments,
+    /// A sidecar *.vm.map file with one `vmLine class subroutine jackLine`
+    /// entry per VM command
+    Map,
+}
+
 pub struct VmCommandWriter {
-    output: Vec<u8>,
+    output:         Vec<u8>,
+    debug_info:     Option<DebugInfo>,
+    source_map:     Vec<u8>,
+    class_name:     String,
+    subroutine:     String,
+    // line in the Jack file of the statement being compiled
+    line:           usize,
+    // line of the last `// Main.jack:42` comment
+    commented_line: usize,
+    // number of lines written to the *.vm file so far
+    vm_lines:       usize,
 }
 
 impl VmCommandWriter {
-    pub fn new() -> Self {
-        Self { output: vec![] }
+    pub fn new(class_name: String, debug_info: Option<DebugInfo>) -> Self {
+        Self {
+            output: vec![],
+            debug_info,
+            source_map: vec![],
+            class_name,
+            subroutine: String::new(),
+            line: 0,
+            commented_line: 0,
+            vm_lines: 0,
+        }
+    }
+
+    pub fn set_subroutine(&mut self, name: &str) {
+        self.subroutine = name.to_owned();
+    }
+
+    pub fn set_line(&mut self, line: usize) {
+        self.line = line;
+    }
+
+    // An error at the statement being compiled
+    fn invalid(&self, message: &str) -> String {
+        format!(
+            "invalid: {}, file: {}.jack, line: {}",
+            message, self.class_name, self.line
+        )
+    }
+
+    fn write_command(&mut self, command: String) {
+        match self.debug_info {
+            Some(DebugInfo::Comments) if self.commented_line != self.line => {
+                writeln!(
+                    &mut self.output,
+                    "// {}.jack:{}",
+                    self.class_name, self.line
+                )
+                .unwrap();
+                self.commented_line = self.line;
+                self.vm_lines += 1;
+            }
+            Some(DebugInfo::Map) => {
+                writeln!(
+                    &mut self.source_map,
+                    "{} {} {} {}",
+                    self.vm_lines + 1,
+                    self.class_name,
+                    self.subroutine,
+                    self.line
+                )
+                .unwrap();
+            }
+            _ => {}
+        }
+
+        writeln!(&mut self.output, "{}", command).unwrap();
+        self.vm_lines += 1;
     }
 
     pub fn write_push(&mut self, segment: Segment, index: u32) {
-        writeln!(&mut self.output, "push {} {}", segment, index).unwrap();
+        self.write_command(format!("push {} {}", segment, index));
     }
 
     pub fn write_pop(&mut self, segment: Segment, index: u32) {
-        writeln!(&mut self.output, "push {} {}", segment, index).unwrap();
+        self.write_command(format!("pop {} {}", segment, index));
     }
 
     pub fn write_arithmetic(&mut self, command: &str) {
-        writeln!(&mut self.output, "{}", command).unwrap();
+        self.write_command(command.to_owned());
     }
 
     pub fn write_label(&mut self, label: &str) {
-        writeln!(&mut self.output, "label {}", label).unwrap();
+        self.write_command(format!("label {}", label));
     }
 
     pub fn write_goto(&mut self, label: &str) {
-        writeln!(&mut self.output, "goto {}", label).unwrap();
+        self.write_command(format!("goto {}", label));
     }
 
     pub fn write_if_goto(&mut self, label: &str) {
-        writeln!(&mut self.output, "if-goto {}", label).unwrap();
+        self.write_command(format!("if-goto {}", label));
     }
 
     pub fn write_call(&mut self, name: &str, args_count: u32) {
-        writeln!(&mut self.output, "call {} {}", name, args_count).unwrap();
+        self.write_command(format!("call {} {}", name, args_count));
     }
 
     pub fn write_function(&mut self, name: &str, args_count: u32) {
-        writeln!(&mut self.output, "function {} {}", name, args_count).unwrap();
+        self.write_command(format!("function {} {}", name, args_count));
     }
 
     pub fn write_return(&mut self) {
-        writeln!(&mut self.output, "return").unwrap();
+        self.write_command("return".to_owned());
     }
 
     pub fn output(self) -> Vec<u8> {
@@ -517,6 +733,7 @@ impl VmCommandWriter {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Segment {
     Argument,
     Local,
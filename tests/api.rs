@@ -0,0 +1,110 @@
+use nand2tetris_rs::ast::{SubroutineType, VarType};
+use nand2tetris_rs::{assemble, compile, parse, tokenize, translate, Error, TokenType};
+
+const MAIN: &str = "
+class Main {
+    function void main() {
+        do Output.printInt(Main.add(2, 3));
+        return;
+    }
+
+    function int add(int a, int b) {
+        return a + b;
+    }
+}
+";
+
+#[test]
+fn assemble_repeatedly() {
+    for _ in 0..3 {
+        let hack = assemble("@2\nD=A\n@3\nD=D+A\n@0\nM=D\n").unwrap();
+        assert_eq!(
+            String::from_utf8(hack).unwrap(),
+            "0000000000000010\n1110110000010000\n0000000000000011\n\
+             1110000010010000\n0000000000000000\n1110001100001000\n"
+        );
+        let Err(Error::Invalid(message)) = assemble("(LOOP)\n(LOOP)\n") else {
+            panic!("a duplicate label should be invalid");
+        };
+        assert!(message.starts_with("invalid: duplicate label: LOOP"));
+        assert!(matches!(
+            assemble(".macro M a\n@a\n"),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
+
+#[test]
+fn translate_repeatedly() {
+    let vm =
+        "function Sys.init 0\npush constant 7\ncall Math.abs 1\npop temp 0\nlabel END\ngoto END\n";
+    for _ in 0..3 {
+        let asm = String::from_utf8(translate(&[("Sys", vm)], None).unwrap()).unwrap();
+        assert!(asm.contains("(Sys.init)"));
+        assert!(asm.contains("(Math.abs)"));
+
+        let Err(Error::Link(diagnostics)) = translate(
+            &[("Sys", vm), ("Foo", "function Foo.bar 0\ngoto END\n")],
+            None,
+        ) else {
+            panic!("a jump to an undeclared label should not link");
+        };
+        assert!(diagnostics.iter().any(|v| v.is_error()));
+
+        let Err(Error::Invalid(message)) = translate(&[("Foo", "push nowhere 1\n")], None) else {
+            panic!("an unknown segment should be invalid");
+        };
+        assert_eq!(message, "invalid: push nowhere 1, file: Foo.vm, line: 1");
+    }
+}
+
+#[test]
+fn tokenize_repeatedly() {
+    for _ in 0..3 {
+        let tokens = tokenize("let x = \"a b\"; // comment\n").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|v| v.value.as_str()).collect();
+        assert_eq!(values, ["let", "x", "=", "a b", ";"]);
+        assert_eq!(tokens[3].category, TokenType::StringConstant);
+        let Err(Error::Invalid(message)) = tokenize("let s = \"a b;\n") else {
+            panic!("an unterminated string should be invalid");
+        };
+        assert!(message.ends_with("line: 1"));
+    }
+}
+
+#[test]
+fn parse_repeatedly() {
+    for _ in 0..3 {
+        let class = parse(MAIN).unwrap();
+        assert_eq!(class.name, "Main");
+        assert_eq!(class.subroutines.len(), 2);
+        assert_eq!(class.subroutines[0].type_, SubroutineType::Void);
+        assert_eq!(
+            class.subroutines[1].type_,
+            SubroutineType::Type(VarType::Int)
+        );
+        assert!(matches!(
+            parse("class Main { function }"),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
+
+#[test]
+fn compile_repeatedly() {
+    for _ in 0..3 {
+        let classes = compile(&[MAIN], true).unwrap();
+        assert_eq!(classes.len(), 1);
+        assert_eq!(classes[0].0, "Main");
+        let vm = String::from_utf8(classes[0].1.clone()).unwrap();
+        assert!(vm.starts_with("function Main.main 0\n"));
+        assert!(vm.contains("call Main.add 2\n"));
+        let Err(Error::Invalid(message)) = compile(
+            &["class Main { function void main() { return x; } }"],
+            false,
+        ) else {
+            panic!("an undefined variable should be invalid");
+        };
+        assert_eq!(message, "invalid: undefined: x, file: Main.jack, line: 1");
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+
+// An empty directory of its own for each test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nand2tetris-rs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nand2tetris-rs"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+const SYS: &str = "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n";
+const MAIN: &str = "function Main.main 0\npush constant 1\nreturn\n";
+
+#[test]
+fn bootstrap_a_directory_or_several_files() {
+    let dir = temp_dir("bootstrap");
+    fs::create_dir(dir.join("Prog")).unwrap();
+    fs::write(dir.join("Prog/Sys.vm"), SYS).unwrap();
+    fs::write(dir.join("Prog/Main.vm"), MAIN).unwrap();
+    let vm = |args: &[&str]| stdout(run(&dir, &[&["vm", "--no-os", "-o", "-"], args].concat()));
+
+    // a single file is not bootstrapped unless asked for
+    assert!(vm(&["-p", "Prog/Main.vm"]).starts_with("(Main.main)"));
+    assert!(vm(&["-p", "Prog/Main.vm", "--bootstrap", "--entry", "Main.main"]).starts_with("@256"));
+
+    // a directory or several files are, calling Sys.init
+    let asm = vm(&["-p", "Prog"]);
+    assert!(asm.starts_with("@256\nD=A\n@SP\nM=D\n"));
+    assert!(asm.contains("@Sys.init\n0;JMP"));
+    assert!(vm(&["-p", "Prog/Sys.vm", "Prog/Main.vm"]).starts_with("@256"));
+    assert!(vm(&["-p", "Prog", "--sp", "300"]).starts_with("@300"));
+    assert!(!vm(&["-p", "Prog", "--no-bootstrap"]).starts_with("@256"));
+
+    // the entry function must be defined
+    let output = run(&dir, &[
+        "vm",
+        "--no-os",
+        "-o",
+        "-",
+        "-p",
+        "Prog/Main.vm",
+        "--bootstrap",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("error: undefined entry function: Sys.init"));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+const ADD: &str = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n";
+
+#[test]
+fn assemble_several_inputs() {
+    let dir = temp_dir("inputs");
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    for name in ["a/One.asm", "a/Two.asm", "a/b/Three.asm", "Four.asm"] {
+        fs::write(dir.join(name), ADD).unwrap();
+    }
+
+    // a directory, a glob and a file at once
+    stdout(run(&dir, &[
+        "asm",
+        "--out-dir",
+        "out",
+        "-p",
+        "a",
+        "a/b/*.asm",
+        "Four.asm",
+    ]));
+    let mut outputs: Vec<String> = fs::read_dir(dir.join("out"))
+        .unwrap()
+        .map(|v| v.unwrap().file_name().into_string().unwrap())
+        .collect();
+    outputs.sort();
+    assert_eq!(outputs, ["Four.vm", "One.vm", "Three.vm", "Two.vm"]);
+
+    // each one next to its input by default
+    stdout(run(&dir, &["asm", "-p", "a/b/Three.asm", "Four.asm"]));
+    assert!(dir.join("a/b/output/Three.vm").is_file());
+    assert!(dir.join("output/Four.vm").is_file());
+
+    // a single output file for several outputs
+    let output = run(&dir, &["asm", "-o", "All.hack", "-p", "a"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("invalid: --out with 2 outputs, use --out-dir"));
+    assert!(!dir.join("All.hack").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn pipe_through_stdio() {
+    let dir = temp_dir("stdio");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nand2tetris-rs"))
+        .current_dir(&dir)
+        .args(["asm", "-p", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(ADD.as_bytes())
+        .unwrap();
+    let hack = stdout(child.wait_with_output().unwrap());
+
+    assert_eq!(hack.lines().count(), 6);
+    assert_eq!(hack.lines().next(), Some("0000000000000010"));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    fs::remove_dir_all(dir).unwrap();
+}